@@ -56,8 +56,8 @@ impl Username {
         buffer.copy_from_slice(&user_data[0..8]);
         let mut len = u64::from_le_bytes(buffer) as usize;
         len = len.min(NETCODE_USER_DATA_BYTES - 8);
-        let data = user_data[8..len + 8].to_vec();
-        let username = String::from_utf8(data).unwrap();
+        // anyone can send user data, so a name that isn't valid utf-8 mustn't panic
+        let username = String::from_utf8_lossy(&user_data[8..len + 8]).into_owned();
         Self(username)
     }
 }
@@ -0,0 +1,376 @@
+use super::{
+    link_conditioner::LinkConditioner,
+    network_stats::NetworkStats,
+    networking::{ClientMessages, ServerMessages, UpdateKey},
+    transport::{ClientTransport, ServerTransport},
+};
+use bevy::{prelude::*, utils::HashMap};
+use renet::{DefaultChannel, NetworkInfo, ServerEvent};
+use std::{collections::VecDeque, error::Error, net::SocketAddr, time::Duration};
+
+// channels whose messages are batched, others are sent as they are
+const CHANNELS: [DefaultChannel; 2] = [DefaultChannel::Reliable, DefaultChannel::Unreliable];
+// bytes of messages packed into one frame, a bigger message gets a frame of its own
+const MAX_FRAME_SIZE: usize = 1024;
+
+fn batched(channel: DefaultChannel) -> bool {
+    CHANNELS
+        .iter()
+        .any(|&other| u8::from(other) == u8::from(channel))
+}
+
+fn unpack(frame: &[u8]) -> Vec<Vec<u8>> {
+    match bincode::deserialize(frame) {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Invalid frame: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Messages sent on one channel during a tick
+#[derive(Default)]
+struct Batch {
+    messages: Vec<Vec<u8>>,
+    // index of the update for each player and entity
+    updates: HashMap<UpdateKey, usize>,
+}
+
+impl Batch {
+    fn push(&mut self, message: Vec<u8>, key: Option<UpdateKey>) {
+        if let Some(key) = key {
+            // the newer update takes the slot of the one it replaces, so it isn't moved
+            // after messages that were sent after the first one
+            if let Some(&index) = self.updates.get(&key) {
+                self.messages[index] = message;
+                return;
+            }
+            self.updates.insert(key, self.messages.len());
+        }
+        self.messages.push(message);
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Packs the messages into as few frames as fit under `MAX_FRAME_SIZE`
+    fn take_frames(&mut self) -> Vec<Vec<u8>> {
+        self.updates.clear();
+        let mut frames = Vec::new();
+        let mut frame: Vec<Vec<u8>> = Vec::new();
+        let mut size = 0;
+        for message in self.messages.drain(..) {
+            if !frame.is_empty() && size + message.len() > MAX_FRAME_SIZE {
+                frames.push(bincode::serialize(&frame).unwrap());
+                frame.clear();
+                size = 0;
+            }
+            size += message.len();
+            frame.push(message);
+        }
+        if !frame.is_empty() {
+            frames.push(bincode::serialize(&frame).unwrap());
+        }
+        frames
+    }
+}
+
+#[derive(Default)]
+struct Batches(HashMap<u8, Batch>);
+
+impl Batches {
+    fn push(&mut self, channel: DefaultChannel, message: Vec<u8>, key: Option<UpdateKey>) {
+        self.0.entry(channel.into()).or_default().push(message, key);
+    }
+
+    fn take_frames(&mut self) -> Vec<(DefaultChannel, Vec<u8>)> {
+        let mut frames = Vec::new();
+        for channel in CHANNELS {
+            if let Some(batch) = self.0.get_mut(&channel.into()) {
+                frames.extend(
+                    batch
+                        .take_frames()
+                        .into_iter()
+                        .map(|frame| (channel, frame)),
+                );
+            }
+        }
+        frames
+    }
+
+    fn len(&self) -> usize {
+        self.0.values().map(|batch| batch.len()).sum()
+    }
+}
+
+#[derive(Default)]
+struct Unpacked(HashMap<u8, VecDeque<Vec<u8>>>);
+
+impl Unpacked {
+    fn push(&mut self, channel: DefaultChannel, frame: &[u8]) {
+        self.0
+            .entry(channel.into())
+            .or_default()
+            .extend(unpack(frame));
+    }
+
+    fn pop(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.0.get_mut(&channel.into())?.pop_front()
+    }
+}
+
+/// Sends everything a client sends in a tick as one frame per channel, with only the
+/// latest update for each player and entity
+pub struct BatchedClientTransport {
+    inner: Box<dyn ClientTransport>,
+    batches: Batches,
+    received: Unpacked,
+}
+
+impl BatchedClientTransport {
+    pub fn new(inner: Box<dyn ClientTransport>) -> Self {
+        Self {
+            inner,
+            batches: Batches::default(),
+            received: Unpacked::default(),
+        }
+    }
+}
+
+impl ClientTransport for BatchedClientTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.update(duration)
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        for (channel, frame) in self.batches.take_frames() {
+            self.inner.send_message(channel, frame);
+        }
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        if !batched(channel) {
+            self.inner.send_message(channel, message);
+            return;
+        }
+        self.batches.push(channel, message, None);
+    }
+
+    fn send(&mut self, channel: DefaultChannel, message: &ClientMessages) {
+        let key = message.update_key();
+        let message = bincode::serialize(message).unwrap();
+        if !batched(channel) {
+            self.inner.send_message(channel, message);
+            return;
+        }
+        self.batches.push(channel, message, key);
+    }
+
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        if !batched(channel) {
+            return self.inner.receive_message(channel);
+        }
+        loop {
+            if let Some(message) = self.received.pop(channel) {
+                return Some(message);
+            }
+            let frame = self.inner.receive_message(channel)?;
+            self.received.push(channel, &frame);
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn disconnect(&mut self) {
+        self.inner.disconnect();
+    }
+
+    fn network_info(&self) -> NetworkInfo {
+        self.inner.network_info()
+    }
+
+    fn queued_messages(&self) -> usize {
+        self.batches.len() + self.inner.queued_messages()
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        self.inner.conditioner()
+    }
+
+    fn stats(&self) -> Option<&NetworkStats> {
+        self.inner.stats()
+    }
+
+    fn reconnect(&mut self, transport: Box<dyn ClientTransport>) {
+        self.batches = Batches::default();
+        self.received = Unpacked::default();
+        self.inner.reconnect(transport);
+    }
+}
+
+/// Sends everything the server sends a client in a tick as one frame per channel, with
+/// only the latest update for each player and entity
+pub struct BatchedServerTransport {
+    inner: Box<dyn ServerTransport>,
+    batches: HashMap<u64, Batches>,
+    received: HashMap<u64, Unpacked>,
+}
+
+impl BatchedServerTransport {
+    pub fn new(inner: Box<dyn ServerTransport>) -> Self {
+        Self {
+            inner,
+            batches: HashMap::default(),
+            received: HashMap::default(),
+        }
+    }
+
+    fn push(
+        &mut self,
+        client_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+        key: Option<UpdateKey>,
+    ) {
+        if !batched(channel) {
+            self.inner.send_message(client_id, channel, message);
+            return;
+        }
+        self.batches
+            .entry(client_id)
+            .or_default()
+            .push(channel, message, key);
+    }
+}
+
+impl ServerTransport for BatchedServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.update(duration)?;
+
+        let clients = self.inner.clients_id();
+        self.batches
+            .retain(|client_id, _| clients.contains(client_id));
+        self.received
+            .retain(|client_id, _| clients.contains(client_id));
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.inner.get_event()
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        for (&client_id, batches) in self.batches.iter_mut() {
+            for (channel, frame) in batches.take_frames() {
+                self.inner.send_message(client_id, channel, frame);
+            }
+        }
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        self.push(client_id, channel, message, None);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        for client_id in self.inner.clients_id() {
+            self.push(client_id, channel, message.clone(), None);
+        }
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        for client_id in self.inner.clients_id() {
+            if client_id != except_id {
+                self.push(client_id, channel, message.clone(), None);
+            }
+        }
+    }
+
+    fn send(&mut self, client_id: u64, channel: DefaultChannel, message: &ServerMessages) {
+        let key = message.update_key();
+        self.push(
+            client_id,
+            channel,
+            bincode::serialize(message).unwrap(),
+            key,
+        );
+    }
+
+    fn broadcast(&mut self, channel: DefaultChannel, message: &ServerMessages) {
+        let key = message.update_key();
+        let message = bincode::serialize(message).unwrap();
+        for client_id in self.inner.clients_id() {
+            self.push(client_id, channel, message.clone(), key);
+        }
+    }
+
+    fn broadcast_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: &ServerMessages,
+    ) {
+        let key = message.update_key();
+        let message = bincode::serialize(message).unwrap();
+        for client_id in self.inner.clients_id() {
+            if client_id != except_id {
+                self.push(client_id, channel, message.clone(), key);
+            }
+        }
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        if !batched(channel) {
+            return self.inner.receive_message(client_id, channel);
+        }
+        let received = self.received.entry(client_id).or_default();
+        loop {
+            if let Some(message) = received.pop(channel) {
+                return Some(message);
+            }
+            let frame = self.inner.receive_message(client_id, channel)?;
+            received.push(channel, &frame);
+        }
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        self.inner.clients_id()
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        self.inner.disconnect(client_id);
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        self.inner.network_info(client_id)
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        self.inner.client_addr(client_id)
+    }
+
+    fn queued_messages(&self, client_id: u64) -> usize {
+        self.batches
+            .get(&client_id)
+            .map_or(0, |batches| batches.len())
+            + self.inner.queued_messages(client_id)
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        self.inner.conditioner()
+    }
+
+    fn stats(&self, client_id: u64) -> Option<&NetworkStats> {
+        self.inner.stats(client_id)
+    }
+}
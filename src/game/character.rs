@@ -1,9 +1,11 @@
+use super::{chat::ChatState, health::Health};
 use crate::GameState;
 use bevy::{input::mouse::MouseMotion, prelude::*, window::CursorGrabMode};
 use bevy_voxel_engine::Velocity;
 
 const SPEED: f32 = 10.0;
 const SENSITIVITY: f32 = 0.004;
+const MAX_FALL_SPEED: f32 = 50.0;
 
 #[derive(Component)]
 pub struct CharacterEntity {
@@ -37,15 +39,31 @@ fn toggle_grab_cursor(window: &mut Window) {
     window.set_cursor_visibility(!window.cursor_visible());
 }
 
-fn update_character(
-    mut character: Query<(&mut Transform, &mut Velocity, &mut CharacterEntity)>,
+/// Grabs or releases the mouse cursor
+pub fn set_cursor_grab(window: &mut Window, grab: bool) {
+    window.set_cursor_grab_mode(match grab {
+        true => CursorGrabMode::Locked,
+        false => CursorGrabMode::None,
+    });
+    window.set_cursor_visibility(!grab);
+}
+
+pub fn update_character(
+    mut character: Query<(
+        &mut Transform,
+        &mut Velocity,
+        &mut CharacterEntity,
+        Option<&Health>,
+    )>,
     keys: Res<Input<KeyCode>>,
+    chat: Res<ChatState>,
     mut mouse_motion_events: EventReader<MouseMotion>,
     time: Res<Time>,
     mut windows: ResMut<Windows>,
 ) {
     let window = windows.get_primary_mut().unwrap();
-    if keys.just_pressed(KeyCode::Escape) {
+    // escape closes the chat instead while it is open
+    if keys.just_pressed(KeyCode::Escape) && !chat.open {
         toggle_grab_cursor(window);
     }
 
@@ -53,9 +71,10 @@ fn update_character(
         return;
     }
 
-    let (mut transform, mut velocity, mut character) = character.single_mut();
+    let (mut transform, mut velocity, mut character, health) = character.single_mut();
+    let alive = !health.map(|health| health.is_dead()).unwrap_or(false);
     let target_velocity;
-    if window.cursor_grab_mode() == CursorGrabMode::Locked {
+    if window.cursor_grab_mode() == CursorGrabMode::Locked && alive {
         character.look_at = velocity.portal_rotation * character.look_at;
         character.up = velocity.portal_rotation * character.up;
 
@@ -106,6 +125,7 @@ fn update_character(
             character.grounded = false;
         }
         velocity.velocity += Vec3::new(0.0, -9.81 * time.delta_seconds(), 0.0);
+        velocity.velocity.y = velocity.velocity.y.max(-MAX_FALL_SPEED);
 
         let plane_forward = transform.local_x().cross(Vec3::Y).normalize();
         target_velocity =
@@ -0,0 +1,217 @@
+use super::{
+    character::{set_cursor_grab, update_character},
+    client::ClientResource,
+    networking::ClientMessages,
+};
+use crate::GameState;
+use bevy::{prelude::*, utils::HashSet};
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use renet::DefaultChannel;
+
+const MAX_CHAT_HISTORY: usize = 100;
+const CLOSED_VISIBLE_MESSAGES: usize = 8;
+// seconds the chat stays fully visible after the last activity before fading out
+const FADE_DELAY: f32 = 6.0;
+const FADE_TIME: f32 = 2.0;
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChatState::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::Game).with_system(chat_ui.after(update_character)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(clear_chat));
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ChatState {
+    pub open: bool,
+    input: String,
+    messages: Vec<ChatEntry>,
+    last_activity: f32,
+    // players whose messages are hidden locally
+    pub ignored: HashSet<u64>,
+}
+
+struct ChatEntry {
+    // none for messages from the server itself
+    username: Option<String>,
+    message: String,
+    time: f32,
+}
+
+impl ChatState {
+    pub fn push(&mut self, username: Option<String>, message: String, time: f32) {
+        self.messages.push(ChatEntry {
+            username,
+            message,
+            time,
+        });
+        if self.messages.len() > MAX_CHAT_HISTORY {
+            self.messages.remove(0);
+        }
+        self.last_activity = time;
+    }
+
+    /// Sender and text of each line, oldest first
+    pub fn history(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        self.messages
+            .iter()
+            .map(|entry| (entry.username.as_deref(), entry.message.as_str()))
+    }
+}
+
+fn clear_chat(mut chat: ResMut<ChatState>) {
+    *chat = ChatState::default();
+}
+
+fn chat_ui(
+    mut egui_context: ResMut<EguiContext>,
+    mut chat: ResMut<ChatState>,
+    mut client_resource: ResMut<ClientResource>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut windows: ResMut<Windows>,
+) {
+    let chat = &mut *chat;
+    let window = windows.get_primary_mut().unwrap();
+    let now = time.elapsed_seconds();
+
+    if !chat.open && (keys.just_pressed(KeyCode::Return) || keys.just_pressed(KeyCode::T)) {
+        chat.open = true;
+        chat.last_activity = now;
+        set_cursor_grab(window, false);
+    } else if chat.open && keys.just_pressed(KeyCode::Escape) {
+        chat.open = false;
+        chat.input.clear();
+        chat.last_activity = now;
+        set_cursor_grab(window, true);
+    }
+
+    let opacity = if chat.open {
+        1.0
+    } else {
+        1.0 - ((now - chat.last_activity - FADE_DELAY) / FADE_TIME).clamp(0.0, 1.0)
+    };
+    if opacity <= 0.0 {
+        return;
+    }
+    let alpha = |a: u8| (a as f32 * opacity) as u8;
+
+    let mut send = None;
+    let mut close = false;
+    egui::Window::new("Chat")
+        .anchor(egui::Align2::LEFT_BOTTOM, [5.0, -5.0])
+        .title_bar(false)
+        .resizable(false)
+        .frame(
+            egui::Frame::none().fill(Color32::from_black_alpha(alpha(if chat.open {
+                160
+            } else {
+                80
+            }))),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.set_width(400.0);
+
+            let skip = if chat.open {
+                0
+            } else {
+                chat.messages.len().saturating_sub(CLOSED_VISIBLE_MESSAGES)
+            };
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for entry in chat.messages.iter().skip(skip) {
+                        let seconds = entry.time as u32;
+                        let timestamp = format!("[{:02}:{:02}]", seconds / 60, seconds % 60);
+                        ui.horizontal_wrapped(|ui| {
+                            ui.colored_label(
+                                Color32::from_rgba_unmultiplied(150, 150, 150, alpha(255)),
+                                timestamp,
+                            );
+                            match &entry.username {
+                                Some(username) => {
+                                    ui.colored_label(
+                                        Color32::from_rgba_unmultiplied(255, 200, 80, alpha(255)),
+                                        format!("{}:", username),
+                                    );
+                                    ui.colored_label(
+                                        Color32::from_rgba_unmultiplied(255, 255, 255, alpha(255)),
+                                        &entry.message,
+                                    );
+                                }
+                                None => {
+                                    ui.colored_label(
+                                        Color32::from_rgba_unmultiplied(120, 200, 255, alpha(255)),
+                                        &entry.message,
+                                    );
+                                }
+                            }
+                        });
+                    }
+                });
+
+            if chat.open {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut chat.input)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Say something..."),
+                );
+                if response.lost_focus() {
+                    if ui.input().key_pressed(egui::Key::Enter) {
+                        send = Some(chat.input.trim().to_string());
+                    }
+                    close = true;
+                } else {
+                    response.request_focus();
+                }
+            }
+        });
+
+    if close {
+        chat.open = false;
+        chat.input.clear();
+        chat.last_activity = now;
+        set_cursor_grab(window, true);
+    }
+
+    if let Some(message) = send.filter(|message| !message.is_empty()) {
+        if let Some(client) = (*client_resource).as_mut() {
+            // ignoring is handled locally, the server never sees it
+            let (command, name) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+            if command == "/ignore" {
+                let name = name.trim();
+                let reply = match client
+                    .players
+                    .iter()
+                    .find(|(_, player)| player.username == name)
+                {
+                    Some((client_id, _)) => {
+                        if chat.ignored.remove(client_id) {
+                            format!("No longer ignoring {}", name)
+                        } else {
+                            chat.ignored.insert(*client_id);
+                            format!("Ignoring {}", name)
+                        }
+                    }
+                    None => format!("No player called {}", name),
+                };
+                chat.push(None, reply, now);
+                return;
+            }
+
+            client.client.send(
+                DefaultChannel::Reliable,
+                &ClientMessages::ChatMessage { message },
+            );
+        }
+    }
+}
@@ -1,6 +1,23 @@
 use super::{
+    batching::BatchedClientTransport,
     character::CharacterEntity,
-    networking::{ClientMessages, NetworkTransform, NetworkedEntityType, ServerMessages},
+    chat::ChatState,
+    events::GameEvent,
+    game_mode::GameModeState,
+    health::{Health, PLAYER_HALF_SIZE},
+    interpolation::{interpolate_remote_entities, Interpolated},
+    link_conditioner::{ConditionedClientTransport, LinkConditioner},
+    network_stats::StatsClientTransport,
+    networking::{
+        ClientMessages, NetworkTransform, NetworkedEntityType, PortalId, ServerMessages, SERVER_ID,
+    },
+    portals::{hidden_portal_transform, spawn_portal},
+    props::{send_owned_props, NetworkedProp, OwnedProp},
+    scoreboard::PlayerStats,
+    session::{self, reconnect, Reconnecting},
+    teams::{player_material, portal_material, PortalOwner},
+    transport::{renet_client, ClientTransport, MemoryListener},
+    traversal::{detect_portal_traversals, portal_position},
 };
 use crate::{game::InGame, GameState};
 use bevy::{
@@ -9,11 +26,8 @@ use bevy::{
 };
 use bevy_voxel_engine::*;
 use rand::Rng;
-use renet::{ClientAuthentication, DefaultChannel, RenetClient, RenetConnectionConfig};
-use std::{
-    net::{ToSocketAddrs, UdpSocket},
-    time::SystemTime,
-};
+use renet::{DefaultChannel, NETCODE_USER_DATA_BYTES};
+use std::collections::VecDeque;
 
 pub struct ClientPlugin;
 
@@ -22,19 +36,25 @@ impl Plugin for ClientPlugin {
         app.insert_resource(ClientResource(None))
             .add_system_set_to_stage(
                 CoreStage::PreUpdate,
-                SystemSet::on_update(GameState::Game).with_system(update),
+                SystemSet::on_update(GameState::Game)
+                    .with_system(update)
+                    .with_system(reconnect.after(update)),
             )
             .add_system_set_to_stage(
                 CoreStage::PostUpdate,
                 SystemSet::on_update(GameState::Game).with_system(send_packets),
             )
             .add_system_set(
-                SystemSet::on_update(GameState::Game).with_system(process_server_messages),
+                SystemSet::on_update(GameState::Game)
+                    .with_system(process_server_messages)
+                    .with_system(interpolate_remote_entities.after(process_server_messages)),
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Game)
-                    .with_system(update_player)
-                    .with_system(update_networked_entitys),
+                    .with_system(detect_portal_traversals)
+                    .with_system(update_player.after(detect_portal_traversals))
+                    .with_system(update_networked_entitys.after(detect_portal_traversals))
+                    .with_system(send_owned_props),
             )
             .add_system_set(SystemSet::on_exit(GameState::Game).with_system(disconnect));
     }
@@ -65,13 +85,33 @@ fn disconnect(mut client_resource: ResMut<ClientResource>) {
 pub struct ClientResource(pub Option<Client>);
 
 pub struct Client {
-    pub client: RenetClient,
+    pub client: Box<dyn ClientTransport>,
+    pub client_id: u64,
+    pub username: String,
     pub players: HashMap<u64, ClientPlayerData>,
     // maps remote entity id to local entity for each player
     pub networked_entitys: HashMap<u64, HashMap<Entity, Entity>>,
     pub local_networked_entitys: HashSet<Entity>,
+    // latest round state and the local time it arrived
+    pub game_mode: Option<(GameModeState, f32)>,
+    pub stats: HashMap<u64, PlayerStats>,
+    // portals placed by other players
+    pub portals: HashMap<PortalId, Entity>,
+    // voxels the server changed since the map was loaded and what they are now
+    pub edits: HashMap<IVec3, u8>,
+    // token to reconnect with and the seconds the server waits for it
+    pub session: Option<(u64, f32)>,
+    pub reconnecting: Option<Reconnecting>,
+    pub(crate) connected: bool,
+    // latest respawn of our player, sent with our updates
+    pub respawn: u32,
+    // opens a new connection to the same server
+    connect: Option<Connect>,
 }
 
+type Connect =
+    Box<dyn Fn(u64, [u8; NETCODE_USER_DATA_BYTES]) -> Box<dyn ClientTransport> + Send + Sync>;
+
 pub struct ClientPlayerData {
     pub username: String,
     pub entity: Entity,
@@ -91,30 +131,71 @@ pub struct RemoteNetworkedEntity {
 }
 
 impl Client {
-    pub fn new(ip: String, _: String) -> Self {
-        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let server_addr = ip.to_socket_addrs().unwrap().next().unwrap();
-        let connection_config = RenetConnectionConfig::default();
-
-        let current_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-        let mut rng = rand::thread_rng();
-        let authentication = ClientAuthentication::Unsecure {
-            protocol_id: 0,
-            client_id: rng.gen::<u64>(),
-            server_addr,
-            user_data: None,
-        };
+    pub fn new(ip: String, username: String) -> Result<Self, String> {
+        let connect: Connect = Box::new(move |client_id, user_data| -> Box<dyn ClientTransport> {
+            Box::new(renet_client(&ip, client_id, user_data))
+        });
+        Self::reconnectable(connect, username)
+    }
 
-        info!("Client connected to {}", server_addr);
+    /// Connects to a server in the same process without going through a socket
+    pub fn local(listener: &MemoryListener, username: String) -> Result<Self, String> {
+        let listener = listener.clone();
+        let connect: Connect = Box::new(move |client_id, user_data| -> Box<dyn ClientTransport> {
+            Box::new(listener.connect(client_id, user_data))
+        });
+        Self::reconnectable(connect, username)
+    }
+
+    fn reconnectable(connect: Connect, username: String) -> Result<Self, String> {
+        let client_id = rand::thread_rng().gen::<u64>();
+        let transport = connect(client_id, session::user_data(&username, None)?);
+        Ok(Self {
+            connect: Some(connect),
+            ..Self::with_transport(transport, client_id, username)
+        })
+    }
+
+    pub fn can_reconnect(&self) -> bool {
+        self.session.is_some() && self.connect.is_some()
+    }
+
+    /// Opens a new connection under the same id, asking the server to resume our session
+    pub fn reconnect(&mut self, token: u64) {
+        // the username was already accepted when we first connected
+        let user_data = session::user_data(&self.username, Some(token)).unwrap();
+        let transport = (self.connect.as_ref().unwrap())(self.client_id, user_data);
+        self.client.reconnect(transport);
+    }
 
+    pub fn with_transport(
+        client: Box<dyn ClientTransport>,
+        client_id: u64,
+        username: String,
+    ) -> Self {
+        // conditions are perfect until changed from the command line or the debug panel
+        let conditioner = LinkConditioner::new(default(), rand::thread_rng().gen());
         Self {
-            client: RenetClient::new(current_time, socket, connection_config, authentication)
-                .unwrap(),
+            client: Box::new(StatsClientTransport::new(Box::new(
+                BatchedClientTransport::new(Box::new(ConditionedClientTransport::new(
+                    client,
+                    conditioner,
+                ))),
+            ))),
+            client_id,
+            username,
             players: HashMap::default(),
             networked_entitys: HashMap::default(),
             local_networked_entitys: HashSet::default(),
+            game_mode: None,
+            stats: HashMap::default(),
+            portals: HashMap::default(),
+            edits: HashMap::default(),
+            session: None,
+            reconnecting: None,
+            connected: false,
+            respawn: 0,
+            connect: None,
         }
     }
 }
@@ -122,55 +203,153 @@ impl Client {
 fn process_server_messages(
     mut commands: Commands,
     mut client_resource: ResMut<ClientResource>,
-    mut network_players: Query<&mut Transform, With<RemotePlayer>>,
+    mut network_players: Query<
+        (&mut Interpolated, &mut Health),
+        (With<RemotePlayer>, Without<CharacterEntity>),
+    >,
     mut networked_entitys: Query<
-        (&mut RemoteNetworkedEntity, &mut Transform),
+        (
+            &mut RemoteNetworkedEntity,
+            &mut Transform,
+            Option<&mut Interpolated>,
+        ),
+        (Without<RemotePlayer>, Without<CharacterEntity>),
+    >,
+    mut character: Query<
+        (&mut Transform, &mut Velocity, &mut Health, &CharacterEntity),
         Without<RemotePlayer>,
     >,
+    portals: Query<(&PortalOwner, &GlobalTransform)>,
+    mut portal_transforms: Query<
+        &mut Transform,
+        (
+            With<PortalOwner>,
+            Without<RemoteNetworkedEntity>,
+            Without<RemotePlayer>,
+            Without<CharacterEntity>,
+        ),
+    >,
     asset_server: Res<AssetServer>,
+    mut chat: ResMut<ChatState>,
+    time: Res<Time>,
+    mut load_voxel_world: ResMut<LoadVoxelWorld>,
+    mut game_events: EventWriter<GameEvent>,
 ) {
     if let Some(client) = (*client_resource).as_mut() {
-        while let Some(message) = client.client.receive_message(DefaultChannel::Reliable) {
-            let message: ServerMessages = bincode::deserialize(&message).unwrap();
+        // messages a snapshot was unpacked into, handled before anything received after it
+        let mut unpacked: VecDeque<ServerMessages> = VecDeque::new();
+        loop {
+            let message = match unpacked.pop_front() {
+                Some(message) => message,
+                None => match client.client.receive(DefaultChannel::Reliable) {
+                    Some(message) => message,
+                    None => break,
+                },
+            };
             match message {
                 ServerMessages::ClientConnected {
                     client_id,
                     username,
                 } => {
-                    let entity = commands
-                        .spawn((
-                            Transform::default(),
-                            bevy_voxel_engine::Box {
-                                half_size: IVec3::new(2, 4, 2),
-                                material: 10,
-                            },
-                            RemotePlayer,
-                            InGame,
-                        ))
-                        .id();
-
-                    client.players.insert(
-                        client_id,
-                        ClientPlayerData {
-                            username: username.clone(),
-                            entity,
-                        },
-                    );
                     info!("Player {} ({}) connected.", username, client_id);
+                    spawn_remote_player(&mut commands, client, client_id, username, None);
+                }
+                ServerMessages::WorldSnapshot(snapshot) => {
+                    let now = time.elapsed_seconds();
+                    for player in snapshot.players.iter() {
+                        let health = Health {
+                            current: player.health,
+                            protected_until: now + player.protection,
+                            ..default()
+                        };
+                        spawn_remote_player(
+                            &mut commands,
+                            client,
+                            player.client_id,
+                            player.username.clone(),
+                            Some((now, player.position, health)),
+                        );
+                    }
+                    for line in snapshot.chat.iter() {
+                        match &line.sender {
+                            Some((client_id, _)) if chat.ignored.contains(client_id) => {}
+                            sender => chat.push(
+                                sender.as_ref().map(|(_, username)| username.clone()),
+                                line.message.clone(),
+                                now,
+                            ),
+                        }
+                    }
+                    unpacked.extend(snapshot.into_messages());
+                }
+                ServerMessages::Session { token, grace } => {
+                    if client.reconnecting.take().is_some() {
+                        info!("Reconnected to the server.");
+                        // our entities are spawned again on the new connection
+                        client.local_networked_entitys.clear();
+                    }
+                    client.session = Some((token, grace));
+                }
+                ServerMessages::Disconnected { reason } => {
+                    warn!("Disconnected by the server: {}", reason);
+                    client.session = None;
+                    client.reconnecting = None;
+                    client.client.disconnect();
+                    break;
                 }
                 ServerMessages::ClientDisconnected { client_id } => {
-                    let client_player_data = client.players.remove(&client_id).unwrap();
+                    let client_player_data = match client.players.remove(&client_id) {
+                        Some(client_player_data) => client_player_data,
+                        None => {
+                            warn!("Disconnect of unknown player {}", client_id);
+                            continue;
+                        }
+                    };
                     commands
                         .entity(client_player_data.entity)
                         .despawn_recursive();
+                    for index in 0..2 {
+                        let id = PortalId {
+                            owner: client_id,
+                            index,
+                        };
+                        if let Some(portal) = client.portals.remove(&id) {
+                            commands.entity(portal).despawn_recursive();
+                        }
+                    }
                     info!(
                         "Player {} ({}) disconnected.",
                         client_player_data.username, client_id
                     );
                 }
                 ServerMessages::ChatMessage { client_id, message } => {
-                    let username = &client.players.get(&client_id).unwrap().username;
+                    if chat.ignored.contains(&client_id) {
+                        continue;
+                    }
+
+                    let username = if client_id == client.client_id {
+                        client.username.clone()
+                    } else if let Some(player) = client.players.get(&client_id) {
+                        player.username.clone()
+                    } else {
+                        client_id.to_string()
+                    };
                     info!("{}: {}", username, message);
+                    chat.push(Some(username), message, time.elapsed_seconds());
+                }
+                ServerMessages::SystemMessage { message } => {
+                    info!("{}", message);
+                    chat.push(None, message, time.elapsed_seconds());
+                }
+                ServerMessages::ChangeMap { map } => {
+                    info!("Loading map {}", map);
+                    *load_voxel_world = LoadVoxelWorld::File(format!("assets/{}.vox", map));
+                    client.edits.clear();
+                }
+                ServerMessages::VoxelsEdited { edits } => {
+                    for edit in edits {
+                        client.edits.insert(edit.voxel, edit.material);
+                    }
                 }
                 ServerMessages::UpdatePlayer {
                     client_id,
@@ -178,8 +357,8 @@ fn process_server_messages(
                     ..
                 } => {
                     if let Some(player) = client.players.get_mut(&client_id) {
-                        if let Ok(mut transform) = network_players.get_mut(player.entity) {
-                            transform.translation = position;
+                        if let Ok((mut interpolated, _)) = network_players.get_mut(player.entity) {
+                            interpolated.push(time.elapsed_seconds(), position);
                         }
                     }
                 }
@@ -191,8 +370,7 @@ fn process_server_messages(
                 } => match entity_type {
                     NetworkedEntityType::Bullet(bullet_type) => {
                         let material = match bullet_type {
-                            1 => 120,
-                            2 => 121,
+                            1 | 2 => portal_material(client.team(client_id), bullet_type - 1),
                             _ => 10,
                         };
 
@@ -203,6 +381,7 @@ fn process_server_messages(
                                 RemoteNetworkedEntity {
                                     velocity: transform.velocity,
                                 },
+                                Interpolated::new(time.elapsed_seconds(), transform.position),
                                 InGame,
                             ))
                             .id();
@@ -213,41 +392,25 @@ fn process_server_messages(
                             .or_default()
                             .insert(entity, local_entity);
                     }
-                    NetworkedEntityType::Portal(portal_type) => {
-                        let material = match portal_type {
-                            0 => 120,
-                            1 => 121,
-                            _ => 10,
-                        };
-
+                    NetworkedEntityType::Prop(shape) => {
+                        // simulated by the server until we hear otherwise
                         let local_entity = commands
                             .spawn((
-                                VoxelizationBundle {
-                                    mesh_handle: asset_server.load("models/portal.obj"),
-                                    transform: Transform::from(&transform),
-                                    voxelization_material: VoxelizationMaterial {
-                                        flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
-                                        ..default()
-                                    },
-                                    ..default()
+                                Transform::from(&transform),
+                                bevy_voxel_engine::Box {
+                                    material: shape.material(),
+                                    half_size: shape.half_size(),
+                                },
+                                BoxCollider {
+                                    half_size: shape.half_size(),
                                 },
-                                Portal,
                                 RemoteNetworkedEntity {
                                     velocity: transform.velocity,
                                 },
+                                Interpolated::new(time.elapsed_seconds(), transform.position),
+                                NetworkedProp { entity },
                                 InGame,
                             ))
-                            .with_children(|parent| {
-                                // portal border
-                                parent.spawn(VoxelizationBundle {
-                                    mesh_handle: asset_server.load("models/portal_frame.obj"),
-                                    voxelization_material: VoxelizationMaterial {
-                                        material: VoxelizationMaterialType::Material(material),
-                                        flags: Flags::ANIMATION_FLAG | Flags::COLLISION_FLAG,
-                                    },
-                                    ..default()
-                                });
-                            })
                             .id();
 
                         client
@@ -262,27 +425,319 @@ fn process_server_messages(
                     entity,
                     transform,
                 } => {
-                    let local_entity = client.networked_entitys[&client_id][&entity];
+                    let local_entity = match client
+                        .networked_entitys
+                        .get(&client_id)
+                        .and_then(|entities| entities.get(&entity))
+                    {
+                        Some(&local_entity) => local_entity,
+                        None => {
+                            warn!("Update for unknown entity {:?} of {}", entity, client_id);
+                            continue;
+                        }
+                    };
                     if let Ok(query) = networked_entitys.get_mut(local_entity) {
-                        let (mut remote_networked_entity, mut local_transform) = query;
+                        let (mut remote_networked_entity, mut local_transform, interpolated) =
+                            query;
                         remote_networked_entity.velocity = transform.velocity;
-                        *local_transform = Transform::from(&transform);
+                        match interpolated {
+                            Some(mut interpolated) => {
+                                interpolated.push(time.elapsed_seconds(), transform.position);
+                                local_transform.rotation = transform.rotation;
+                                local_transform.scale = transform.scale;
+                            }
+                            None => *local_transform = Transform::from(&transform),
+                        }
+                    }
+                }
+                ServerMessages::RemoveLocalEntity { entity } => {
+                    // the despawn is sent to the server by update_networked_entitys
+                    if let Some(mut entity_commands) = commands.get_entity(entity) {
+                        entity_commands.despawn_recursive();
+                    }
+                }
+                ServerMessages::PlayerDamaged {
+                    client_id, health, ..
+                } => {
+                    if client_id == client.client_id {
+                        if let Ok((_, _, mut local_health, _)) = character.get_single_mut() {
+                            local_health.current = health;
+                        }
+                    } else if let Some(player) = client.players.get(&client_id) {
+                        if let Ok((_, mut remote_health)) = network_players.get_mut(player.entity) {
+                            remote_health.current = health;
+                        }
+                    }
+                }
+                ServerMessages::PlayerKilled {
+                    client_id,
+                    attacker_id,
+                } => {
+                    if client_id != client.client_id {
+                        if let Some(player) = client.players.get(&client_id) {
+                            commands
+                                .entity(player.entity)
+                                .remove::<bevy_voxel_engine::Box>();
+                        }
+                    }
+                    info!("Player {} was killed by {}.", client_id, attacker_id);
+                }
+                ServerMessages::PlayerRespawned {
+                    client_id,
+                    position,
+                    health,
+                    protection,
+                    respawn,
+                } => {
+                    let protected_until = time.elapsed_seconds() + protection;
+                    if client_id == client.client_id {
+                        client.respawn = respawn;
+                        if let Ok((mut transform, mut velocity, mut local_health, _)) =
+                            character.get_single_mut()
+                        {
+                            transform.translation = position;
+                            velocity.velocity = Vec3::ZERO;
+                            local_health.current = health;
+                            local_health.protected_until = protected_until;
+                        }
+                    } else if let Some(player) = client.players.get(&client_id) {
+                        if let Ok((mut interpolated, mut remote_health)) =
+                            network_players.get_mut(player.entity)
+                        {
+                            interpolated.teleport(time.elapsed_seconds(), position);
+                            remote_health.current = health;
+                            remote_health.protected_until = protected_until;
+                        }
+                        commands
+                            .entity(player.entity)
+                            .insert(bevy_voxel_engine::Box {
+                                half_size: PLAYER_HALF_SIZE,
+                                material: player_material(client.team(client_id)),
+                            });
+                    }
+                }
+                ServerMessages::GameModeState(state) => {
+                    client.game_mode = Some((state, time.elapsed_seconds()));
+                }
+                ServerMessages::GameEvent(event) => {
+                    game_events.send(event);
+                }
+                ServerMessages::PlayerStats { stats } => {
+                    client.stats = stats
+                        .into_iter()
+                        .map(|stats| (stats.client_id, stats))
+                        .collect();
+                }
+                ServerMessages::PortalTraversal {
+                    client_id,
+                    entity,
+                    from,
+                    to,
+                } => {
+                    let source = portal_position(&portals, client.client_id, from);
+                    let destination = portal_position(&portals, client.client_id, to);
+                    let (source, destination) = match (source, destination) {
+                        (Some(source), Some(destination)) => (source, destination),
+                        _ => continue,
+                    };
+
+                    let now = time.elapsed_seconds();
+                    match entity {
+                        None => {
+                            if let Some(player) = client.players.get(&client_id) {
+                                if let Ok((mut interpolated, _)) =
+                                    network_players.get_mut(player.entity)
+                                {
+                                    interpolated.traverse_portal(now, source, destination);
+                                }
+                            }
+                        }
+                        Some(entity) => {
+                            let local_entity = client
+                                .networked_entitys
+                                .get(&client_id)
+                                .and_then(|entities| entities.get(&entity));
+                            if let Some(&local_entity) = local_entity {
+                                if let Ok((_, _, Some(mut interpolated))) =
+                                    networked_entitys.get_mut(local_entity)
+                                {
+                                    interpolated.traverse_portal(now, source, destination);
+                                }
+                            }
+                        }
+                    }
+                }
+                ServerMessages::PortalPlaced {
+                    client_id,
+                    index,
+                    placement,
+                } => {
+                    let transform = placement.transform(index);
+                    if client_id == client.client_id {
+                        if let Ok((_, _, _, character)) = character.get_single() {
+                            let portal = match index {
+                                0 => character.portal1,
+                                _ => character.portal2,
+                            };
+                            if let Ok(mut portal_transform) = portal_transforms.get_mut(portal) {
+                                *portal_transform = transform;
+                            }
+                        }
+                        continue;
+                    }
+
+                    let id = PortalId {
+                        owner: client_id,
+                        index,
+                    };
+                    match client.portals.get(&id) {
+                        Some(&portal) => {
+                            if let Ok(mut portal_transform) = portal_transforms.get_mut(portal) {
+                                *portal_transform = transform;
+                            }
+                        }
+                        None => {
+                            let portal = spawn_portal(
+                                &mut commands,
+                                &asset_server,
+                                Some(client_id),
+                                index,
+                                transform,
+                            );
+                            client.portals.insert(id, portal);
+                        }
+                    }
+                }
+                ServerMessages::PortalRemoved { client_id, index } => {
+                    if client_id == client.client_id {
+                        if let Ok((_, _, _, character)) = character.get_single() {
+                            let portal = match index {
+                                0 => character.portal1,
+                                _ => character.portal2,
+                            };
+                            if let Ok(mut portal_transform) = portal_transforms.get_mut(portal) {
+                                *portal_transform = hidden_portal_transform(index);
+                            }
+                        }
+                    } else if let Some(portal) = client.portals.remove(&PortalId {
+                        owner: client_id,
+                        index,
+                    }) {
+                        commands.entity(portal).despawn_recursive();
+                    }
+                }
+                ServerMessages::PropOwner {
+                    entity,
+                    owner,
+                    transform,
+                } => {
+                    let local_entity = match client
+                        .networked_entitys
+                        .get(&SERVER_ID)
+                        .and_then(|entities| entities.get(&entity))
+                    {
+                        Some(&local_entity) => local_entity,
+                        None => continue,
+                    };
+
+                    if owner == Some(client.client_id) {
+                        // simulate it here, starting from the server's latest state
+                        if let Ok((_, mut local_transform, _)) =
+                            networked_entitys.get_mut(local_entity)
+                        {
+                            local_transform.translation = transform.position;
+                        }
+                        commands
+                            .entity(local_entity)
+                            .remove::<Interpolated>()
+                            .insert((Velocity::new(transform.velocity), OwnedProp));
+                    } else {
+                        commands
+                            .entity(local_entity)
+                            .remove::<Velocity>()
+                            .remove::<OwnedProp>()
+                            .insert(Interpolated::new(
+                                time.elapsed_seconds(),
+                                transform.position,
+                            ));
                     }
                 }
                 ServerMessages::DespawnNetworkedEntity { client_id, entity } => {
-                    let local_entity = client.networked_entitys[&client_id][&entity];
-                    commands.entity(local_entity).despawn_recursive();
-                    client
+                    match client
                         .networked_entitys
                         .get_mut(&client_id)
-                        .unwrap()
-                        .remove(&entity);
+                        .and_then(|entities| entities.remove(&entity))
+                    {
+                        Some(local_entity) => commands.entity(local_entity).despawn_recursive(),
+                        None => warn!("Despawn of unknown entity {:?} of {}", entity, client_id),
+                    }
                 }
             }
         }
     }
 }
 
+/// Despawns everything the server told us about, before it is sent again
+pub fn reset_remote_world(commands: &mut Commands, client: &mut Client) {
+    for (_, player) in client.players.drain() {
+        commands.entity(player.entity).despawn_recursive();
+    }
+    for (_, entities) in client.networked_entitys.drain() {
+        for (_, local_entity) in entities {
+            commands.entity(local_entity).despawn_recursive();
+        }
+    }
+    for (_, portal) in client.portals.drain() {
+        commands.entity(portal).despawn_recursive();
+    }
+    client.stats.clear();
+    client.edits.clear();
+    client.game_mode = None;
+}
+
+/// Spawns another player's character, at the position and health it already has when
+/// joining from a snapshot
+fn spawn_remote_player(
+    commands: &mut Commands,
+    client: &mut Client,
+    client_id: u64,
+    username: String,
+    state: Option<(f32, Vec3, Health)>,
+) {
+    let mut entity_commands = commands.spawn((RemotePlayer, InGame));
+    let alive = match state {
+        Some((time, position, health)) => {
+            let alive = !health.is_dead();
+            entity_commands.insert((
+                Transform::from_translation(position),
+                Interpolated::new(time, position),
+                health,
+            ));
+            alive
+        }
+        None => {
+            entity_commands.insert((
+                Transform::default(),
+                Interpolated::default(),
+                Health::default(),
+            ));
+            true
+        }
+    };
+    // dead players are hidden until they respawn
+    if alive {
+        entity_commands.insert(bevy_voxel_engine::Box {
+            half_size: PLAYER_HALF_SIZE,
+            material: player_material(client.team(client_id)),
+        });
+    }
+    let entity = entity_commands.id();
+
+    client
+        .players
+        .insert(client_id, ClientPlayerData { username, entity });
+}
+
 fn update_player(
     mut client_resource: ResMut<ClientResource>,
     player: Query<(&Transform, &Velocity), With<CharacterEntity>>,
@@ -292,11 +747,9 @@ fn update_player(
         let message = ClientMessages::UpdatePlayer {
             position: player.translation,
             velocity: velocity.velocity,
+            respawn: client.respawn,
         };
-        client.client.send_message(
-            DefaultChannel::Reliable,
-            bincode::serialize(&message).unwrap(),
-        );
+        client.client.send(DefaultChannel::Reliable, &message);
     }
 }
 
@@ -307,29 +760,27 @@ fn update_networked_entitys(
     if let Some(client) = (*client_resource).as_mut() {
         for (entity, local_networked_entity, transform, velocity) in networked_entitys.iter() {
             if client.local_networked_entitys.contains(&entity) {
-                client.client.send_message(
+                client.client.send(
                     DefaultChannel::Reliable,
-                    bincode::serialize(&ClientMessages::UpdateNetworkedEntity {
+                    &ClientMessages::UpdateNetworkedEntity {
                         entity,
                         transform: NetworkTransform::from_transform(
                             transform,
                             velocity.map(|v| v.velocity).unwrap_or_default(),
                         ),
-                    })
-                    .unwrap(),
+                    },
                 );
             } else {
-                client.client.send_message(
+                client.client.send(
                     DefaultChannel::Reliable,
-                    bincode::serialize(&ClientMessages::SpawnNetworkedEntity {
+                    &ClientMessages::SpawnNetworkedEntity {
                         entity,
                         entity_type: local_networked_entity.entity_type,
                         transform: NetworkTransform::from_transform(
                             transform,
                             velocity.map(|v| v.velocity).unwrap_or_default(),
                         ),
-                    })
-                    .unwrap(),
+                    },
                 );
                 client.local_networked_entitys.insert(entity);
             }
@@ -337,10 +788,9 @@ fn update_networked_entitys(
 
         for entity in client.local_networked_entitys.clone().iter() {
             if networked_entitys.get(*entity).is_err() {
-                client.client.send_message(
+                client.client.send(
                     DefaultChannel::Reliable,
-                    bincode::serialize(&ClientMessages::DespawnNetworkedEntity { entity: *entity })
-                        .unwrap(),
+                    &ClientMessages::DespawnNetworkedEntity { entity: *entity },
                 );
                 client.local_networked_entitys.remove(entity);
             }
@@ -0,0 +1,343 @@
+use super::{
+    map::MapInfo,
+    moderation::Ban,
+    networking::ServerMessages,
+    portals::PortalAccess,
+    server::{Server, ServerResource},
+    voxel_world::VoxelWorld,
+};
+use crate::GameState;
+use bevy::prelude::*;
+use renet::DefaultChannel;
+use std::{
+    io::BufRead,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+};
+
+const HELP: &[&str] = &[
+    "/help - show this list",
+    "/list - list connected players",
+    "/kick <player> - disconnect a player",
+    "/ban <player> - disconnect a player and refuse them in the future",
+    "/map <name> - switch every client to assets/<name>.vox",
+    "/mute <player> - toggle whether a player can chat",
+    "/filter [word] - toggle a word in the chat filter, or list the filtered words",
+    "/password <password> - log in as admin, or change the admin password if already admin",
+    "/friendlyfire - toggle whether players can damage their own team",
+    "/portals <private|team|global> - set who can go through a player's portals",
+    // handled on the client, the server never sees it
+    "/ignore <player> - toggle hiding a player's chat, only for you",
+];
+// commands whose argument is a secret that mustn't end up in the logs
+const SECRET_COMMANDS: &[&str] = &["password"];
+
+pub enum Command {
+    Help,
+    List,
+    Kick(String),
+    Ban(String),
+    Map(String),
+    Mute(String),
+    Filter(Option<String>),
+    Password(String),
+    FriendlyFire,
+    Portals(PortalAccess),
+}
+
+/// The command line as it can be logged, with the argument of secret commands hidden
+pub fn redact(line: &str) -> String {
+    let (name, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+    if !argument.trim().is_empty() && SECRET_COMMANDS.contains(&name.trim_start_matches('/')) {
+        format!("{} <hidden>", name)
+    } else {
+        line.to_string()
+    }
+}
+
+impl Command {
+    /// Parses a command line, with or without the leading slash
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim().trim_start_matches('/');
+        let (name, argument) = match line.split_once(' ') {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+
+        let argument = |usage: &str| {
+            if argument.is_empty() {
+                Err(format!("Usage: {}", usage))
+            } else {
+                Ok(argument.to_string())
+            }
+        };
+
+        match name {
+            "help" => Ok(Command::Help),
+            "list" => Ok(Command::List),
+            "kick" => Ok(Command::Kick(argument("/kick <player>")?)),
+            "ban" => Ok(Command::Ban(argument("/ban <player>")?)),
+            "map" => Ok(Command::Map(argument("/map <name>")?)),
+            "mute" => Ok(Command::Mute(argument("/mute <player>")?)),
+            "filter" => Ok(Command::Filter(argument("/filter [word]").ok())),
+            "password" => Ok(Command::Password(argument("/password <password>")?)),
+            "friendlyfire" => Ok(Command::FriendlyFire),
+            "portals" => PortalAccess::parse(argument("/portals <private|team|global>")?.as_str())
+                .map(Command::Portals)
+                .ok_or_else(|| "Usage: /portals <private|team|global>".to_string()),
+            _ => Err(format!("Unknown command /{}, try /help", name)),
+        }
+    }
+
+    fn requires_admin(&self) -> bool {
+        !matches!(self, Command::Help | Command::Password(_))
+    }
+}
+
+impl Server {
+    /// Looks a player up by client id or username
+    pub fn find_player(&self, name: &str) -> Option<u64> {
+        if let Ok(client_id) = name.parse::<u64>() {
+            if self.players.contains_key(&client_id) {
+                return Some(client_id);
+            }
+        }
+        self.players
+            .iter()
+            .find(|(_, player)| player.username == name)
+            .map(|(client_id, _)| *client_id)
+    }
+
+    pub fn is_admin(&self, client_id: u64) -> bool {
+        self.players
+            .get(&client_id)
+            .map(|player| player.admin)
+            .unwrap_or(false)
+    }
+
+    /// Runs a command for a player, or for the server console when `issuer` is none,
+    /// and returns the lines to show the issuer
+    pub fn run_command(&mut self, issuer: Option<u64>, line: &str, time: f32) -> Vec<String> {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(error) => return vec![error],
+        };
+
+        let is_admin = issuer.map(|id| self.is_admin(id)).unwrap_or(true);
+        if command.requires_admin() && !is_admin {
+            return vec!["You need to be an admin to use that command".to_string()];
+        }
+
+        match command {
+            Command::Help => HELP.iter().map(|line| line.to_string()).collect(),
+            Command::List => {
+                let mut lines = vec![format!("{} player(s) connected:", self.players.len())];
+                for (client_id, player) in self.players.iter() {
+                    lines.push(format!(
+                        "{} ({}){}{}",
+                        player.username,
+                        client_id,
+                        if player.admin { " [admin]" } else { "" },
+                        if player.muted { " [muted]" } else { "" },
+                    ));
+                }
+                lines
+            }
+            Command::Kick(name) => match self.find_player(&name) {
+                Some(client_id) => {
+                    let username = self.players[&client_id].username.clone();
+                    self.end_session(client_id);
+                    self.disconnect_with_reason(client_id, "You were kicked".to_string(), time);
+                    self.broadcast_system_message(format!("{} was kicked", username));
+                    vec![]
+                }
+                None => vec![format!("No player called {}", name)],
+            },
+            Command::Ban(name) => match self.find_player(&name) {
+                Some(client_id) => {
+                    let username = self.players[&client_id].username.clone();
+                    self.bans.add(Ban {
+                        username: username.clone(),
+                        address: self.server.client_addr(client_id).map(|addr| addr.ip()),
+                        token: self.session_token_of(client_id),
+                    });
+                    self.end_session(client_id);
+                    self.disconnect_with_reason(client_id, "You were banned".to_string(), time);
+                    self.broadcast_system_message(format!("{} was banned", username));
+                    vec![]
+                }
+                None => vec![format!("No player called {}", name)],
+            },
+            Command::Map(map) => {
+                if map.contains(['/', '\\', '.']) {
+                    return vec![format!("Invalid map name {}", map)];
+                }
+                if !Path::new(&format!("assets/{}.vox", map)).exists() {
+                    return vec![format!("No map called {}", map)];
+                }
+
+                self.map = map.clone();
+                self.map_info = MapInfo::load(&map);
+                self.world = VoxelWorld::load(&map);
+                self.server.broadcast(
+                    DefaultChannel::Reliable,
+                    &ServerMessages::ChangeMap { map: map.clone() },
+                );
+                self.broadcast_system_message(format!("Map changed to {}", map));
+
+                // portals and props from the old map would be floating in the air
+                let client_ids: Vec<u64> = self.players.keys().copied().collect();
+                for client_id in client_ids {
+                    self.remove_portals(client_id);
+                    self.respawn_player(client_id, time);
+                }
+                let props: Vec<Entity> = self.props.keys().copied().collect();
+                for entity in props {
+                    self.despawn_prop(entity);
+                }
+                vec![]
+            }
+            Command::Mute(name) => match self.find_player(&name) {
+                Some(client_id) => {
+                    let player = self.players.get_mut(&client_id).unwrap();
+                    player.muted = !player.muted;
+                    let message = format!(
+                        "{} was {}",
+                        player.username,
+                        if player.muted { "muted" } else { "unmuted" }
+                    );
+                    self.broadcast_system_message(message);
+                    vec![]
+                }
+                None => vec![format!("No player called {}", name)],
+            },
+            Command::Filter(None) => {
+                if self.chat_settings.word_filter.is_empty() {
+                    vec!["No words are filtered".to_string()]
+                } else {
+                    vec![format!(
+                        "Filtered words: {}",
+                        self.chat_settings.word_filter.join(", ")
+                    )]
+                }
+            }
+            Command::Filter(Some(word)) => {
+                let word = word.to_lowercase();
+                let word_filter = &mut self.chat_settings.word_filter;
+                if let Some(index) = word_filter.iter().position(|w| *w == word) {
+                    word_filter.remove(index);
+                    vec![format!("{} is no longer filtered", word)]
+                } else {
+                    word_filter.push(word.clone());
+                    vec![format!("{} is now filtered", word)]
+                }
+            }
+            Command::Password(password) => match issuer {
+                Some(client_id) if !is_admin => {
+                    if self.admin_password.as_ref() == Some(&password) {
+                        self.players.get_mut(&client_id).unwrap().admin = true;
+                        vec!["You are now an admin".to_string()]
+                    } else {
+                        vec!["Wrong password".to_string()]
+                    }
+                }
+                _ => {
+                    self.admin_password = Some(password);
+                    vec!["Admin password changed".to_string()]
+                }
+            },
+            Command::FriendlyFire => {
+                self.rules.friendly_fire = !self.rules.friendly_fire;
+                self.broadcast_system_message(format!(
+                    "Friendly fire {}",
+                    if self.rules.friendly_fire {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                ));
+                self.broadcast_game_mode_state(time);
+                vec![]
+            }
+            Command::Portals(portal_access) => {
+                self.rules.portal_access = portal_access;
+                self.broadcast_system_message(format!(
+                    "Portal access set to {}",
+                    portal_access.name()
+                ));
+                self.broadcast_game_mode_state(time);
+                vec![]
+            }
+        }
+    }
+
+    pub fn send_system_message(&mut self, client_id: u64, message: String) {
+        self.server.send(
+            client_id,
+            DefaultChannel::Reliable,
+            &ServerMessages::SystemMessage { message },
+        );
+    }
+
+    pub fn broadcast_system_message(&mut self, message: String) {
+        info!("{}", message);
+        self.record_chat(None, message.clone());
+        self.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::SystemMessage { message },
+        );
+    }
+}
+
+/// Reads commands from stdin, used by the dedicated server
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        app.insert_resource(ConsoleInput(Mutex::new(receiver)))
+            .add_system_set(
+                SystemSet::on_update(GameState::Game).with_system(process_console_commands),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct ConsoleInput(Mutex<Receiver<String>>);
+
+fn process_console_commands(
+    mut server_resource: ResMut<ServerResource>,
+    console_input: Res<ConsoleInput>,
+    time: Res<Time>,
+) {
+    let receiver = console_input.0.lock().unwrap();
+    if let Some(server) = (*server_resource).as_mut() {
+        while let Ok(line) = receiver.try_recv() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            for reply in server.run_command(None, &line, time.elapsed_seconds()) {
+                info!("{}", reply);
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use super::{
+    client::ClientResource,
+    game_mode::{team_name, Winner},
+    networking::ServerMessages,
+    server::Server,
+};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+
+// seconds toasts and kill feed entries stay on screen
+const TOAST_TIME: f32 = 4.0;
+const KILL_FEED_TIME: f32 = 8.0;
+const MAX_KILL_FEED: usize = 6;
+
+pub struct GameEventsPlugin;
+
+impl Plugin for GameEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<GameEvent>()
+            .insert_resource(EventFeed::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::Game)
+                    .with_system(receive_game_events)
+                    .with_system(game_events_ui.after(receive_game_events)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(clear_event_feed));
+    }
+}
+
+/// Something that happened in the game that players should be told about, sent by the
+/// server and fired as a bevy event on the client
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum GameEvent {
+    PlayerJoined {
+        client_id: u64,
+        username: String,
+    },
+    PlayerLeft {
+        client_id: u64,
+        username: String,
+    },
+    PlayerKilled {
+        victim: u64,
+        // none when the player fell out of the world
+        killer: Option<u64>,
+        weapon: Weapon,
+        // whether the bullet went through a portal on its way
+        through_portal: bool,
+    },
+    RoundEnded {
+        winner: Winner,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Weapon {
+    PortalGun,
+    World,
+}
+
+impl Weapon {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weapon::PortalGun => "portal gun",
+            Weapon::World => "the void",
+        }
+    }
+}
+
+impl Server {
+    pub fn broadcast_game_event(&mut self, event: GameEvent) {
+        self.server
+            .broadcast(DefaultChannel::Reliable, &ServerMessages::GameEvent(event));
+    }
+}
+
+struct FeedEntry {
+    text: String,
+    color: Color32,
+    time: f32,
+}
+
+#[derive(Resource, Default)]
+struct EventFeed {
+    toasts: Vec<FeedEntry>,
+    kills: Vec<FeedEntry>,
+}
+
+fn receive_game_events(
+    mut game_events: EventReader<GameEvent>,
+    mut feed: ResMut<EventFeed>,
+    client_resource: Res<ClientResource>,
+    time: Res<Time>,
+) {
+    let client = match (*client_resource).as_ref() {
+        Some(client) => client,
+        None => return,
+    };
+    let username = |client_id: u64| {
+        if client_id == client.client_id {
+            client.username.clone()
+        } else {
+            client
+                .players
+                .get(&client_id)
+                .map(|player| player.username.clone())
+                .unwrap_or_else(|| client_id.to_string())
+        }
+    };
+    let now = time.elapsed_seconds();
+
+    for event in game_events.iter() {
+        match event {
+            GameEvent::PlayerJoined { username, .. } => feed.toasts.push(FeedEntry {
+                text: format!("{} joined the game", username),
+                color: Color32::LIGHT_GREEN,
+                time: now,
+            }),
+            GameEvent::PlayerLeft { username, .. } => feed.toasts.push(FeedEntry {
+                text: format!("{} left the game", username),
+                color: Color32::LIGHT_GRAY,
+                time: now,
+            }),
+            GameEvent::PlayerKilled {
+                victim,
+                killer,
+                weapon,
+                through_portal,
+            } => {
+                let text = match killer {
+                    Some(killer) => format!(
+                        "{} [{}{}] {}",
+                        username(*killer),
+                        weapon.name(),
+                        if *through_portal { " via portal" } else { "" },
+                        username(*victim)
+                    ),
+                    None => format!("{} fell into {}", username(*victim), weapon.name()),
+                };
+                // highlight kills involving us
+                let color = if *victim == client.client_id || *killer == Some(client.client_id) {
+                    Color32::GOLD
+                } else {
+                    Color32::WHITE
+                };
+                feed.kills.push(FeedEntry {
+                    text,
+                    color,
+                    time: now,
+                });
+                if feed.kills.len() > MAX_KILL_FEED {
+                    feed.kills.remove(0);
+                }
+            }
+            GameEvent::RoundEnded { winner } => {
+                let text = match winner {
+                    Winner::Player(client_id) => format!("{} won the round", username(*client_id)),
+                    Winner::Team(team) => format!("{} team won the round", team_name(*team)),
+                    Winner::Draw => "The round was a draw".to_string(),
+                };
+                feed.toasts.push(FeedEntry {
+                    text,
+                    color: Color32::GOLD,
+                    time: now,
+                });
+            }
+        }
+    }
+
+    feed.toasts.retain(|toast| now - toast.time < TOAST_TIME);
+    feed.kills.retain(|kill| now - kill.time < KILL_FEED_TIME);
+}
+
+fn game_events_ui(mut egui_context: ResMut<EguiContext>, feed: Res<EventFeed>) {
+    if !feed.kills.is_empty() {
+        egui::Area::new("kill_feed")
+            .anchor(egui::Align2::LEFT_TOP, [5.0, 5.0])
+            .show(egui_context.ctx_mut(), |ui| {
+                for kill in feed.kills.iter() {
+                    ui.colored_label(kill.color, &kill.text);
+                }
+            });
+    }
+
+    if !feed.toasts.is_empty() {
+        egui::Area::new("toasts")
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 80.0])
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.vertical_centered(|ui| {
+                    for toast in feed.toasts.iter() {
+                        ui.colored_label(toast.color, &toast.text);
+                    }
+                });
+            });
+    }
+}
+
+fn clear_event_feed(mut feed: ResMut<EventFeed>) {
+    *feed = EventFeed::default();
+}
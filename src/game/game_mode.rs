@@ -0,0 +1,514 @@
+use super::{
+    client::ClientResource,
+    events::GameEvent,
+    networking::ServerMessages,
+    portals::PortalAccess,
+    server::{Server, ServerResource},
+};
+use crate::GameState;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+
+// seconds between the end of a round and the start of the next one
+pub const INTERMISSION_TIME: f32 = 10.0;
+
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(game_mode_hud));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum GameModeKind {
+    Sandbox,
+    FreeForAll,
+    TeamDeathmatch,
+}
+
+impl GameModeKind {
+    pub const ALL: [GameModeKind; 3] = [
+        GameModeKind::Sandbox,
+        GameModeKind::FreeForAll,
+        GameModeKind::TeamDeathmatch,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameModeKind::Sandbox => "Sandbox",
+            GameModeKind::FreeForAll => "Free for all",
+            GameModeKind::TeamDeathmatch => "Team deathmatch",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sandbox" => Some(GameModeKind::Sandbox),
+            "ffa" | "freeforall" => Some(GameModeKind::FreeForAll),
+            "tdm" | "teamdeathmatch" => Some(GameModeKind::TeamDeathmatch),
+            _ => None,
+        }
+    }
+
+    pub fn create(&self) -> Box<dyn GameMode> {
+        match self {
+            GameModeKind::Sandbox => Box::new(Sandbox),
+            GameModeKind::FreeForAll => Box::new(FreeForAll { score_limit: 20 }),
+            GameModeKind::TeamDeathmatch => Box::new(TeamDeathmatch { score_limit: 50 }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoundPhase {
+    // waiting for the server to start the first round
+    Waiting,
+    Playing,
+    Ended,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Winner {
+    Player(u64),
+    Team(u8),
+    Draw,
+}
+
+/// Server side state of the current round
+pub struct Round {
+    pub phase: RoundPhase,
+    // end of the round while playing, end of the intermission once it has ended
+    pub ends_at: Option<f32>,
+    pub scores: HashMap<u64, i32>,
+    pub team_scores: Vec<i32>,
+    pub winner: Option<Winner>,
+}
+
+impl Default for Round {
+    fn default() -> Self {
+        Self {
+            phase: RoundPhase::Waiting,
+            ends_at: None,
+            scores: HashMap::default(),
+            team_scores: Vec::new(),
+            winner: None,
+        }
+    }
+}
+
+/// Settings of the current game that a mode picks defaults for and the host can change
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct GameRules {
+    // whether players can damage their own team
+    pub friendly_fire: bool,
+    pub portal_access: PortalAccess,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            friendly_fire: false,
+            portal_access: PortalAccess::Global,
+        }
+    }
+}
+
+/// A client id along with the team it is on
+pub type Combatant = (u64, Option<u8>);
+
+/// Rules of a game mode, run by the server
+pub trait GameMode: Send + Sync {
+    fn kind(&self) -> GameModeKind;
+
+    /// Length of a round in seconds, none if rounds don't end on time
+    fn round_length(&self) -> Option<f32>;
+
+    /// Number of teams players are split into, zero for no teams
+    fn teams(&self) -> u8 {
+        0
+    }
+
+    fn default_rules(&self) -> GameRules {
+        GameRules::default()
+    }
+
+    fn on_round_start(&mut self, round: &mut Round) {
+        round.scores.clear();
+        round.team_scores = vec![0; self.teams() as usize];
+    }
+
+    /// Scores a kill, `killer` being none when the victim died on their own
+    fn on_kill(&mut self, round: &mut Round, killer: Option<Combatant>, victim: Combatant);
+
+    /// Checks the win condition among `players`, the players still in the game, `time_up`
+    /// being set once the round timer has run out
+    fn winner(&self, round: &Round, players: &[u64], time_up: bool) -> Option<Winner>;
+}
+
+pub struct Sandbox;
+
+impl GameMode for Sandbox {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::Sandbox
+    }
+
+    fn round_length(&self) -> Option<f32> {
+        None
+    }
+
+    fn on_kill(&mut self, _: &mut Round, _: Option<Combatant>, _: Combatant) {}
+
+    fn winner(&self, _: &Round, _: &[u64], _: bool) -> Option<Winner> {
+        None
+    }
+}
+
+pub struct FreeForAll {
+    pub score_limit: i32,
+}
+
+impl GameMode for FreeForAll {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::FreeForAll
+    }
+
+    fn round_length(&self) -> Option<f32> {
+        Some(600.0)
+    }
+
+    fn default_rules(&self) -> GameRules {
+        GameRules {
+            portal_access: PortalAccess::Private,
+            ..default()
+        }
+    }
+
+    fn on_kill(&mut self, round: &mut Round, killer: Option<Combatant>, victim: Combatant) {
+        match killer {
+            Some((killer, _)) if killer != victim.0 => {
+                *round.scores.entry(killer).or_default() += 1;
+            }
+            // suicides and falling out of the world
+            _ => *round.scores.entry(victim.0).or_default() -= 1,
+        }
+    }
+
+    fn winner(&self, round: &Round, players: &[u64], time_up: bool) -> Option<Winner> {
+        // players that left can't win, and those that haven't scored are on 0
+        let score = |client_id: &u64| round.scores.get(client_id).copied().unwrap_or(0);
+        let best = match players.iter().map(score).max() {
+            Some(best) => best,
+            None => return time_up.then_some(Winner::Draw),
+        };
+        if best < self.score_limit && !time_up {
+            return None;
+        }
+
+        let mut leaders = players.iter().filter(|client_id| score(client_id) == best);
+        match (leaders.next(), leaders.next()) {
+            (Some(&client_id), None) => Some(Winner::Player(client_id)),
+            _ => Some(Winner::Draw),
+        }
+    }
+}
+
+pub struct TeamDeathmatch {
+    pub score_limit: i32,
+}
+
+impl GameMode for TeamDeathmatch {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::TeamDeathmatch
+    }
+
+    fn round_length(&self) -> Option<f32> {
+        Some(600.0)
+    }
+
+    fn teams(&self) -> u8 {
+        2
+    }
+
+    fn default_rules(&self) -> GameRules {
+        GameRules {
+            portal_access: PortalAccess::Team,
+            ..default()
+        }
+    }
+
+    fn on_kill(&mut self, round: &mut Round, killer: Option<Combatant>, victim: Combatant) {
+        match killer {
+            Some((killer, killer_team)) if killer != victim.0 => {
+                *round.scores.entry(killer).or_default() += 1;
+                // team kills count against the team
+                let change = if killer_team == victim.1 { -1 } else { 1 };
+                if let Some(score) =
+                    killer_team.and_then(|team| round.team_scores.get_mut(team as usize))
+                {
+                    *score += change;
+                }
+            }
+            _ => {
+                *round.scores.entry(victim.0).or_default() -= 1;
+            }
+        }
+    }
+
+    fn winner(&self, round: &Round, _: &[u64], time_up: bool) -> Option<Winner> {
+        let best = round.team_scores.iter().copied().max()?;
+        if best < self.score_limit && !time_up {
+            return None;
+        }
+
+        let mut leaders = round
+            .team_scores
+            .iter()
+            .enumerate()
+            .filter(|(_, &score)| score == best);
+        match (leaders.next(), leaders.next()) {
+            (Some((team, _)), None) => Some(Winner::Team(team as u8)),
+            _ => Some(Winner::Draw),
+        }
+    }
+}
+
+/// What clients know about the current round
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameModeState {
+    pub mode: GameModeKind,
+    pub teams: u8,
+    pub phase: RoundPhase,
+    // seconds until the round or intermission ends
+    pub time_remaining: Option<f32>,
+    pub players: Vec<PlayerScore>,
+    pub team_scores: Vec<i32>,
+    pub winner: Option<Winner>,
+    pub rules: GameRules,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerScore {
+    pub client_id: u64,
+    pub score: i32,
+    pub team: Option<u8>,
+}
+
+pub fn team_name(team: u8) -> &'static str {
+    match team {
+        0 => "Orange",
+        1 => "Blue",
+        _ => "Unknown",
+    }
+}
+
+impl Server {
+    /// Switches game mode, the new mode's first round starts on the next tick
+    pub fn set_game_mode(&mut self, kind: GameModeKind) {
+        self.game_mode = kind.create();
+        self.rules = self.game_mode.default_rules();
+        self.round = Round::default();
+
+        let mut client_ids: Vec<u64> = self.players.keys().copied().collect();
+        client_ids.sort();
+        for client_id in client_ids.iter() {
+            self.players.get_mut(client_id).unwrap().team = None;
+        }
+        for client_id in client_ids {
+            let team = self.smallest_team();
+            self.players.get_mut(&client_id).unwrap().team = team;
+        }
+    }
+
+    /// The team with the fewest players, none if the mode has no teams
+    pub fn smallest_team(&self) -> Option<u8> {
+        (0..self.game_mode.teams()).min_by_key(|&team| {
+            self.players
+                .values()
+                .filter(|player| player.team == Some(team))
+                .count()
+        })
+    }
+
+    fn combatant(&self, client_id: u64) -> Combatant {
+        (
+            client_id,
+            self.players.get(&client_id).and_then(|player| player.team),
+        )
+    }
+
+    pub fn register_kill(&mut self, killer: Option<u64>, victim: u64, time: f32) {
+        // scoreboard stats count outside of rounds too
+        if let Some(player) = self.players.get_mut(&victim) {
+            player.deaths += 1;
+        }
+        if let Some(player) = killer
+            .filter(|&killer| killer != victim)
+            .and_then(|killer| self.players.get_mut(&killer))
+        {
+            player.kills += 1;
+        }
+
+        if self.round.phase != RoundPhase::Playing {
+            return;
+        }
+
+        let killer = killer.map(|killer| self.combatant(killer));
+        let victim = self.combatant(victim);
+        self.game_mode.on_kill(&mut self.round, killer, victim);
+        self.broadcast_game_mode_state(time);
+    }
+
+    fn start_round(&mut self, time: f32) {
+        self.round.phase = RoundPhase::Playing;
+        self.round.ends_at = self.game_mode.round_length().map(|length| time + length);
+        self.round.winner = None;
+        self.game_mode.on_round_start(&mut self.round);
+        self.balance_teams(time);
+
+        let client_ids: Vec<u64> = self.players.keys().copied().collect();
+        for client_id in client_ids {
+            self.respawn_player(client_id, time);
+        }
+
+        info!("{} round started.", self.game_mode.kind().name());
+        self.broadcast_game_mode_state(time);
+    }
+
+    fn end_round(&mut self, winner: Winner, time: f32) {
+        self.round.phase = RoundPhase::Ended;
+        self.round.ends_at = Some(time + INTERMISSION_TIME);
+        self.round.winner = Some(winner);
+
+        info!("Round ended: {:?}", winner);
+        self.broadcast_game_mode_state(time);
+        self.broadcast_game_event(GameEvent::RoundEnded { winner });
+    }
+
+    pub fn game_mode_state(&self, time: f32) -> GameModeState {
+        let mut players: Vec<PlayerScore> = self
+            .players
+            .iter()
+            .map(|(&client_id, player)| PlayerScore {
+                client_id,
+                score: self.round.scores.get(&client_id).copied().unwrap_or(0),
+                team: player.team,
+            })
+            .collect();
+        players.sort_by_key(|player| -player.score);
+
+        GameModeState {
+            mode: self.game_mode.kind(),
+            teams: self.game_mode.teams(),
+            phase: self.round.phase,
+            time_remaining: self.round.ends_at.map(|ends_at| (ends_at - time).max(0.0)),
+            players,
+            team_scores: self.round.team_scores.clone(),
+            winner: self.round.winner,
+            rules: self.rules,
+        }
+    }
+
+    pub fn broadcast_game_mode_state(&mut self, time: f32) {
+        let state = self.game_mode_state(time);
+        self.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::GameModeState(state),
+        );
+    }
+}
+
+pub fn update_game_mode(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let now = time.elapsed_seconds();
+        let time_up = matches!(server.round.ends_at, Some(ends_at) if now >= ends_at);
+        match server.round.phase {
+            RoundPhase::Waiting => server.start_round(now),
+            RoundPhase::Playing => {
+                let players: Vec<u64> = server.players.keys().copied().collect();
+                if let Some(winner) = server.game_mode.winner(&server.round, &players, time_up) {
+                    server.end_round(winner, now);
+                }
+            }
+            RoundPhase::Ended => {
+                if time_up {
+                    server.start_round(now);
+                }
+            }
+        }
+    }
+}
+
+fn game_mode_hud(
+    mut egui_context: ResMut<EguiContext>,
+    client_resource: Res<ClientResource>,
+    time: Res<Time>,
+) {
+    let client = match (*client_resource).as_ref() {
+        Some(client) => client,
+        None => return,
+    };
+    let (state, received_at) = match &client.game_mode {
+        Some((state, received_at)) => (state, *received_at),
+        None => return,
+    };
+    if state.mode == GameModeKind::Sandbox {
+        return;
+    }
+
+    let username = |client_id: u64| {
+        if client_id == client.client_id {
+            client.username.clone()
+        } else {
+            client
+                .players
+                .get(&client_id)
+                .map(|player| player.username.clone())
+                .unwrap_or_else(|| client_id.to_string())
+        }
+    };
+
+    egui::Area::new("game_mode")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 5.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(state.mode.name());
+                if let Some(time_remaining) = state.time_remaining {
+                    let seconds =
+                        (time_remaining - (time.elapsed_seconds() - received_at)).max(0.0) as u32;
+                    ui.label(format!("{}:{:02}", seconds / 60, seconds % 60));
+                }
+
+                if !state.team_scores.is_empty() {
+                    let scores: Vec<String> = state
+                        .team_scores
+                        .iter()
+                        .enumerate()
+                        .map(|(team, score)| format!("{} {}", team_name(team as u8), score))
+                        .collect();
+                    ui.label(scores.join("  -  "));
+                } else if let Some(leader) = state.players.iter().max_by_key(|player| player.score)
+                {
+                    ui.label(format!(
+                        "Leader: {} ({})",
+                        username(leader.client_id),
+                        leader.score
+                    ));
+                }
+
+                if state.phase == RoundPhase::Ended {
+                    let text = match state.winner {
+                        Some(Winner::Player(client_id)) => format!("{} wins!", username(client_id)),
+                        Some(Winner::Team(team)) => format!("{} team wins!", team_name(team)),
+                        Some(Winner::Draw) | None => "Draw!".to_string(),
+                    };
+                    ui.colored_label(Color32::GOLD, text);
+                }
+            });
+        });
+}
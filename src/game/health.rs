@@ -0,0 +1,114 @@
+use super::character::CharacterEntity;
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use bevy_voxel_engine::VOXELS_PER_METER;
+
+pub const MAX_HEALTH: f32 = 100.0;
+// seconds between dying and respawning
+pub const RESPAWN_TIME: f32 = 3.0;
+pub const PLAYER_HALF_SIZE: IVec3 = IVec3::new(2, 4, 2);
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(health_hud));
+    }
+}
+
+#[derive(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    // local time until which spawn protection is active
+    pub protected_until: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: MAX_HEALTH,
+            max: MAX_HEALTH,
+            protected_until: 0.0,
+        }
+    }
+}
+
+impl Health {
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+/// Damage a bullet does on a hit, only portal bullets are fired so other types do none
+pub fn bullet_damage(bullet_type: u32) -> f32 {
+    match bullet_type {
+        1 | 2 => 25.0,
+        _ => 0.0,
+    }
+}
+
+/// Half size of a player's collision box in meters
+pub fn player_half_extents() -> Vec3 {
+    PLAYER_HALF_SIZE.as_vec3() / VOXELS_PER_METER
+}
+
+/// Returns whether the segment from `start` to `end` passes through the box
+pub fn segment_hits_box(start: Vec3, end: Vec3, center: Vec3, half_extents: Vec3) -> bool {
+    let min = center - half_extents;
+    let max = center + half_extents;
+    let direction = end - start;
+
+    // slab test, clipping the segment against each axis in turn
+    let mut t_min = 0.0f32;
+    let mut t_max = 1.0f32;
+    for axis in 0..3 {
+        if direction[axis].abs() < f32::EPSILON {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return false;
+            }
+        } else {
+            let t1 = (min[axis] - start[axis]) / direction[axis];
+            let t2 = (max[axis] - start[axis]) / direction[axis];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn health_hud(
+    mut egui_context: ResMut<EguiContext>,
+    character: Query<&Health, With<CharacterEntity>>,
+    time: Res<Time>,
+) {
+    let health = match character.get_single() {
+        Ok(health) => health,
+        Err(_) => return,
+    };
+
+    egui::Area::new("health")
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -20.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            if health.is_dead() {
+                ui.colored_label(Color32::RED, "You died, respawning...");
+            } else {
+                let color = if health.current > health.max * 0.3 {
+                    Color32::WHITE
+                } else {
+                    Color32::RED
+                };
+                ui.colored_label(color, format!("Health: {:.0}", health.current));
+                if time.elapsed_seconds() < health.protected_until {
+                    ui.colored_label(Color32::LIGHT_BLUE, "Spawn protection");
+                }
+            }
+        });
+}
@@ -0,0 +1,222 @@
+use super::{
+    networking::{ServerMessages, SERVER_ID},
+    replication::Replicated,
+    server::{Server, ServerResource},
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_voxel_engine::VOXELS_PER_METER;
+use renet::DefaultChannel;
+
+// how much further than the relevance distance an entity has to go before it is
+// despawned, so ones on the edge don't keep respawning
+const HYSTERESIS: f32 = 1.2;
+// seconds a spawned entity stays relevant after going out of sight, so ones that duck
+// in and out of cover don't keep respawning
+const OCCLUSION_GRACE: f32 = 1.0;
+
+pub struct Interest {
+    pub enabled: bool,
+    // meters from a player, straight or through a pair of portals, that entities are sent
+    pub distance: f32,
+    // entities each client currently has spawned
+    relevant: HashMap<u64, HashSet<Replicated>>,
+    // when each client last had a spawned entity in sight
+    last_seen: HashMap<u64, HashMap<Replicated, f32>>,
+}
+
+impl Default for Interest {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            distance: 64.0,
+            relevant: HashMap::default(),
+            last_seen: HashMap::default(),
+        }
+    }
+}
+
+impl Server {
+    /// Whether a client has been sent something and should get its updates, players are
+    /// always relevant
+    pub fn is_relevant(&self, client_id: u64, replicated: Replicated) -> bool {
+        match replicated {
+            Replicated::Player(_) => true,
+            Replicated::Entity { .. } => self
+                .interest
+                .relevant
+                .get(&client_id)
+                .map_or(false, |relevant| relevant.contains(&replicated)),
+        }
+    }
+
+    pub fn relevant_count(&self, client_id: u64) -> usize {
+        self.interest
+            .relevant
+            .get(&client_id)
+            .map_or(0, |relevant| relevant.len())
+    }
+
+    /// How far a point is from a player, the shorter of straight there or in one portal
+    /// and out of its partner. Walls aren't taken into account here, `is_visible` is
+    /// what checks for them
+    pub fn interest_distance(&self, viewer: Vec3, position: Vec3) -> f32 {
+        self.portals
+            .iter()
+            .filter_map(|(id, placement)| {
+                let partner = self.portals.get(&id.partner())?;
+                Some(viewer.distance(placement.position) + partner.position.distance(position))
+            })
+            .fold(viewer.distance(position), f32::min)
+    }
+
+    fn in_sight(&self, from: Vec3, to: Vec3) -> bool {
+        let offset = to - from;
+        self.world.raycast(from, offset, offset.length()).is_none()
+    }
+
+    /// Whether nothing in the voxel world is between a player and a point, straight there
+    /// or through a portal the player can see and out of its partner
+    pub fn is_visible(&self, viewer: Vec3, position: Vec3) -> bool {
+        self.in_sight(viewer, position)
+            || self.portals.iter().any(|(id, placement)| {
+                self.portals.get(&id.partner()).map_or(false, |partner| {
+                    // just off the wall so the portal's own voxel doesn't block the ray
+                    let entrance = placement.position + placement.normal / VOXELS_PER_METER;
+                    let exit = partner.position + partner.normal / VOXELS_PER_METER;
+                    self.in_sight(viewer, entrance) && self.in_sight(exit, position)
+                })
+            })
+    }
+
+    /// The entities relevant to a player and when each of them was last in sight
+    fn relevant_set(
+        &self,
+        client_id: u64,
+        viewer: Vec3,
+        time: f32,
+    ) -> (HashSet<Replicated>, HashMap<Replicated, f32>) {
+        let previous = self.interest.relevant.get(&client_id);
+        let previous_seen = self.interest.last_seen.get(&client_id);
+
+        let entities = self
+            .networked_entities()
+            // owners already have their own entities
+            .filter(|&(owner, ..)| owner != client_id)
+            .map(|(owner, entity, _, transform)| {
+                (
+                    Replicated::Entity { owner, entity },
+                    transform.position,
+                    false,
+                )
+            });
+        let props = self.props.iter().map(|(&entity, prop)| {
+            (
+                Replicated::Entity {
+                    owner: SERVER_ID,
+                    entity,
+                },
+                prop.transform.position,
+                // whoever simulates a prop needs it
+                prop.owner == Some(client_id),
+            )
+        });
+
+        let mut relevant = HashSet::default();
+        let mut seen = HashMap::default();
+        for (replicated, position, needed) in entities.chain(props) {
+            if needed || !self.interest.enabled {
+                relevant.insert(replicated);
+                continue;
+            }
+            let spawned = previous.map_or(false, |previous| previous.contains(&replicated));
+            let distance = if spawned {
+                self.interest.distance * HYSTERESIS
+            } else {
+                self.interest.distance
+            };
+            if self.interest_distance(viewer, position) > distance {
+                continue;
+            }
+
+            if self.is_visible(viewer, position) {
+                seen.insert(replicated, time);
+                relevant.insert(replicated);
+            } else if let Some(&last_seen) = previous_seen
+                .and_then(|previous_seen| previous_seen.get(&replicated))
+                .filter(|&&last_seen| spawned && time - last_seen <= OCCLUSION_GRACE)
+            {
+                seen.insert(replicated, last_seen);
+                relevant.insert(replicated);
+            }
+        }
+        (relevant, seen)
+    }
+
+    /// Counts everything relevant to a player as spawned on its client, for when the
+    /// spawns are sent some other way
+    pub fn start_interest(&mut self, client_id: u64, viewer: Vec3, time: f32) -> Vec<Replicated> {
+        let (relevant, seen) = self.relevant_set(client_id, viewer, time);
+        let spawned = relevant.iter().copied().collect();
+        self.interest.relevant.insert(client_id, relevant);
+        self.interest.last_seen.insert(client_id, seen);
+        spawned
+    }
+}
+
+/// Spawns entities on the clients they became relevant to and despawns them from the ones
+/// they left, including entities that were removed or stayed out of sight
+pub fn update_interest(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let time = time.elapsed_seconds();
+        let players = &server.players;
+        server
+            .interest
+            .relevant
+            .retain(|client_id, _| players.contains_key(client_id));
+        server
+            .interest
+            .last_seen
+            .retain(|client_id, _| players.contains_key(client_id));
+
+        let viewers: Vec<(u64, Vec3)> = server
+            .players
+            .iter()
+            .map(|(&client_id, player)| (client_id, player.position))
+            .collect();
+        for (client_id, viewer) in viewers {
+            let (relevant, seen) = server.relevant_set(client_id, viewer, time);
+            let previous = server
+                .interest
+                .relevant
+                .remove(&client_id)
+                .unwrap_or_default();
+
+            for &replicated in relevant.difference(&previous) {
+                let snapshot = server.entity_snapshot(replicated).unwrap();
+                for message in snapshot.messages() {
+                    server
+                        .server
+                        .send(client_id, DefaultChannel::Reliable, &message);
+                }
+            }
+            for &replicated in previous.difference(&relevant) {
+                if let Replicated::Entity { owner, entity } = replicated {
+                    server.server.send(
+                        client_id,
+                        DefaultChannel::Reliable,
+                        &ServerMessages::DespawnNetworkedEntity {
+                            client_id: owner,
+                            entity,
+                        },
+                    );
+                }
+            }
+
+            server.interest.relevant.insert(client_id, relevant);
+            server.interest.last_seen.insert(client_id, seen);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+// how far behind the newest update remote entities are drawn, so there is usually a pair
+// of snapshots to interpolate between
+pub const INTERPOLATION_DELAY: f32 = 0.1;
+const MAX_SNAPSHOTS: usize = 32;
+
+struct Snapshot {
+    time: f32,
+    position: Vec3,
+    // jump straight to this snapshot instead of interpolating towards it
+    teleport: bool,
+}
+
+/// Positions received for a remote entity, stamped with the local time they arrived
+#[derive(Component, Default)]
+pub struct Interpolated {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Interpolated {
+    pub fn new(time: f32, position: Vec3) -> Self {
+        let mut interpolated = Self {
+            snapshots: VecDeque::new(),
+        };
+        interpolated.teleport(time, position);
+        interpolated
+    }
+
+    pub fn push(&mut self, time: f32, position: Vec3) {
+        self.push_snapshot(Snapshot {
+            time,
+            position,
+            teleport: false,
+        });
+    }
+
+    /// Adds a position that is jumped to rather than interpolated towards, e.g. a respawn
+    pub fn teleport(&mut self, time: f32, position: Vec3) {
+        self.push_snapshot(Snapshot {
+            time,
+            position,
+            teleport: true,
+        });
+    }
+
+    /// Moves into the source portal and then jumps out of the destination, so the entity
+    /// isn't dragged across the map between the two
+    pub fn traverse_portal(&mut self, time: f32, source: Vec3, destination: Vec3) {
+        self.push(time, source);
+        self.teleport(time, destination);
+    }
+
+    fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Position at `render_time`, dropping the snapshots that are no longer needed
+    pub fn sample(&mut self, render_time: f32) -> Option<Vec3> {
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+
+        let next = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.time > render_time);
+        match next {
+            None => self.snapshots.back().map(|snapshot| snapshot.position),
+            Some(0) => Some(self.snapshots[0].position),
+            Some(i) => {
+                let a = &self.snapshots[i - 1];
+                let b = &self.snapshots[i];
+                if b.teleport {
+                    Some(a.position)
+                } else {
+                    let t = (render_time - a.time) / (b.time - a.time);
+                    Some(a.position.lerp(b.position, t))
+                }
+            }
+        }
+    }
+}
+
+pub fn interpolate_remote_entities(
+    mut interpolated: Query<(&mut Transform, &mut Interpolated)>,
+    time: Res<Time>,
+) {
+    let render_time = time.elapsed_seconds() - INTERPOLATION_DELAY;
+    for (mut transform, mut interpolated) in interpolated.iter_mut() {
+        if let Some(position) = interpolated.sample(render_time) {
+            transform.translation = position;
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use super::{
+    health::PLAYER_HALF_SIZE,
+    interpolation::INTERPOLATION_DELAY,
+    server::{Server, ServerResource},
+    InGame,
+};
+use bevy::{prelude::*, utils::HashMap};
+use std::collections::VecDeque;
+
+// seconds a rewound hitbox stays visible in the debug overlay
+const DEBUG_HITBOX_TIME: f32 = 0.5;
+const DEBUG_HITBOX_MATERIAL: u8 = 120;
+
+pub struct LagCompensation {
+    pub enabled: bool,
+    // furthest back in seconds the server will rewind players for a shot
+    pub max_rewind: f32,
+    pub show_hitboxes: bool,
+    // last rewound position of each player and the time it stops being shown
+    pub debug_hitboxes: HashMap<u64, (Vec3, f32)>,
+}
+
+impl Default for LagCompensation {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_rewind: 0.25,
+            show_hitboxes: false,
+            debug_hitboxes: HashMap::default(),
+        }
+    }
+}
+
+/// Positions of a player over the last few ticks, oldest first
+#[derive(Default)]
+pub struct PositionHistory {
+    samples: VecDeque<(f32, Vec3)>,
+}
+
+impl PositionHistory {
+    /// Adds a sample and forgets the ones older than `keep` seconds
+    pub fn record(&mut self, time: f32, position: Vec3, keep: f32) {
+        self.samples.push_back((time, position));
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if oldest >= time - keep {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Interpolated position at `time`, clamped to the recorded range
+    pub fn sample(&self, time: f32) -> Option<Vec3> {
+        let &(first_time, first_position) = self.samples.front()?;
+        if time <= first_time {
+            return Some(first_position);
+        }
+
+        for (&(t0, p0), &(t1, p1)) in self.samples.iter().zip(self.samples.iter().skip(1)) {
+            if time >= t0 && time <= t1 {
+                let s = if t1 > t0 {
+                    (time - t0) / (t1 - t0)
+                } else {
+                    1.0
+                };
+                return Some(p0.lerp(p1, s));
+            }
+        }
+
+        self.samples.back().map(|&(_, position)| position)
+    }
+}
+
+impl Server {
+    /// The server time whose state the shooter was looking at when it sent an update, half
+    /// a round trip for the update to arrive plus how far behind remote players are drawn
+    pub fn shooter_view_time(&self, client_id: u64, now: f32) -> f32 {
+        if !self.lag_compensation.enabled {
+            return now;
+        }
+
+        let rtt = self
+            .server
+            .network_info(client_id)
+            .map(|info| info.rtt as f32 / 1000.0)
+            .unwrap_or(0.0);
+        now - (rtt / 2.0 + INTERPOLATION_DELAY).min(self.lag_compensation.max_rewind)
+    }
+
+    /// Moves every player back to where they were at `time`, returning their real positions
+    /// so they can be put back with `restore_players`
+    pub fn rewind_players(&mut self, time: f32, now: f32) -> Vec<(u64, Vec3)> {
+        let mut saved = Vec::with_capacity(self.players.len());
+        for (&client_id, player) in self.players.iter_mut() {
+            saved.push((client_id, player.position));
+            if let Some(position) = player.history.sample(time) {
+                player.position = position;
+            }
+
+            if self.lag_compensation.show_hitboxes {
+                self.lag_compensation
+                    .debug_hitboxes
+                    .insert(client_id, (player.position, now + DEBUG_HITBOX_TIME));
+            }
+        }
+        saved
+    }
+
+    pub fn restore_players(&mut self, saved: Vec<(u64, Vec3)>) {
+        for (client_id, position) in saved {
+            if let Some(player) = self.players.get_mut(&client_id) {
+                player.position = position;
+            }
+        }
+    }
+}
+
+pub fn record_player_history(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let now = time.elapsed_seconds();
+        // keep a little more than the window so sampling the oldest time still interpolates
+        let keep = server.lag_compensation.max_rewind + 0.1;
+        for player in server.players.values_mut() {
+            player.history.record(now, player.position, keep);
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct RewoundHitbox(u64);
+
+/// Draws the rewound hitboxes of recent shots as voxel boxes on the host while the debug
+/// toggle is on, moving the box of each player rather than spawning a new one
+pub fn draw_rewound_hitboxes(
+    mut commands: Commands,
+    mut server_resource: ResMut<ServerResource>,
+    mut hitboxes: Query<(Entity, &RewoundHitbox, &mut Transform)>,
+    time: Res<Time>,
+) {
+    let server = match (*server_resource).as_mut() {
+        Some(server) if server.lag_compensation.show_hitboxes => server,
+        server => {
+            if let Some(server) = server {
+                server.lag_compensation.debug_hitboxes.clear();
+            }
+            for (entity, ..) in hitboxes.iter() {
+                commands.entity(entity).despawn();
+            }
+            return;
+        }
+    };
+
+    let now = time.elapsed_seconds();
+    let debug_hitboxes = &mut server.lag_compensation.debug_hitboxes;
+    debug_hitboxes.retain(|_, (_, expires)| *expires > now);
+
+    let mut drawn = Vec::new();
+    for (entity, hitbox, mut transform) in hitboxes.iter_mut() {
+        match debug_hitboxes.get(&hitbox.0) {
+            Some((position, _)) => {
+                transform.translation = *position;
+                drawn.push(hitbox.0);
+            }
+            None => commands.entity(entity).despawn(),
+        }
+    }
+
+    for (client_id, (position, _)) in debug_hitboxes.iter() {
+        if !drawn.contains(client_id) {
+            commands.spawn((
+                Transform::from_translation(*position),
+                bevy_voxel_engine::Box {
+                    half_size: PLAYER_HALF_SIZE,
+                    material: DEBUG_HITBOX_MATERIAL,
+                },
+                RewoundHitbox(*client_id),
+                InGame,
+            ));
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use super::{
+    client::ClientResource,
+    network_stats::NetworkStats,
+    server::ServerResource,
+    transport::{ClientTransport, ServerTransport},
+};
+use crate::GameState;
+use bevy::{prelude::*, utils::HashMap};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use renet::{DefaultChannel, NetworkInfo, ServerEvent};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    net::SocketAddr,
+    time::Duration,
+};
+
+// roughly how long the reliable channel waits before resending a lost packet
+const RESEND_TIME: f32 = 0.2;
+// most a reordered message is held back on top of its latency
+const REORDER_DELAY: f32 = 0.05;
+// channels whose messages are conditioned, others like chunks pass straight through
+const CHANNELS: [DefaultChannel; 2] = [DefaultChannel::Reliable, DefaultChannel::Unreliable];
+
+fn conditioned(channel: DefaultChannel) -> bool {
+    CHANNELS
+        .iter()
+        .any(|&other| u8::from(other) == u8::from(channel))
+}
+
+pub struct LinkConditionerPlugin;
+
+impl Plugin for LinkConditionerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Game).with_system(apply_link_conditioner_settings),
+        );
+    }
+}
+
+/// How bad the simulated network is, latencies being in seconds and the rest chances from
+/// zero to one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkConditions {
+    pub latency: f32,
+    // most the latency of a message varies either way
+    pub jitter: f32,
+    pub loss: f32,
+    pub duplication: f32,
+    pub reordering: f32,
+}
+
+/// Link conditions to start the game with, e.g. from the command line
+#[derive(Resource)]
+pub struct LinkConditionerSettings {
+    pub conditions: LinkConditions,
+    pub seed: Option<u64>,
+}
+
+impl LinkConditionerSettings {
+    /// Reads `--latency <ms> --jitter <ms> --loss <%> --duplicate <%> --reorder <%>
+    /// --seed <n>`, none if no condition is given
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        let value = |flag: &str, max: f32| -> Result<Option<f32>, String> {
+            match args.iter().position(|arg| arg == flag) {
+                Some(index) => args
+                    .get(index + 1)
+                    .and_then(|value| value.parse::<f32>().ok())
+                    .filter(|value| (0.0..=max).contains(value))
+                    .map(Some)
+                    .ok_or(format!("{} needs a number from 0 to {}", flag, max)),
+                None => Ok(None),
+            }
+        };
+
+        let latency = value("--latency", f32::MAX)?;
+        let jitter = value("--jitter", f32::MAX)?;
+        let loss = value("--loss", 100.0)?;
+        let duplication = value("--duplicate", 100.0)?;
+        let reordering = value("--reorder", 100.0)?;
+        if [latency, jitter, loss, duplication, reordering]
+            .iter()
+            .all(Option::is_none)
+        {
+            return Ok(None);
+        }
+
+        let seed = match args.iter().position(|arg| arg == "--seed") {
+            Some(index) => Some(
+                args.get(index + 1)
+                    .and_then(|seed| seed.parse().ok())
+                    .ok_or("--seed needs a whole number")?,
+            ),
+            None => None,
+        };
+
+        Ok(Some(Self {
+            conditions: LinkConditions {
+                latency: latency.unwrap_or(0.0) / 1000.0,
+                jitter: jitter.unwrap_or(0.0) / 1000.0,
+                loss: loss.unwrap_or(0.0) / 100.0,
+                duplication: duplication.unwrap_or(0.0) / 100.0,
+                reordering: reordering.unwrap_or(0.0) / 100.0,
+            },
+            seed,
+        }))
+    }
+
+    pub fn apply(&self, conditioner: &mut LinkConditioner) {
+        conditioner.conditions = self.conditions;
+        if let Some(seed) = self.seed {
+            conditioner.reseed(seed);
+        }
+        info!("Simulating network conditions {:?}", self.conditions);
+    }
+}
+
+/// Clients condition their own link, a server only conditions its links when it has no
+/// local client so the host's connection isn't slowed down twice. A dedicated server
+/// applies the settings itself
+fn apply_link_conditioner_settings(
+    settings: Option<Res<LinkConditionerSettings>>,
+    client_resource: Option<ResMut<ClientResource>>,
+    server_resource: Option<ResMut<ServerResource>>,
+) {
+    let settings = match settings {
+        Some(settings) => settings,
+        None => return,
+    };
+
+    let client = client_resource.and_then(|resource| resource.into_inner().0.as_mut());
+    let server = server_resource.and_then(|resource| resource.into_inner().0.as_mut());
+    let conditioner = match client {
+        Some(client) => client.client.conditioner(),
+        None => server.and_then(|server| server.server.conditioner()),
+    };
+    if let Some(conditioner) = conditioner {
+        settings.apply(conditioner);
+    }
+}
+
+struct DelayedMessage {
+    deliver_at: f32,
+    // order it was sent in, to break ties
+    order: u64,
+    channel: DefaultChannel,
+    // position in the reliable stream, none for unreliable messages
+    sequence: Option<u64>,
+    message: Vec<u8>,
+}
+
+/// Messages in flight in one direction of one connection, and the receiving end of its
+/// reliable stream
+#[derive(Default)]
+struct Link {
+    in_flight: Vec<DelayedMessage>,
+    sent: u64,
+    next_sequence: u64,
+    // next reliable message to hand on, later ones that arrive first wait in `early`
+    next_delivery: u64,
+    early: BTreeMap<u64, (DefaultChannel, Vec<u8>)>,
+}
+
+impl Link {
+    fn queued(&self) -> usize {
+        self.in_flight.len() + self.early.len()
+    }
+}
+
+/// Decides what happens to every message, running off its own clock so a seeded
+/// conditioner always does the same thing given the same frames
+pub struct LinkConditioner {
+    pub conditions: LinkConditions,
+    rng: StdRng,
+    time: f32,
+    // reliable messages that arrived twice and were dropped, and ones that arrived ahead
+    // of an earlier message and were held back for it
+    pub duplicates_dropped: u64,
+    pub reordered: u64,
+}
+
+impl LinkConditioner {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+            time: 0.0,
+            duplicates_dropped: 0,
+            reordered: 0,
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// What the connection looks like with the simulated latency and loss on top, the
+    /// latency being added both ways
+    fn condition_info(&self, info: NetworkInfo) -> NetworkInfo {
+        NetworkInfo {
+            rtt: info.rtt + self.conditions.latency * 2000.0,
+            packet_loss: info.packet_loss.max(self.conditions.loss),
+            ..info
+        }
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        self.time += duration.as_secs_f32();
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0.0 && self.rng.gen::<f32>() < probability
+    }
+
+    /// Puts a message on a link, losing, duplicating or holding it back as the conditions
+    /// say. Reliable messages go through this too, as their packets would, but lost ones
+    /// are resent rather than dropped as renet would
+    fn send(&mut self, link: &mut Link, channel: DefaultChannel, message: Vec<u8>) {
+        let conditions = self.conditions;
+        let reliable = u8::from(channel) != u8::from(DefaultChannel::Unreliable);
+        let sequence = reliable.then(|| {
+            link.next_sequence += 1;
+            link.next_sequence - 1
+        });
+        let copies = if self.chance(conditions.duplication) {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let jitter = if conditions.jitter > 0.0 {
+                self.rng.gen_range(-conditions.jitter..conditions.jitter)
+            } else {
+                0.0
+            };
+            let mut deliver_at = self.time + (conditions.latency + jitter).max(0.0);
+            if reliable {
+                // a link losing everything would never deliver
+                while self.chance(conditions.loss.min(0.9)) {
+                    deliver_at += RESEND_TIME;
+                }
+            } else if self.chance(conditions.loss) {
+                continue;
+            }
+            if self.chance(conditions.reordering) {
+                deliver_at += self.rng.gen_range(0.0..REORDER_DELAY + conditions.jitter);
+            }
+
+            link.sent += 1;
+            link.in_flight.push(DelayedMessage {
+                deliver_at,
+                order: link.sent,
+                channel,
+                sequence,
+                message: message.clone(),
+            });
+        }
+    }
+
+    /// Takes the messages that have arrived, dropping reliable ones that already arrived
+    /// and holding back ones that overtook an earlier message, as renet's reliable channel
+    /// does
+    fn receive(&mut self, link: &mut Link) -> Vec<(DefaultChannel, Vec<u8>)> {
+        let time = self.time;
+        let (mut due, in_flight): (Vec<_>, Vec<_>) = link
+            .in_flight
+            .drain(..)
+            .partition(|message| message.deliver_at <= time);
+        link.in_flight = in_flight;
+        due.sort_by(|a, b| {
+            a.deliver_at
+                .total_cmp(&b.deliver_at)
+                .then(a.order.cmp(&b.order))
+        });
+
+        let mut received = Vec::new();
+        for message in due {
+            let sequence = match message.sequence {
+                Some(sequence) => sequence,
+                None => {
+                    received.push((message.channel, message.message));
+                    continue;
+                }
+            };
+            if sequence < link.next_delivery || link.early.contains_key(&sequence) {
+                self.duplicates_dropped += 1;
+                continue;
+            }
+            if sequence > link.next_delivery {
+                self.reordered += 1;
+            }
+            link.early
+                .insert(sequence, (message.channel, message.message));
+            while let Some(message) = link.early.remove(&link.next_delivery) {
+                received.push(message);
+                link.next_delivery += 1;
+            }
+        }
+        received
+    }
+}
+
+#[derive(Default)]
+struct ReceivedMessages(HashMap<u8, VecDeque<Vec<u8>>>);
+
+impl ReceivedMessages {
+    fn push(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        self.0.entry(channel.into()).or_default().push_back(message);
+    }
+
+    fn pop(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.0.get_mut(&channel.into())?.pop_front()
+    }
+}
+
+/// Runs a client's messages both ways through a `LinkConditioner`. It sits above renet, so
+/// renet's own acks and RTT never see the simulated latency, only `network_info` adds it
+pub struct ConditionedClientTransport {
+    inner: Box<dyn ClientTransport>,
+    conditioner: LinkConditioner,
+    outgoing: Link,
+    incoming: Link,
+    received: ReceivedMessages,
+}
+
+impl ConditionedClientTransport {
+    pub fn new(inner: Box<dyn ClientTransport>, conditioner: LinkConditioner) -> Self {
+        Self {
+            inner,
+            conditioner,
+            outgoing: Link::default(),
+            incoming: Link::default(),
+            received: ReceivedMessages::default(),
+        }
+    }
+}
+
+impl ClientTransport for ConditionedClientTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.conditioner.advance(duration);
+        self.inner.update(duration)?;
+
+        for channel in CHANNELS {
+            while let Some(message) = self.inner.receive_message(channel) {
+                self.conditioner.send(&mut self.incoming, channel, message);
+            }
+        }
+        for (channel, message) in self.conditioner.receive(&mut self.incoming) {
+            self.received.push(channel, message);
+        }
+        Ok(())
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        for (channel, message) in self.conditioner.receive(&mut self.outgoing) {
+            self.inner.send_message(channel, message);
+        }
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        if !conditioned(channel) {
+            self.inner.send_message(channel, message);
+            return;
+        }
+        self.conditioner.send(&mut self.outgoing, channel, message);
+    }
+
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        if !conditioned(channel) {
+            return self.inner.receive_message(channel);
+        }
+        self.received.pop(channel)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn disconnect(&mut self) {
+        self.inner.disconnect();
+    }
+
+    fn network_info(&self) -> NetworkInfo {
+        self.conditioner.condition_info(self.inner.network_info())
+    }
+
+    fn queued_messages(&self) -> usize {
+        self.outgoing.queued() + self.inner.queued_messages()
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        Some(&mut self.conditioner)
+    }
+
+    fn stats(&self) -> Option<&NetworkStats> {
+        self.inner.stats()
+    }
+
+    fn reconnect(&mut self, transport: Box<dyn ClientTransport>) {
+        // whatever was in flight went down with the old connection
+        self.inner = transport;
+        self.outgoing = Link::default();
+        self.incoming = Link::default();
+        self.received = ReceivedMessages::default();
+    }
+}
+
+/// Runs every client's messages both ways through one `LinkConditioner`. Like the client
+/// side it sits above renet, so renet's acks and RTT don't see the simulated latency
+pub struct ConditionedServerTransport {
+    inner: Box<dyn ServerTransport>,
+    conditioner: LinkConditioner,
+    outgoing: HashMap<u64, Link>,
+    incoming: HashMap<u64, Link>,
+    received: HashMap<u64, ReceivedMessages>,
+}
+
+impl ConditionedServerTransport {
+    pub fn new(inner: Box<dyn ServerTransport>, conditioner: LinkConditioner) -> Self {
+        Self {
+            inner,
+            conditioner,
+            outgoing: HashMap::default(),
+            incoming: HashMap::default(),
+            received: HashMap::default(),
+        }
+    }
+}
+
+impl ServerTransport for ConditionedServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.conditioner.advance(duration);
+        self.inner.update(duration)?;
+
+        let clients = self.inner.clients_id();
+        self.outgoing
+            .retain(|client_id, _| clients.contains(client_id));
+        self.incoming
+            .retain(|client_id, _| clients.contains(client_id));
+        self.received
+            .retain(|client_id, _| clients.contains(client_id));
+
+        for client_id in clients {
+            let incoming = self.incoming.entry(client_id).or_default();
+            for channel in CHANNELS {
+                while let Some(message) = self.inner.receive_message(client_id, channel) {
+                    self.conditioner.send(incoming, channel, message);
+                }
+            }
+            let received = self.received.entry(client_id).or_default();
+            for (channel, message) in self.conditioner.receive(incoming) {
+                received.push(channel, message);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.inner.get_event()
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        for (&client_id, link) in self.outgoing.iter_mut() {
+            for (channel, message) in self.conditioner.receive(link) {
+                self.inner.send_message(client_id, channel, message);
+            }
+        }
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        if !conditioned(channel) {
+            self.inner.send_message(client_id, channel, message);
+            return;
+        }
+        let link = self.outgoing.entry(client_id).or_default();
+        self.conditioner.send(link, channel, message);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        for client_id in self.inner.clients_id() {
+            self.send_message(client_id, channel, message.clone());
+        }
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        for client_id in self.inner.clients_id() {
+            if client_id != except_id {
+                self.send_message(client_id, channel, message.clone());
+            }
+        }
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        if !conditioned(channel) {
+            return self.inner.receive_message(client_id, channel);
+        }
+        self.received.get_mut(&client_id)?.pop(channel)
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        self.inner.clients_id()
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        self.inner.disconnect(client_id);
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        self.inner
+            .network_info(client_id)
+            .map(|info| self.conditioner.condition_info(info))
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        self.inner.client_addr(client_id)
+    }
+
+    fn queued_messages(&self, client_id: u64) -> usize {
+        self.outgoing
+            .get(&client_id)
+            .map_or(0, |link| link.queued())
+            + self.inner.queued_messages(client_id)
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        Some(&mut self.conditioner)
+    }
+
+    fn stats(&self, client_id: u64) -> Option<&NetworkStats> {
+        self.inner.stats(client_id)
+    }
+}
@@ -0,0 +1,157 @@
+use super::SPAWN_POSITION;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use std::fs;
+
+/// Gameplay information for a map that isn't stored in the .vox itself, read from
+/// `assets/<map>.map` next to it
+///
+/// The file is a list of lines, in meters, with `#` comments:
+/// - `spawn x y z` adds a spawn point
+/// - `kill_plane y` sets the height players die below
+/// - `bounds x1 y1 z1 x2 y2 z2` sets the box players have to stay in
+/// - `no_portal m1 m2 ...` lists palette indices portals can't be placed on
+/// - `spawn_rule farthest|random` sets how spawn points are picked
+pub struct MapInfo {
+    pub spawn_points: Vec<Vec3>,
+    // players below this height are killed and respawned
+    pub kill_plane: f32,
+    // players leaving this box are respawned
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub no_portal_materials: Vec<u8>,
+    pub spawn_rule: SpawnRule,
+}
+
+impl Default for MapInfo {
+    fn default() -> Self {
+        Self {
+            spawn_points: vec![SPAWN_POSITION],
+            kill_plane: -64.0,
+            bounds_min: Vec3::splat(-64.0),
+            bounds_max: Vec3::new(64.0, 128.0, 64.0),
+            no_portal_materials: Vec::new(),
+            spawn_rule: SpawnRule::FarthestFromEnemies,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpawnRule {
+    FarthestFromEnemies,
+    Random,
+}
+
+impl SpawnRule {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "farthest" => Some(SpawnRule::FarthestFromEnemies),
+            "random" => Some(SpawnRule::Random),
+            _ => None,
+        }
+    }
+}
+
+impl MapInfo {
+    /// Loads the sidecar file for a map, falling back to the defaults if there isn't one
+    pub fn load(map: &str) -> Self {
+        let path = format!("assets/{}.map", map);
+        match fs::read_to_string(&path) {
+            Ok(source) => match Self::parse(&source) {
+                Ok(map_info) => map_info,
+                Err(e) => {
+                    error!("{}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => {
+                warn!("No map info at {}, using defaults", path);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut map_info = Self {
+            spawn_points: Vec::new(),
+            ..default()
+        };
+
+        for (number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap();
+            if key == "spawn_rule" {
+                let rule = match words.collect::<Vec<&str>>().as_slice() {
+                    &[name] => SpawnRule::parse(name),
+                    _ => None,
+                };
+                map_info.spawn_rule = rule
+                    .ok_or_else(|| format!("line {}: can't understand '{}'", number + 1, line))?;
+                continue;
+            }
+            let values = words
+                .map(|word| word.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+
+            match (key, values.as_slice()) {
+                ("spawn", &[x, y, z]) => map_info.spawn_points.push(Vec3::new(x, y, z)),
+                ("kill_plane", &[y]) => map_info.kill_plane = y,
+                ("bounds", &[x1, y1, z1, x2, y2, z2]) => {
+                    map_info.bounds_min = Vec3::new(x1, y1, z1).min(Vec3::new(x2, y2, z2));
+                    map_info.bounds_max = Vec3::new(x1, y1, z1).max(Vec3::new(x2, y2, z2));
+                }
+                ("no_portal", materials) if !materials.is_empty() => map_info
+                    .no_portal_materials
+                    .extend(materials.iter().map(|&material| material as u8)),
+                _ => return Err(format!("line {}: can't understand '{}'", number + 1, line)),
+            }
+        }
+
+        if map_info.spawn_points.is_empty() {
+            map_info.spawn_points.push(SPAWN_POSITION);
+        }
+
+        Ok(map_info)
+    }
+
+    /// Whether a living player at this position should be respawned
+    pub fn is_out_of_world(&self, position: Vec3) -> bool {
+        position.y < self.kill_plane
+            || position.cmplt(self.bounds_min).any()
+            || position.cmpgt(self.bounds_max).any()
+    }
+
+    /// Whether a position is too far outside the world to have been reached legitimately,
+    /// `margin` being how far past the bounds a player can get before being respawned
+    pub fn is_invalid_position(&self, position: Vec3, margin: f32) -> bool {
+        !position.is_finite()
+            || position.cmplt(self.bounds_min - margin).any()
+            || position.cmpgt(self.bounds_max + margin).any()
+    }
+
+    /// Picks where to spawn a player given the positions of the players it should avoid
+    pub fn pick_spawn_point(&self, enemies: &[Vec3]) -> Vec3 {
+        match self.spawn_rule {
+            SpawnRule::FarthestFromEnemies if !enemies.is_empty() => *self
+                .spawn_points
+                .iter()
+                .max_by(|a, b| {
+                    let distance = |point: &Vec3| {
+                        enemies
+                            .iter()
+                            .map(|enemy| enemy.distance_squared(*point))
+                            .fold(f32::INFINITY, f32::min)
+                    };
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap(),
+            _ => *self.spawn_points.choose(&mut rand::thread_rng()).unwrap(),
+        }
+    }
+}
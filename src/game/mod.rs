@@ -1,8 +1,19 @@
 use self::{
     character::{CharacterEntity, CharacterPlugin},
-    client::{ClientPlugin, LocalNetworkedEntity},
+    chat::{ChatPlugin, ChatState},
+    client::{ClientPlugin, ClientResource, LocalNetworkedEntity},
+    events::{GameEvent, GameEventsPlugin},
+    game_mode::GameModePlugin,
+    health::{Health, HealthPlugin, PLAYER_HALF_SIZE},
+    link_conditioner::LinkConditionerPlugin,
+    networking::ClientMessages,
     networking::NetworkedEntityType,
+    portals::{hidden_portal_transform, spawn_portal, PortalsPlugin},
+    props::PropShape,
+    scoreboard::ScoreboardPlugin,
     server::ServerPlugin,
+    session::SessionPlugin,
+    teams::{portal_material, TeamsPlugin},
     ui::UiPlugin,
 };
 use crate::{despawn_screen, GameState};
@@ -12,12 +23,40 @@ use bevy::{
 };
 use bevy_obj::ObjPlugin;
 use bevy_voxel_engine::*;
+use renet::DefaultChannel;
 
+pub mod batching;
 mod character;
+pub mod chat;
 pub mod client;
+pub mod commands;
+pub mod events;
+pub mod game_mode;
+pub mod health;
+pub mod interest;
+pub mod interpolation;
+pub mod lag_compensation;
+pub mod link_conditioner;
+pub mod map;
+pub mod moderation;
+pub mod network_stats;
 pub mod networking;
+pub mod portals;
+pub mod props;
+pub mod replication;
+pub mod scoreboard;
 pub mod server;
+pub mod session;
+pub mod snapshot;
+pub mod teams;
+pub mod transport;
+pub mod traversal;
 mod ui;
+pub mod voxel_world;
+
+// name of the .vox file in assets that is loaded when joining
+pub const DEFAULT_MAP: &str = "monu9";
+pub const SPAWN_POSITION: Vec3 = Vec3::new(5.0, 5.0, -5.0);
 
 #[derive(Component)]
 struct InGame;
@@ -29,66 +68,102 @@ impl Plugin for GamePlugin {
         app.add_plugin(BevyVoxelEnginePlugin)
             .add_plugin(CharacterPlugin)
             .add_plugin(UiPlugin)
+            .add_plugin(ChatPlugin)
+            .add_plugin(HealthPlugin)
+            .add_plugin(GameModePlugin)
+            .add_plugin(TeamsPlugin)
+            .add_plugin(PortalsPlugin)
+            .add_plugin(ScoreboardPlugin)
+            .add_plugin(GameEventsPlugin)
             .add_plugin(ClientPlugin)
             .add_plugin(ServerPlugin)
+            .add_plugin(SessionPlugin)
+            .add_plugin(LinkConditionerPlugin)
             .add_plugin(ObjPlugin)
             .add_system_set(SystemSet::on_enter(GameState::Game).with_system(setup))
             .add_system_set(SystemSet::on_update(GameState::Game).with_system(shoot))
-            .add_system_set(SystemSet::on_update(GameState::Game).with_system(spawn_portals))
+            .add_system_set(
+                SystemSet::on_update(GameState::Game).with_system(despawn_portal_bullets),
+            )
             .add_system_set(
                 SystemSet::on_exit(GameState::Game).with_system(despawn_screen::<InGame>),
             );
     }
 }
 
+/// The client's networking without rendering or input, driving a stand-in character that
+/// only moves when something moves it, for running clients in tests
+pub struct HeadlessClientPlugin;
+
+impl Plugin for HeadlessClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChatState::default())
+            .insert_resource(LoadVoxelWorld::None)
+            .add_event::<GameEvent>()
+            .add_plugin(PortalsPlugin)
+            .add_plugin(ClientPlugin)
+            .add_system_set(SystemSet::on_enter(GameState::Game).with_system(setup_headless))
+            .add_system_set(
+                SystemSet::on_exit(GameState::Game).with_system(despawn_screen::<InGame>),
+            );
+    }
+}
+
+fn setup_headless(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let portals: Vec<Entity> = (0..2)
+        .map(|i| {
+            spawn_portal(
+                &mut commands,
+                &asset_server,
+                None,
+                i,
+                hidden_portal_transform(i),
+            )
+        })
+        .collect();
+
+    let transform = Transform::from_translation(SPAWN_POSITION).looking_at(Vec3::ZERO, Vec3::Y);
+    commands.spawn((
+        TransformBundle::from_transform(transform),
+        CharacterEntity {
+            grounded: false,
+            look_at: -transform.local_z(),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            portal1: portals[0],
+            portal2: portals[1],
+        },
+        Velocity::new(Vec3::splat(0.0)),
+        BoxCollider {
+            half_size: PLAYER_HALF_SIZE,
+        },
+        Health::default(),
+        InGame,
+    ));
+}
+
 fn setup(
     mut commands: Commands,
     mut load_voxel_world: ResMut<LoadVoxelWorld>,
     asset_server: Res<AssetServer>,
 ) {
     // voxel world
-    *load_voxel_world = LoadVoxelWorld::File("assets/monu9.vox".to_string());
-
-    // portals
-    let mut portals = vec![None; 2];
-    for i in 0..2 {
-        portals[i] = Some(
-            commands
-                .spawn((
-                    VoxelizationBundle {
-                        mesh_handle: asset_server.load("models/portal.obj"),
-                        transform: Transform::from_xyz(1.0, 100.0, 0.0)
-                            .looking_at(Vec3::ZERO, Vec3::Y)
-                            .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
-                        voxelization_material: VoxelizationMaterial {
-                            flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
-                            ..default()
-                        },
-                        ..default()
-                    },
-                    Portal,
-                    InGame,
-                    LocalNetworkedEntity {
-                        entity_type: NetworkedEntityType::Portal(i as u32),
-                    },
-                ))
-                .with_children(|parent| {
-                    // portal border
-                    parent.spawn(VoxelizationBundle {
-                        mesh_handle: asset_server.load("models/portal_frame.obj"),
-                        voxelization_material: VoxelizationMaterial {
-                            material: VoxelizationMaterialType::Material(120 + i as u8),
-                            flags: Flags::ANIMATION_FLAG | Flags::COLLISION_FLAG,
-                        },
-                        ..default()
-                    });
-                })
-                .id(),
-        );
-    }
+    *load_voxel_world = LoadVoxelWorld::File(format!("assets/{}.vox", DEFAULT_MAP));
+
+    // portals, kept out of sight until the server places them
+    let portals: Vec<Entity> = (0..2)
+        .map(|i| {
+            spawn_portal(
+                &mut commands,
+                &asset_server,
+                None,
+                i,
+                hidden_portal_transform(i),
+            )
+        })
+        .collect();
 
     // camera
-    let transform = Transform::from_xyz(5.0, 5.0, -5.0).looking_at(Vec3::ZERO, Vec3::Y);
+    let transform = Transform::from_translation(SPAWN_POSITION).looking_at(Vec3::ZERO, Vec3::Y);
     commands.spawn((
         VoxelCameraBundle {
             transform,
@@ -102,20 +177,20 @@ fn setup(
             grounded: false,
             look_at: -transform.local_z(),
             up: Vec3::new(0.0, 1.0, 0.0),
-            portal1: portals[0].unwrap(),
-            portal2: portals[1].unwrap(),
+            portal1: portals[0],
+            portal2: portals[1],
         },
         Velocity::new(Vec3::splat(0.0)),
         BoxCollider {
-            half_size: IVec3::new(2, 4, 2),
+            half_size: PLAYER_HALF_SIZE,
         },
+        Health::default(),
         BloomSettings::default(),
         Fxaa::default(),
         InGame,
     ));
 }
 
-// zero: normal bullet
 // one: orange portal bullet
 // two: blue portal bullet
 #[derive(Component)]
@@ -127,87 +202,72 @@ fn shoot(
     mut commands: Commands,
     input: Res<Input<MouseButton>>,
     keyboard: Res<Input<KeyCode>>,
-    character: Query<&Transform, With<CharacterEntity>>,
+    character: Query<(&Transform, &Health), With<CharacterEntity>>,
+    chat: Res<ChatState>,
+    mut client_resource: ResMut<ClientResource>,
 ) {
-    let character = character.single();
+    let (character, health) = character.single();
+    if chat.open || health.is_dead() {
+        return;
+    }
+
+    let team = (*client_resource)
+        .as_ref()
+        .and_then(|client| client.team(client.client_id));
+
+    // where portals end up is decided by the server, the bullets are only for show
+    for (button, index) in [(MouseButton::Left, 0), (MouseButton::Right, 1)] {
+        if !input.just_pressed(button) {
+            continue;
+        }
 
-    if input.just_pressed(MouseButton::Left) {
         commands.spawn((
             Transform::from_translation(character.translation),
-            Particle { material: 120 },
-            Velocity::new(-character.local_z() * 50.0),
-            Bullet { bullet_type: 1 },
-            LocalNetworkedEntity {
-                entity_type: NetworkedEntityType::Bullet(1),
+            Particle {
+                material: portal_material(team, index),
             },
-            InGame,
-        ));
-    }
-    if input.just_pressed(MouseButton::Right) {
-        commands.spawn((
-            Transform::from_translation(character.translation),
-            Particle { material: 121 },
             Velocity::new(-character.local_z() * 50.0),
-            Bullet { bullet_type: 2 },
+            Bullet {
+                bullet_type: index + 1,
+            },
             LocalNetworkedEntity {
-                entity_type: NetworkedEntityType::Bullet(2),
+                entity_type: NetworkedEntityType::Bullet(index + 1),
             },
             InGame,
         ));
+        if let Some(client) = (*client_resource).as_mut() {
+            client.client.send(
+                DefaultChannel::Reliable,
+                &ClientMessages::ShootPortal {
+                    index,
+                    direction: -character.local_z(),
+                },
+            );
+        }
     }
 
     if keyboard.just_pressed(KeyCode::B) {
-        commands.spawn((
-            Transform::from_translation(character.translation),
-            Velocity::new(-character.local_z() * 10.0),
-            Bullet { bullet_type: 0 },
-            BoxCollider {
-                half_size: IVec3::new(3, 3, 3),
-            },
-            Box {
-                material: 14,
-                half_size: IVec3::new(3, 3, 3),
-            },
-            InGame,
-        ));
+        if let Some(client) = (*client_resource).as_mut() {
+            client.client.send(
+                DefaultChannel::Reliable,
+                &ClientMessages::SpawnProp {
+                    shape: PropShape::Box,
+                    direction: -character.local_z(),
+                },
+            );
+        }
     }
 }
 
-fn spawn_portals(
+fn despawn_portal_bullets(
     mut commands: Commands,
-    bullet_query: Query<(&Transform, &Velocity, &Bullet, Entity)>,
-    mut character_query: Query<&mut CharacterEntity>,
-    mut portal_query: Query<&mut Transform, (With<Portal>, Without<Bullet>)>,
+    bullet_query: Query<(&Velocity, &Bullet, Entity)>,
 ) {
-    for (transform, velocity, bullet, entity) in bullet_query.iter() {
-        if bullet.bullet_type == 1 || bullet.bullet_type == 2 {
-            if velocity.hit_normal != Vec3::splat(0.0) {
-                commands.entity(entity).despawn();
-
-                let normal = velocity.hit_normal;
-
-                let plane = 1.0 - normal.abs();
-                let pos =
-                    (transform.translation * plane * VOXELS_PER_METER).floor() / VOXELS_PER_METER;
-                let pos = pos + transform.translation * normal.abs();
-
-                let character = character_query.single_mut();
-                let entity = match bullet.bullet_type {
-                    1 => character.portal1,
-                    2 => character.portal2,
-                    _ => panic!(),
-                };
-
-                let up = if normal.abs() == Vec3::Y {
-                    Vec3::Z
-                } else {
-                    Vec3::Y
-                };
-
-                let mut transform = portal_query.get_mut(entity).unwrap();
-                transform.translation = pos;
-                transform.look_at(pos + normal, up);
-            }
+    for (velocity, bullet, entity) in bullet_query.iter() {
+        if (bullet.bullet_type == 1 || bullet.bullet_type == 2)
+            && velocity.hit_normal != Vec3::splat(0.0)
+        {
+            commands.entity(entity).despawn();
         }
     }
 }
@@ -0,0 +1,233 @@
+use super::server::{Server, ServerPlayer};
+use bevy::prelude::*;
+use std::{fs, net::IpAddr, path::PathBuf};
+
+// where a dedicated or listen server keeps its bans between runs
+pub const BANS_FILE: &str = "bans.txt";
+
+pub struct ChatSettings {
+    pub max_length: usize,
+    // messages per second a player can keep sending
+    pub rate: f32,
+    // messages a player can send at once before being rate limited
+    pub burst: f32,
+    // lowercase words that get starred out
+    pub word_filter: Vec<String>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            max_length: 200,
+            rate: 0.5,
+            burst: 5.0,
+            word_filter: Vec::new(),
+        }
+    }
+}
+
+pub struct TokenBucket {
+    tokens: f32,
+    last_update: f32,
+}
+
+impl TokenBucket {
+    pub fn new(tokens: f32, time: f32) -> Self {
+        Self {
+            tokens,
+            last_update: time,
+        }
+    }
+
+    /// Refills the bucket and takes a token out of it if there is one
+    pub fn take(&mut self, time: f32, rate: f32, burst: f32) -> bool {
+        self.tokens = (self.tokens + (time - self.last_update) * rate).min(burst);
+        self.last_update = time;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A banned player, refused when they come back from the same address or with the same
+/// session token whatever name they use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub username: String,
+    // none for players connected in memory
+    pub address: Option<IpAddr>,
+    pub token: Option<u64>,
+}
+
+impl Ban {
+    /// `address token username`, with `-` for an address or token that isn't known
+    fn to_line(&self) -> String {
+        let unknown = || "-".to_string();
+        format!(
+            "{} {} {}",
+            self.address
+                .map_or_else(unknown, |address| address.to_string()),
+            self.token.map_or_else(unknown, |token| token.to_string()),
+            self.username
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let (address, token, username) = match line.splitn(3, ' ').collect::<Vec<_>>()[..] {
+            [address, token, username] => (address, token, username),
+            _ => return Err("expected an address, a token and a name".to_string()),
+        };
+        Ok(Self {
+            username: username.to_string(),
+            address: match address {
+                "-" => None,
+                address => Some(
+                    address
+                        .parse()
+                        .map_err(|_| format!("invalid address {}", address))?,
+                ),
+            },
+            token: match token {
+                "-" => None,
+                token => Some(
+                    token
+                        .parse()
+                        .map_err(|_| format!("invalid token {}", token))?,
+                ),
+            },
+        })
+    }
+}
+
+/// Players refused on connect, written back to a file on every ban when there is one
+#[derive(Default)]
+pub struct Bans {
+    bans: Vec<Ban>,
+    path: Option<PathBuf>,
+}
+
+impl Bans {
+    /// Reads the bans kept at `path`, an empty list if there is no such file yet
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let bans = match fs::read_to_string(&path) {
+            Ok(source) => source
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .enumerate()
+                .filter_map(|(number, line)| match Ban::parse(line) {
+                    Ok(ban) => Some(ban),
+                    Err(e) => {
+                        error!("{} line {}: {}", path.display(), number + 1, e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            bans,
+            path: Some(path),
+        }
+    }
+
+    pub fn add(&mut self, ban: Ban) {
+        self.bans.push(ban);
+        if let Some(path) = &self.path {
+            let source: String = self.bans.iter().map(|ban| ban.to_line() + "\n").collect();
+            if let Err(e) = fs::write(path, source) {
+                error!("Couldn't save bans to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// The ban a connecting client falls under, if any
+    pub fn find(&self, address: Option<IpAddr>, token: Option<u64>) -> Option<&Ban> {
+        self.bans.iter().find(|ban| {
+            (ban.address.is_some() && ban.address == address)
+                || (ban.token.is_some() && ban.token == token)
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Ban> {
+        self.bans.iter()
+    }
+}
+
+impl Server {
+    /// Checks a chat message against the chat settings, returning the message to broadcast
+    /// or the reason it was refused
+    pub fn moderate_chat(
+        &mut self,
+        client_id: u64,
+        message: &str,
+        time: f32,
+    ) -> Result<String, String> {
+        let settings = &self.chat_settings;
+        let player = self.players.get_mut(&client_id).unwrap();
+
+        if player.muted {
+            return Err("You are muted".to_string());
+        }
+
+        let message = message.trim();
+        if message.is_empty() {
+            return Err("Message is empty".to_string());
+        }
+        check_limits(player, settings, message, time)?;
+
+        Ok(filter_words(message, &settings.word_filter))
+    }
+
+    /// Holds commands to the same length and rate limits as chat, muted players can still
+    /// run them
+    pub fn moderate_command(
+        &mut self,
+        client_id: u64,
+        command: &str,
+        time: f32,
+    ) -> Result<(), String> {
+        let player = self.players.get_mut(&client_id).unwrap();
+        check_limits(player, &self.chat_settings, command, time)
+    }
+}
+
+fn check_limits(
+    player: &mut ServerPlayer,
+    settings: &ChatSettings,
+    message: &str,
+    time: f32,
+) -> Result<(), String> {
+    if message.chars().count() > settings.max_length {
+        return Err(format!(
+            "Message is longer than {} characters",
+            settings.max_length
+        ));
+    }
+
+    if !player.chat_limit.take(time, settings.rate, settings.burst) {
+        return Err("You are sending messages too quickly".to_string());
+    }
+    Ok(())
+}
+
+/// Replaces every word in the filter with asterisks, ignoring case
+pub fn filter_words(message: &str, word_filter: &[String]) -> String {
+    message
+        .split(' ')
+        .map(|word| {
+            let stripped = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if word_filter.contains(&stripped) {
+                "*".repeat(word.chars().count())
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
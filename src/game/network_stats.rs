@@ -0,0 +1,303 @@
+use super::{
+    link_conditioner::LinkConditioner,
+    networking::{ClientMessages, ServerMessages},
+    transport::{ClientTransport, ServerTransport},
+};
+use bevy::utils::HashMap;
+use renet::{DefaultChannel, NetworkInfo, ServerEvent};
+use std::{collections::VecDeque, error::Error, net::SocketAddr, time::Duration};
+
+// seconds between samples, the message counters are per sample
+const SAMPLE_INTERVAL: f32 = 1.0;
+// samples kept for the graphs
+const MAX_SAMPLES: usize = 60;
+
+/// The state of a connection at one point in time
+#[derive(Clone, Copy, Default)]
+pub struct NetworkSample {
+    pub time: f32,
+    // milliseconds
+    pub rtt: f32,
+    pub packet_loss: f32,
+    pub sent_kbps: f32,
+    pub received_kbps: f32,
+    pub messages_sent: u32,
+    pub messages_received: u32,
+    pub queued_messages: usize,
+}
+
+/// Message counts and a rolling history of a connection
+#[derive(Default)]
+pub struct NetworkStats {
+    time: f32,
+    next_sample: f32,
+    sent: HashMap<&'static str, u32>,
+    received: HashMap<&'static str, u32>,
+    // messages of each type over the last sample, most frequent first
+    pub sent_per_second: Vec<(&'static str, u32)>,
+    pub received_per_second: Vec<(&'static str, u32)>,
+    pub samples: VecDeque<NetworkSample>,
+}
+
+impl NetworkStats {
+    pub fn latest(&self) -> Option<&NetworkSample> {
+        self.samples.back()
+    }
+
+    fn record_sent(&mut self, kind: &'static str) {
+        *self.sent.entry(kind).or_default() += 1;
+    }
+
+    fn record_received(&mut self, kind: &'static str) {
+        *self.received.entry(kind).or_default() += 1;
+    }
+
+    fn update(&mut self, duration: Duration, info: NetworkInfo, queued_messages: usize) {
+        self.time += duration.as_secs_f32();
+        if self.time < self.next_sample {
+            return;
+        }
+        self.next_sample = self.time + SAMPLE_INTERVAL;
+
+        let per_second = |counts: &mut HashMap<&'static str, u32>| {
+            let mut rates: Vec<(&'static str, u32)> = counts.drain().collect();
+            rates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            rates
+        };
+        self.sent_per_second = per_second(&mut self.sent);
+        self.received_per_second = per_second(&mut self.received);
+
+        self.samples.push_back(NetworkSample {
+            time: self.time,
+            rtt: info.rtt,
+            packet_loss: info.packet_loss,
+            sent_kbps: info.sent_kbps,
+            received_kbps: info.received_kbps,
+            messages_sent: self.sent_per_second.iter().map(|(_, count)| count).sum(),
+            messages_received: self
+                .received_per_second
+                .iter()
+                .map(|(_, count)| count)
+                .sum(),
+            queued_messages,
+        });
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Counts the messages a client sends and receives by type, only typed messages are counted
+pub struct StatsClientTransport {
+    inner: Box<dyn ClientTransport>,
+    stats: NetworkStats,
+}
+
+impl StatsClientTransport {
+    pub fn new(inner: Box<dyn ClientTransport>) -> Self {
+        Self {
+            inner,
+            stats: NetworkStats::default(),
+        }
+    }
+}
+
+impl ClientTransport for StatsClientTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.update(duration)?;
+        self.stats.update(
+            duration,
+            self.inner.network_info(),
+            self.inner.queued_messages(),
+        );
+        Ok(())
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        self.inner.send_message(channel, message);
+    }
+
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.inner.receive_message(channel)
+    }
+
+    fn send(&mut self, channel: DefaultChannel, message: &ClientMessages) {
+        self.stats.record_sent(message.kind());
+        self.inner.send(channel, message);
+    }
+
+    fn receive(&mut self, channel: DefaultChannel) -> Option<ServerMessages> {
+        let message = self.inner.receive(channel)?;
+        self.stats.record_received(message.kind());
+        Some(message)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn disconnect(&mut self) {
+        self.inner.disconnect();
+    }
+
+    fn network_info(&self) -> NetworkInfo {
+        self.inner.network_info()
+    }
+
+    fn queued_messages(&self) -> usize {
+        self.inner.queued_messages()
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        self.inner.conditioner()
+    }
+
+    fn stats(&self) -> Option<&NetworkStats> {
+        Some(&self.stats)
+    }
+
+    fn reconnect(&mut self, transport: Box<dyn ClientTransport>) {
+        self.inner.reconnect(transport);
+    }
+}
+
+/// Counts the messages the server sends to and receives from each client by type, only
+/// typed messages are counted
+pub struct StatsServerTransport {
+    inner: Box<dyn ServerTransport>,
+    stats: HashMap<u64, NetworkStats>,
+}
+
+impl StatsServerTransport {
+    pub fn new(inner: Box<dyn ServerTransport>) -> Self {
+        Self {
+            inner,
+            stats: HashMap::default(),
+        }
+    }
+}
+
+impl ServerTransport for StatsServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.update(duration)?;
+
+        let clients = self.inner.clients_id();
+        self.stats
+            .retain(|client_id, _| clients.contains(client_id));
+        for client_id in clients {
+            let info = self.inner.network_info(client_id).unwrap_or_default();
+            let queued_messages = self.inner.queued_messages(client_id);
+            self.stats
+                .entry(client_id)
+                .or_default()
+                .update(duration, info, queued_messages);
+        }
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.inner.get_event()
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.send_packets()
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        self.inner.send_message(client_id, channel, message);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        self.inner.broadcast_message(channel, message);
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        self.inner
+            .broadcast_message_except(except_id, channel, message);
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.inner.receive_message(client_id, channel)
+    }
+
+    fn send(&mut self, client_id: u64, channel: DefaultChannel, message: &ServerMessages) {
+        self.stats
+            .entry(client_id)
+            .or_default()
+            .record_sent(message.kind());
+        self.inner.send(client_id, channel, message);
+    }
+
+    fn broadcast(&mut self, channel: DefaultChannel, message: &ServerMessages) {
+        for client_id in self.inner.clients_id() {
+            self.stats
+                .entry(client_id)
+                .or_default()
+                .record_sent(message.kind());
+        }
+        self.inner.broadcast(channel, message);
+    }
+
+    fn broadcast_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: &ServerMessages,
+    ) {
+        for client_id in self.inner.clients_id() {
+            if client_id != except_id {
+                self.stats
+                    .entry(client_id)
+                    .or_default()
+                    .record_sent(message.kind());
+            }
+        }
+        self.inner.broadcast_except(except_id, channel, message);
+    }
+
+    fn receive(&mut self, client_id: u64, channel: DefaultChannel) -> Option<ClientMessages> {
+        let message = self.inner.receive(client_id, channel)?;
+        self.stats
+            .entry(client_id)
+            .or_default()
+            .record_received(message.kind());
+        Some(message)
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        self.inner.clients_id()
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        self.inner.disconnect(client_id);
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        self.inner.network_info(client_id)
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        self.inner.client_addr(client_id)
+    }
+
+    fn queued_messages(&self, client_id: u64) -> usize {
+        self.inner.queued_messages(client_id)
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        self.inner.conditioner()
+    }
+
+    fn stats(&self, client_id: u64) -> Option<&NetworkStats> {
+        self.stats.get(&client_id)
+    }
+}
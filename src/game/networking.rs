@@ -1,6 +1,13 @@
+use super::{
+    events::GameEvent, game_mode::GameModeState, portals::PortalPlacement, props::PropShape,
+    scoreboard::PlayerStats, snapshot::WorldSnapshot, voxel_world::VoxelEdit,
+};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// Client id used for networked entities the server owns, like props
+pub const SERVER_ID: u64 = 0;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessages {
     ClientConnected {
@@ -14,6 +21,16 @@ pub enum ServerMessages {
         client_id: u64,
         message: String,
     },
+    // chat line from the server itself, e.g. command output
+    SystemMessage {
+        message: String,
+    },
+    ChangeMap {
+        map: String,
+    },
+    VoxelsEdited {
+        edits: Vec<VoxelEdit>,
+    },
     UpdatePlayer {
         client_id: u64,
         position: Vec3,
@@ -34,6 +51,67 @@ pub enum ServerMessages {
         client_id: u64,
         entity: Entity,
     },
+    // tells the owner of an entity to despawn it, e.g. when a bullet hits a player
+    RemoveLocalEntity {
+        entity: Entity,
+    },
+    PlayerDamaged {
+        client_id: u64,
+        attacker_id: u64,
+        health: f32,
+    },
+    PlayerKilled {
+        client_id: u64,
+        attacker_id: u64,
+    },
+    PlayerRespawned {
+        client_id: u64,
+        position: Vec3,
+        health: f32,
+        // seconds of spawn protection
+        protection: f32,
+        // how many times the server has moved the player, echoed back in their updates
+        respawn: u32,
+    },
+    GameModeState(GameModeState),
+    // sent once to a player that just joined
+    WorldSnapshot(WorldSnapshot),
+    // what a player that joined can reconnect with if they lose their connection
+    Session {
+        token: u64,
+        // seconds the server keeps the player after they disconnect
+        grace: f32,
+    },
+    // sent before the server disconnects a client on purpose, which shouldn't reconnect
+    Disconnected {
+        reason: String,
+    },
+    PlayerStats {
+        stats: Vec<PlayerStats>,
+    },
+    GameEvent(GameEvent),
+    PortalTraversal {
+        client_id: u64,
+        // none for the player itself
+        entity: Option<Entity>,
+        from: PortalId,
+        to: PortalId,
+    },
+    PortalPlaced {
+        client_id: u64,
+        index: u32,
+        placement: PortalPlacement,
+    },
+    PortalRemoved {
+        client_id: u64,
+        index: u32,
+    },
+    PropOwner {
+        entity: Entity,
+        // none when the server simulates the prop
+        owner: Option<u64>,
+        transform: NetworkTransform,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -41,9 +119,14 @@ pub enum ClientMessages {
     ChatMessage {
         message: String,
     },
+    SwitchTeam {
+        team: u8,
+    },
     UpdatePlayer {
         position: Vec3,
         velocity: Vec3,
+        // latest respawn the update was made after
+        respawn: u32,
     },
     SpawnNetworkedEntity {
         entity: Entity,
@@ -57,12 +140,121 @@ pub enum ClientMessages {
     DespawnNetworkedEntity {
         entity: Entity,
     },
+    PortalTraversal {
+        // none for the player itself
+        entity: Option<Entity>,
+        from: PortalId,
+        to: PortalId,
+    },
+    ShootPortal {
+        index: u32,
+        direction: Vec3,
+    },
+    SpawnProp {
+        shape: PropShape,
+        direction: Vec3,
+    },
+    UpdateProp {
+        entity: Entity,
+        transform: NetworkTransform,
+    },
+}
+
+impl ServerMessages {
+    /// Name of the message type, for network stats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessages::ClientConnected { .. } => "ClientConnected",
+            ServerMessages::ClientDisconnected { .. } => "ClientDisconnected",
+            ServerMessages::ChatMessage { .. } => "ChatMessage",
+            ServerMessages::SystemMessage { .. } => "SystemMessage",
+            ServerMessages::ChangeMap { .. } => "ChangeMap",
+            ServerMessages::VoxelsEdited { .. } => "VoxelsEdited",
+            ServerMessages::UpdatePlayer { .. } => "UpdatePlayer",
+            ServerMessages::SpawnNetworkedEntity { .. } => "SpawnNetworkedEntity",
+            ServerMessages::UpdateNetworkedEntity { .. } => "UpdateNetworkedEntity",
+            ServerMessages::DespawnNetworkedEntity { .. } => "DespawnNetworkedEntity",
+            ServerMessages::RemoveLocalEntity { .. } => "RemoveLocalEntity",
+            ServerMessages::PlayerDamaged { .. } => "PlayerDamaged",
+            ServerMessages::PlayerKilled { .. } => "PlayerKilled",
+            ServerMessages::PlayerRespawned { .. } => "PlayerRespawned",
+            ServerMessages::GameModeState(_) => "GameModeState",
+            ServerMessages::WorldSnapshot(_) => "WorldSnapshot",
+            ServerMessages::Session { .. } => "Session",
+            ServerMessages::Disconnected { .. } => "Disconnected",
+            ServerMessages::PlayerStats { .. } => "PlayerStats",
+            ServerMessages::GameEvent(_) => "GameEvent",
+            ServerMessages::PortalTraversal { .. } => "PortalTraversal",
+            ServerMessages::PortalPlaced { .. } => "PortalPlaced",
+            ServerMessages::PortalRemoved { .. } => "PortalRemoved",
+            ServerMessages::PropOwner { .. } => "PropOwner",
+        }
+    }
+
+    /// What a state update describes, for dropping updates a newer one replaces
+    pub fn update_key(&self) -> Option<UpdateKey> {
+        match self {
+            ServerMessages::UpdatePlayer { client_id, .. } => {
+                Some(UpdateKey::Player(Some(*client_id)))
+            }
+            ServerMessages::UpdateNetworkedEntity {
+                client_id, entity, ..
+            } => Some(UpdateKey::Entity(Some(*client_id), *entity)),
+            _ => None,
+        }
+    }
+}
+
+impl ClientMessages {
+    /// Name of the message type, for network stats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessages::ChatMessage { .. } => "ChatMessage",
+            ClientMessages::SwitchTeam { .. } => "SwitchTeam",
+            ClientMessages::UpdatePlayer { .. } => "UpdatePlayer",
+            ClientMessages::SpawnNetworkedEntity { .. } => "SpawnNetworkedEntity",
+            ClientMessages::UpdateNetworkedEntity { .. } => "UpdateNetworkedEntity",
+            ClientMessages::DespawnNetworkedEntity { .. } => "DespawnNetworkedEntity",
+            ClientMessages::PortalTraversal { .. } => "PortalTraversal",
+            ClientMessages::ShootPortal { .. } => "ShootPortal",
+            ClientMessages::SpawnProp { .. } => "SpawnProp",
+            ClientMessages::UpdateProp { .. } => "UpdateProp",
+        }
+    }
+
+    /// What a state update describes, for dropping updates a newer one replaces
+    pub fn update_key(&self) -> Option<UpdateKey> {
+        match self {
+            ClientMessages::UpdatePlayer { .. } => Some(UpdateKey::Player(None)),
+            ClientMessages::UpdateNetworkedEntity { entity, .. } => {
+                Some(UpdateKey::Entity(None, *entity))
+            }
+            ClientMessages::UpdateProp { entity, .. } => {
+                Some(UpdateKey::Entity(Some(SERVER_ID), *entity))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The player or entity a state update is for, the owner being none for the sender's own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UpdateKey {
+    Player(Option<u64>),
+    Entity(Option<u64>, Entity),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum NetworkedEntityType {
     Bullet(u32),
-    Portal(u32),
+    Prop(PropShape),
+}
+
+/// A portal identified by the player that placed it and which of their two portals it is
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortalId {
+    pub owner: u64,
+    pub index: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
@@ -92,4 +284,4 @@ impl From<&NetworkTransform> for Transform {
             scale: network_transform.scale,
         }
     }
-}
\ No newline at end of file
+}
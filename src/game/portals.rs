@@ -0,0 +1,300 @@
+use super::{
+    client::{Client, ClientResource},
+    game_mode::{Combatant, GameRules},
+    networking::{PortalId, ServerMessages},
+    server::Server,
+    teams::{portal_material, PortalFrame, PortalOwner},
+    InGame,
+};
+use crate::GameState;
+use bevy::{prelude::*, utils::HashSet};
+use bevy_voxel_engine::*;
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+
+// furthest away a portal can be shot, in meters
+const MAX_PORTAL_DISTANCE: f32 = 100.0;
+// half the width and height of the flat area a portal needs, matching models/portal.obj
+const PORTAL_HALF_SIZE: Vec2 = Vec2::new(1.0, 1.5);
+// where portals that haven't been placed yet are kept
+pub const HIDDEN_PORTAL_POSITION: Vec3 = Vec3::new(1.0, 100.0, 0.0);
+
+pub struct PortalsPlugin;
+
+impl Plugin for PortalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(link_portals));
+    }
+}
+
+/// Who can go through a player's portals
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PortalAccess {
+    // only the player that placed them
+    Private,
+    // the owner and their teammates
+    Team,
+    Global,
+}
+
+impl PortalAccess {
+    pub const ALL: [PortalAccess; 3] = [
+        PortalAccess::Private,
+        PortalAccess::Team,
+        PortalAccess::Global,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PortalAccess::Private => "Private",
+            PortalAccess::Team => "Team",
+            PortalAccess::Global => "Global",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "private" => Some(PortalAccess::Private),
+            "team" => Some(PortalAccess::Team),
+            "global" => Some(PortalAccess::Global),
+            _ => None,
+        }
+    }
+
+    /// Whether `user` can go through a portal placed by `owner`, teams being none outside
+    /// of team modes
+    pub fn allows(&self, user: Combatant, owner: Combatant) -> bool {
+        match self {
+            PortalAccess::Private => user.0 == owner.0,
+            // without teams everyone is on their own
+            PortalAccess::Team => user.0 == owner.0 || (user.1.is_some() && user.1 == owner.1),
+            PortalAccess::Global => true,
+        }
+    }
+}
+
+impl PortalId {
+    /// The portal this one leads to, each player's two portals are a pair
+    pub fn partner(&self) -> PortalId {
+        PortalId {
+            owner: self.owner,
+            index: 1 - self.index % 2,
+        }
+    }
+}
+
+impl Server {
+    /// Whether a player is allowed to go from one portal to another
+    pub fn can_use_portal(&self, client_id: u64, from: PortalId, to: PortalId) -> bool {
+        let team = |client_id: u64| self.players.get(&client_id).and_then(|player| player.team);
+        to == from.partner()
+            && self
+                .rules
+                .portal_access
+                .allows((client_id, team(client_id)), (from.owner, team(from.owner)))
+    }
+}
+
+impl Client {
+    pub fn rules(&self) -> GameRules {
+        self.game_mode
+            .as_ref()
+            .map(|(state, _)| state.rules)
+            .unwrap_or_default()
+    }
+
+    /// Whether the local player can go through a portal with this id
+    pub fn can_use_portal(&self, id: PortalId) -> bool {
+        self.rules().portal_access.allows(
+            (self.client_id, self.team(self.client_id)),
+            (id.owner, self.team(id.owner)),
+        )
+    }
+}
+
+/// Only leaves the `Portal` component on portals whose partner exists and that the local
+/// player is allowed through, the engine links the ones that have it by itself. Every
+/// client ends up with the same links for the same rules
+fn link_portals(
+    mut commands: Commands,
+    client_resource: Res<ClientResource>,
+    portals: Query<(Entity, &PortalOwner, Option<&Portal>)>,
+) {
+    let client = match (*client_resource).as_ref() {
+        Some(client) => client,
+        None => return,
+    };
+
+    let id = |owner: &PortalOwner| PortalId {
+        owner: owner.client_id.unwrap_or(client.client_id),
+        index: owner.index,
+    };
+    let placed: HashSet<PortalId> = portals.iter().map(|(_, owner, _)| id(owner)).collect();
+
+    for (entity, owner, portal) in portals.iter() {
+        let portal_id = id(owner);
+        let usable = placed.contains(&portal_id.partner()) && client.can_use_portal(portal_id);
+        match (usable, portal.is_some()) {
+            (true, false) => {
+                commands.entity(entity).insert(Portal);
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<Portal>();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where the server put a portal, `position` being on the surface it was shot at
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PortalPlacement {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+impl PortalPlacement {
+    fn up(&self) -> Vec3 {
+        if self.normal.abs() == Vec3::Y {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        }
+    }
+
+    pub fn transform(&self, index: u32) -> Transform {
+        Transform::from_translation(self.position)
+            .looking_at(self.position + self.normal, self.up())
+            .with_scale(portal_scale(index))
+    }
+}
+
+// the second portal is mirrored so the two line up when looking through them
+fn portal_scale(index: u32) -> Vec3 {
+    let sign = index as f32 * 2.0 - 1.0;
+    Vec3::new(sign, 1.0, sign)
+}
+
+/// Spawns a portal with its frame, `owner` being none for the local player's portals
+pub fn spawn_portal(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    owner: Option<u64>,
+    index: u32,
+    transform: Transform,
+) -> Entity {
+    commands
+        .spawn((
+            VoxelizationBundle {
+                mesh_handle: asset_server.load("models/portal.obj"),
+                transform,
+                voxelization_material: VoxelizationMaterial {
+                    flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
+                    ..default()
+                },
+                ..default()
+            },
+            Portal,
+            PortalOwner {
+                client_id: owner,
+                index,
+            },
+            InGame,
+        ))
+        .with_children(|parent| {
+            // portal border, recoloured once the owner's team is known
+            parent.spawn((
+                VoxelizationBundle {
+                    mesh_handle: asset_server.load("models/portal_frame.obj"),
+                    voxelization_material: VoxelizationMaterial {
+                        material: VoxelizationMaterialType::Material(portal_material(None, index)),
+                        flags: Flags::ANIMATION_FLAG | Flags::COLLISION_FLAG,
+                    },
+                    ..default()
+                },
+                PortalFrame,
+            ));
+        })
+        .id()
+}
+
+/// Transform of a portal that hasn't been placed yet
+pub fn hidden_portal_transform(index: u32) -> Transform {
+    Transform::from_translation(HIDDEN_PORTAL_POSITION)
+        .looking_at(Vec3::ZERO, Vec3::Y)
+        .with_scale(portal_scale(index))
+}
+
+impl Server {
+    /// Shoots a portal from a player's position against the server's copy of the world,
+    /// returning why it couldn't be placed if the surface isn't suitable
+    pub fn place_portal(
+        &self,
+        client_id: u64,
+        index: u32,
+        direction: Vec3,
+    ) -> Result<PortalPlacement, String> {
+        let player = &self.players[&client_id];
+        if player.health <= 0.0 || index > 1 {
+            return Err("You can't place a portal right now".to_string());
+        }
+
+        let hit = self
+            .world
+            .raycast(player.position, direction, MAX_PORTAL_DISTANCE)
+            .ok_or("There's nothing to put a portal on")?;
+        let normal = hit.normal.as_vec3();
+
+        // snap to the voxel grid along the surface
+        let plane = 1.0 - normal.abs();
+        let position = (hit.position * plane * VOXELS_PER_METER).floor() / VOXELS_PER_METER
+            + hit.position * normal.abs();
+        let placement = PortalPlacement { position, normal };
+
+        // every voxel under the portal has to be solid and allowed with air in front of it
+        let up = placement.up();
+        let right = normal.cross(up);
+        let steps = (PORTAL_HALF_SIZE * VOXELS_PER_METER).as_ivec2();
+        let half_voxel = 0.5 / VOXELS_PER_METER;
+        for x in -steps.x..steps.x {
+            for y in -steps.y..steps.y {
+                let point = position
+                    + right * (x as f32 + 0.5) / VOXELS_PER_METER
+                    + up * (y as f32 + 0.5) / VOXELS_PER_METER;
+                let surface = self
+                    .world
+                    .get(self.world.voxel_at(point - normal * half_voxel));
+                let front = self
+                    .world
+                    .get(self.world.voxel_at(point + normal * half_voxel));
+                if surface == 0 || front != 0 {
+                    return Err("That surface isn't big or flat enough for a portal".to_string());
+                }
+                if self.map_info.no_portal_materials.contains(&surface) {
+                    return Err("Portals can't be placed on that surface".to_string());
+                }
+            }
+        }
+
+        Ok(placement)
+    }
+
+    /// Removes a player's portals everywhere, returning what they were
+    pub fn remove_portals(&mut self, client_id: u64) -> Vec<(PortalId, PortalPlacement)> {
+        let mut removed = Vec::new();
+        for index in 0..2 {
+            let id = PortalId {
+                owner: client_id,
+                index,
+            };
+            if let Some(placement) = self.portals.remove(&id) {
+                self.server.broadcast(
+                    DefaultChannel::Reliable,
+                    &ServerMessages::PortalRemoved { client_id, index },
+                );
+                removed.push((id, placement));
+            }
+        }
+        removed
+    }
+}
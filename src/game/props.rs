@@ -0,0 +1,326 @@
+use super::{
+    client::ClientResource,
+    health::player_half_extents,
+    networking::{ClientMessages, NetworkTransform, ServerMessages, SERVER_ID},
+    replication::Replicated,
+    server::{Server, ServerResource},
+};
+use bevy::prelude::*;
+use bevy_voxel_engine::{Velocity, VOXELS_PER_METER};
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+
+const GRAVITY: f32 = 9.8;
+// fraction of horizontal speed kept per second while resting on the ground
+const GROUND_FRICTION: f32 = 0.05;
+// how much further than touching a player can be and still take over a prop
+const INTERACTION_MARGIN: f32 = 1.0;
+// seconds a prop stays with a player after they stop touching it
+const OWNERSHIP_TIMEOUT: f32 = 1.0;
+const MAX_PROPS: usize = 64;
+// props a player can keep throwing per second, and how many at once
+const PROP_RATE: f32 = 1.0;
+pub const PROP_BURST: f32 = 3.0;
+// gap left between a thrown prop and the player throwing it
+const THROW_CLEARANCE: f32 = 0.1;
+// speed a thrown prop leaves the player at
+const THROW_SPEED: f32 = 10.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PropShape {
+    Box,
+}
+
+impl PropShape {
+    /// Half size in voxels
+    pub fn half_size(&self) -> IVec3 {
+        match self {
+            PropShape::Box => IVec3::new(3, 3, 3),
+        }
+    }
+
+    pub fn material(&self) -> u8 {
+        match self {
+            PropShape::Box => 14,
+        }
+    }
+
+    fn half_extents(&self) -> Vec3 {
+        self.half_size().as_vec3() / VOXELS_PER_METER
+    }
+}
+
+/// A physics object the server spawned, `entity` being its id on the server
+#[derive(Component)]
+pub struct NetworkedProp {
+    pub entity: Entity,
+}
+
+/// Marks a prop the local player owns, so it is simulated here and sent to the server
+#[derive(Component)]
+pub struct OwnedProp;
+
+/// Server side state of a prop
+pub struct Prop {
+    pub shape: PropShape,
+    pub transform: NetworkTransform,
+    // player simulating the prop, none when the server does
+    pub owner: Option<u64>,
+    // server time the owner was last touching it
+    owner_touched_at: f32,
+    spawned_at: f32,
+}
+
+impl Server {
+    /// Throws a new prop out of a player, who owns it to begin with
+    pub fn spawn_prop(&mut self, client_id: u64, shape: PropShape, direction: Vec3, time: f32) {
+        let player = self.players.get_mut(&client_id).unwrap();
+        if player.health <= 0.0 || !player.prop_limit.take(time, PROP_RATE, PROP_BURST) {
+            return;
+        }
+        let direction = direction.normalize_or_zero();
+        // start just outside the thrower so the two don't push each other apart
+        let out = if direction == Vec3::ZERO {
+            Vec3::Y
+        } else {
+            direction
+        };
+        let extents = player_half_extents() + shape.half_extents();
+        let clear = (0..3)
+            .filter(|&axis| out[axis] != 0.0)
+            .map(|axis| extents[axis] / out[axis].abs())
+            .fold(f32::INFINITY, f32::min);
+        let position = player.position + out * (clear + THROW_CLEARANCE);
+
+        // the oldest prop makes room for the new one
+        if self.props.len() >= MAX_PROPS {
+            let oldest = self
+                .props
+                .iter()
+                .min_by(|(_, a), (_, b)| a.spawned_at.total_cmp(&b.spawned_at))
+                .map(|(&entity, _)| entity)
+                .unwrap();
+            self.despawn_prop(oldest);
+        }
+
+        let entity = Entity::from_raw(self.next_entity_id);
+        self.next_entity_id += 1;
+        let transform = NetworkTransform {
+            position,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            velocity: direction * THROW_SPEED,
+        };
+        // clients are sent the prop and its owner once it is relevant to them
+        self.props.insert(
+            entity,
+            Prop {
+                shape,
+                transform,
+                owner: Some(client_id),
+                owner_touched_at: time,
+                spawned_at: time,
+            },
+        );
+    }
+
+    /// Removes a prop, clients that have it despawn it on the next interest update
+    pub fn despawn_prop(&mut self, entity: Entity) {
+        self.props.remove(&entity);
+    }
+
+    pub fn set_prop_owner(&mut self, entity: Entity, owner: Option<u64>, time: f32) {
+        let prop = self.props.get_mut(&entity).unwrap();
+        prop.owner = owner;
+        prop.owner_touched_at = time;
+        let transform = prop.transform;
+        let message = ServerMessages::PropOwner {
+            entity,
+            owner,
+            transform,
+        };
+
+        // everyone else hears who owns it when it is spawned for them
+        let replicated = Replicated::Entity {
+            owner: SERVER_ID,
+            entity,
+        };
+        for client_id in self.server.clients_id() {
+            if self.is_relevant(client_id, replicated) {
+                self.server
+                    .send(client_id, DefaultChannel::Reliable, &message);
+            }
+        }
+    }
+
+    /// Takes a state update for a prop from its owner
+    pub fn update_prop(&mut self, client_id: u64, entity: Entity, transform: NetworkTransform) {
+        let prop = match self.props.get_mut(&entity) {
+            Some(prop) if prop.owner == Some(client_id) => prop,
+            // updates sent before the owner heard it lost the prop
+            _ => return,
+        };
+        if self
+            .map_info
+            .is_invalid_position(transform.position, prop.shape.half_extents().max_element())
+        {
+            return;
+        }
+        prop.transform = transform;
+
+        self.replicate(
+            Replicated::Entity {
+                owner: SERVER_ID,
+                entity,
+            },
+            Some(client_id),
+        );
+    }
+
+    /// Gives every prop a player owned back to the server
+    pub fn release_props(&mut self, client_id: u64, time: f32) {
+        let owned: Vec<Entity> = self
+            .props
+            .iter()
+            .filter(|(_, prop)| prop.owner == Some(client_id))
+            .map(|(&entity, _)| entity)
+            .collect();
+        for entity in owned {
+            self.set_prop_owner(entity, None, time);
+        }
+    }
+
+    /// Whether a box of `half_extents` at `position` overlaps any solid voxel
+    fn prop_collides(&self, position: Vec3, half_extents: Vec3) -> bool {
+        let min = self.world.voxel_at(position - half_extents);
+        let max = self
+            .world
+            .voxel_at(position + half_extents - 0.5 / VOXELS_PER_METER);
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    if self.world.get(IVec3::new(x, y, z)) != 0 {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Moves a prop nobody owns one step, returning whether it moved
+    fn simulate_prop(&mut self, entity: Entity, delta: f32) -> bool {
+        let prop = &self.props[&entity];
+        let half_extents = prop.shape.half_extents();
+        let mut transform = prop.transform;
+        let mut velocity = transform.velocity - Vec3::Y * GRAVITY * delta;
+
+        // resolve each axis on its own so props slide along walls
+        let mut grounded = false;
+        for axis in 0..3 {
+            let mut moved = transform.position;
+            moved[axis] += velocity[axis] * delta;
+            if self.prop_collides(moved, half_extents) {
+                if axis == 1 && velocity.y < 0.0 {
+                    grounded = true;
+                }
+                velocity[axis] = 0.0;
+            } else {
+                transform.position = moved;
+            }
+        }
+        if grounded {
+            let friction = GROUND_FRICTION.powf(delta);
+            velocity.x *= friction;
+            velocity.z *= friction;
+        }
+        // settle instead of creeping forever
+        if velocity.length_squared() < 0.01 {
+            velocity = Vec3::ZERO;
+        }
+        transform.velocity = velocity;
+
+        let prop = self.props.get_mut(&entity).unwrap();
+        let moved = prop.transform.position != transform.position;
+        prop.transform = transform;
+        moved
+    }
+}
+
+/// Hands props to whoever is touching them and simulates the ones nobody owns
+pub fn update_props(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let now = time.elapsed_seconds();
+        let entities: Vec<Entity> = server.props.keys().copied().collect();
+        for entity in entities {
+            let prop = &server.props[&entity];
+            let reach = prop.shape.half_extents() + player_half_extents() + INTERACTION_MARGIN;
+            let touching = |position: Vec3| {
+                (position - prop.transform.position)
+                    .abs()
+                    .cmplt(reach)
+                    .all()
+            };
+
+            let owner_touching = prop
+                .owner
+                .and_then(|owner| server.players.get(&owner))
+                .map(|player| player.health > 0.0 && touching(player.position))
+                .unwrap_or(false);
+            let nearest = server
+                .players
+                .iter()
+                .filter(|(_, player)| player.health > 0.0 && touching(player.position))
+                .min_by(|(_, a), (_, b)| {
+                    let distance = |position: Vec3| position.distance(prop.transform.position);
+                    distance(a.position).total_cmp(&distance(b.position))
+                })
+                .map(|(&client_id, _)| client_id);
+
+            if owner_touching {
+                server.props.get_mut(&entity).unwrap().owner_touched_at = now;
+            } else if let Some(nearest) = nearest {
+                // whoever pushes a prop simulates it so it responds without lag
+                server.set_prop_owner(entity, Some(nearest), now);
+            } else if prop.owner.is_some() && now - prop.owner_touched_at > OWNERSHIP_TIMEOUT {
+                server.set_prop_owner(entity, None, now);
+            }
+
+            let prop = &server.props[&entity];
+            if prop.owner.is_none() && server.simulate_prop(entity, time.delta_seconds()) {
+                server.replicate(
+                    Replicated::Entity {
+                        owner: SERVER_ID,
+                        entity,
+                    },
+                    None,
+                );
+            }
+
+            if server
+                .map_info
+                .is_out_of_world(server.props[&entity].transform.position)
+            {
+                server.despawn_prop(entity);
+            }
+        }
+    }
+}
+
+/// Sends the state of the props the local player is simulating
+pub fn send_owned_props(
+    mut client_resource: ResMut<ClientResource>,
+    props: Query<(&NetworkedProp, &Transform, &Velocity), With<OwnedProp>>,
+) {
+    if let Some(client) = (*client_resource).as_mut() {
+        for (prop, transform, velocity) in props.iter() {
+            client.client.send(
+                DefaultChannel::Reliable,
+                &ClientMessages::UpdateProp {
+                    entity: prop.entity,
+                    transform: NetworkTransform::from_transform(transform, velocity.velocity),
+                },
+            );
+        }
+    }
+}
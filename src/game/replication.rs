@@ -0,0 +1,185 @@
+use super::{
+    networking::{NetworkedEntityType, ServerMessages, SERVER_ID},
+    server::{Server, ServerResource},
+};
+use bevy::{prelude::*, utils::HashMap};
+use renet::DefaultChannel;
+
+// how much faster each kind of update gains priority while it waits
+const PLAYER_PRIORITY: f32 = 4.0;
+const PROP_PRIORITY: f32 = 2.0;
+const BULLET_PRIORITY: f32 = 1.0;
+// distance in meters at which an update gains priority half as fast
+const PRIORITY_DISTANCE: f32 = 16.0;
+// seconds of budget a quiet client can save up for a burst
+const MAX_BURST: f32 = 0.25;
+
+/// Something whose state the server sends to clients as it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Replicated {
+    Player(u64),
+    // an entity a client spawned, or a prop when the owner is the server
+    Entity { owner: u64, entity: Entity },
+}
+
+pub struct Replication {
+    pub enabled: bool,
+    // bytes per second of state updates each client is sent
+    pub budget: f32,
+    clients: HashMap<u64, ClientReplication>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            budget: 32_000.0,
+            clients: HashMap::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClientReplication {
+    // bytes that can be sent, refilled by the budget every tick
+    credit: f32,
+    // changed state the client hasn't been sent yet and its accumulated priority
+    pending: HashMap<Replicated, f32>,
+}
+
+impl Server {
+    /// Marks something as changed for every player except `except`, its latest state is
+    /// sent once it wins a place in the budget
+    pub fn replicate(&mut self, replicated: Replicated, except: Option<u64>) {
+        for &client_id in self.players.keys() {
+            if Some(client_id) != except {
+                self.replication
+                    .clients
+                    .entry(client_id)
+                    .or_default()
+                    .pending
+                    .entry(replicated)
+                    .or_insert(0.0);
+            }
+        }
+    }
+
+    /// Number of changes waiting for room in a client's budget
+    pub fn pending_updates(&self, client_id: u64) -> usize {
+        self.replication
+            .clients
+            .get(&client_id)
+            .map_or(0, |client| client.pending.len())
+    }
+
+    /// Whether a change is still waiting for room in a client's budget
+    pub fn is_pending(&self, client_id: u64, replicated: Replicated) -> bool {
+        self.replication
+            .clients
+            .get(&client_id)
+            .map_or(false, |client| client.pending.contains_key(&replicated))
+    }
+
+    /// The current state of something as sent to `client_id`, its position and how fast
+    /// it gains priority
+    fn replication_update(
+        &self,
+        client_id: u64,
+        replicated: Replicated,
+    ) -> Option<(ServerMessages, Vec3, f32)> {
+        match replicated {
+            Replicated::Player(player_id) => {
+                let player = self.players.get(&player_id)?;
+                let message = ServerMessages::UpdatePlayer {
+                    client_id: player_id,
+                    position: player.position,
+                    velocity: player.velocity,
+                };
+                Some((message, player.position, PLAYER_PRIORITY))
+            }
+            Replicated::Entity { owner, entity }
+                if owner == SERVER_ID && self.props.contains_key(&entity) =>
+            {
+                let prop = &self.props[&entity];
+                // the owner simulates the prop itself
+                if prop.owner == Some(client_id) {
+                    return None;
+                }
+                let message = ServerMessages::UpdateNetworkedEntity {
+                    client_id: SERVER_ID,
+                    entity,
+                    transform: prop.transform,
+                };
+                Some((message, prop.transform.position, PROP_PRIORITY))
+            }
+            Replicated::Entity { owner, entity } => {
+                let (entity_type, transform) = self.networked_entity(owner, entity)?;
+                let priority = match entity_type {
+                    NetworkedEntityType::Bullet(_) => BULLET_PRIORITY,
+                    NetworkedEntityType::Prop(_) => PROP_PRIORITY,
+                };
+                let message = ServerMessages::UpdateNetworkedEntity {
+                    client_id: owner,
+                    entity,
+                    transform,
+                };
+                Some((message, transform.position, priority))
+            }
+        }
+    }
+
+    /// Sends a client the most important changes that fit in its budget, the rest wait
+    /// and keep gaining priority
+    fn replicate_to(&mut self, client_id: u64, delta: f32) {
+        let viewer = match self.players.get(&client_id) {
+            Some(player) => player.position,
+            None => {
+                self.replication.clients.remove(&client_id);
+                return;
+            }
+        };
+
+        let pending = match self.replication.clients.get_mut(&client_id) {
+            Some(client) => std::mem::take(&mut client.pending),
+            None => return,
+        };
+        let mut updates: Vec<(f32, Replicated, ServerMessages)> = pending
+            .into_iter()
+            // things that were removed or aren't relevant since they changed are dropped
+            .filter(|&(replicated, _)| self.is_relevant(client_id, replicated))
+            .filter_map(|(replicated, priority)| {
+                let (message, position, rate) = self.replication_update(client_id, replicated)?;
+                let distance = self.interest_distance(viewer, position);
+                let priority = priority + rate * delta / (1.0 + distance / PRIORITY_DISTANCE);
+                Some((priority, replicated, message))
+            })
+            .collect();
+        updates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let enabled = self.replication.enabled;
+        let budget = self.replication.budget;
+        let client = self.replication.clients.get_mut(&client_id).unwrap();
+        client.credit = (client.credit + budget * delta).min(budget * MAX_BURST);
+        for (priority, replicated, message) in updates {
+            if enabled {
+                if client.credit <= 0.0 {
+                    client.pending.insert(replicated, priority);
+                    continue;
+                }
+                client.credit -= bincode::serialized_size(&message).unwrap() as f32;
+            }
+            self.server
+                .send(client_id, DefaultChannel::Reliable, &message);
+        }
+    }
+}
+
+/// Sends every client the state updates it has room for this tick
+pub fn send_replication(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let clients: Vec<u64> = server.replication.clients.keys().copied().collect();
+        for client_id in clients {
+            server.replicate_to(client_id, time.delta_seconds());
+        }
+    }
+}
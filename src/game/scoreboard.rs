@@ -0,0 +1,140 @@
+use super::{
+    chat::ChatState,
+    client::ClientResource,
+    game_mode::team_name,
+    networking::ServerMessages,
+    server::{Server, ServerResource},
+};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+
+// seconds between stats updates sent to clients
+const STATS_INTERVAL: f32 = 1.0;
+
+pub struct ScoreboardPlugin;
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(scoreboard_ui));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerStats {
+    pub client_id: u64,
+    pub kills: u32,
+    pub deaths: u32,
+    // round trip time in milliseconds as seen by the server
+    pub ping: f32,
+}
+
+impl Server {
+    pub fn player_stats(&self) -> Vec<PlayerStats> {
+        self.players
+            .iter()
+            .map(|(&client_id, player)| PlayerStats {
+                client_id,
+                kills: player.kills,
+                deaths: player.deaths,
+                ping: self
+                    .server
+                    .network_info(client_id)
+                    .map(|info| info.rtt)
+                    .unwrap_or(0.0),
+            })
+            .collect()
+    }
+}
+
+pub fn send_player_stats(
+    mut server_resource: ResMut<ServerResource>,
+    time: Res<Time>,
+    mut last_sent: Local<f32>,
+) {
+    if let Some(server) = (*server_resource).as_mut() {
+        if time.elapsed_seconds() - *last_sent < STATS_INTERVAL {
+            return;
+        }
+        *last_sent = time.elapsed_seconds();
+
+        let stats = server.player_stats();
+        server.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::PlayerStats { stats },
+        );
+    }
+}
+
+fn scoreboard_ui(
+    mut egui_context: ResMut<EguiContext>,
+    keyboard: Res<Input<KeyCode>>,
+    chat: Res<ChatState>,
+    client_resource: Res<ClientResource>,
+) {
+    if chat.open || !keyboard.pressed(KeyCode::Tab) {
+        return;
+    }
+    let client = match (*client_resource).as_ref() {
+        Some(client) => client,
+        None => return,
+    };
+
+    // the local player isn't in `players` so gets added on its own
+    let mut rows: Vec<(u64, &str)> = client
+        .players
+        .iter()
+        .map(|(&client_id, player)| (client_id, player.username.as_str()))
+        .collect();
+    rows.push((client.client_id, client.username.as_str()));
+    rows.sort_by_key(|&(client_id, _)| {
+        let kills = client
+            .stats
+            .get(&client_id)
+            .map(|stats| stats.kills)
+            .unwrap_or(0);
+        (client.team(client_id), std::cmp::Reverse(kills))
+    });
+
+    egui::Window::new("Scoreboard")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            egui::Grid::new("scoreboard_grid")
+                .striped(true)
+                .min_col_width(60.0)
+                .show(ui, |ui| {
+                    ui.strong("Player");
+                    ui.strong("Team");
+                    ui.strong("Kills");
+                    ui.strong("Deaths");
+                    ui.strong("Ping");
+                    ui.end_row();
+
+                    for (client_id, username) in rows {
+                        if client_id == client.client_id {
+                            ui.strong(username);
+                        } else {
+                            ui.label(username);
+                        }
+                        ui.label(client.team(client_id).map(team_name).unwrap_or("-"));
+                        match client.stats.get(&client_id) {
+                            Some(stats) => {
+                                ui.label(stats.kills.to_string());
+                                ui.label(stats.deaths.to_string());
+                                ui.label(format!("{:.0} ms", stats.ping));
+                            }
+                            None => {
+                                ui.label("-");
+                                ui.label("-");
+                                ui.label("-");
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+}
@@ -2,17 +2,43 @@ use crate::{
     game::networking::{ClientMessages, ServerMessages},
     GameState,
 };
-use bevy::{prelude::*, utils::HashMap};
-use renet::{
-    DefaultChannel, RenetConnectionConfig, RenetServer, ServerAuthentication, ServerConfig,
-    ServerEvent,
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
 };
+use rand::Rng;
+use renet::{DefaultChannel, ServerEvent};
 use std::{
-    net::{ToSocketAddrs, UdpSocket},
-    time::SystemTime,
+    collections::VecDeque,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
 };
 
-use super::networking::{NetworkTransform, NetworkedEntityType};
+use super::{
+    batching::BatchedServerTransport,
+    client::Client,
+    commands::redact,
+    events::{GameEvent, Weapon},
+    game_mode::{update_game_mode, GameMode, GameModeKind, GameRules, Round},
+    health::{bullet_damage, player_half_extents, segment_hits_box, MAX_HEALTH, RESPAWN_TIME},
+    interest::{update_interest, Interest},
+    lag_compensation::{
+        draw_rewound_hitboxes, record_player_history, LagCompensation, PositionHistory,
+    },
+    link_conditioner::{ConditionedServerTransport, LinkConditioner},
+    map::MapInfo,
+    moderation::{Bans, ChatSettings, TokenBucket, BANS_FILE},
+    network_stats::StatsServerTransport,
+    networking::{NetworkTransform, NetworkedEntityType, PortalId, SERVER_ID},
+    portals::PortalPlacement,
+    props::{update_props, Prop, PROP_BURST},
+    replication::{send_replication, Replicated, Replication},
+    scoreboard::send_player_stats,
+    session::{session_token, username, Sessions},
+    snapshot::ChatLine,
+    transport::{renet_server, ListenServerTransport, MemoryServerTransport, ServerTransport},
+    voxel_world::VoxelWorld,
+    DEFAULT_MAP, SPAWN_POSITION,
+};
 
 pub struct ServerPlugin;
 
@@ -31,7 +57,16 @@ impl Plugin for ServerPlugin {
             .add_system_set(
                 SystemSet::on_update(GameState::Game)
                     .with_system(process_server_events)
-                    .with_system(process_client_messages.after(process_server_events)),
+                    .with_system(process_client_messages.after(process_server_events))
+                    .with_system(update_respawns.after(process_client_messages))
+                    .with_system(update_orphans.after(process_client_messages))
+                    .with_system(update_game_mode.after(update_respawns))
+                    .with_system(record_player_history.after(update_respawns))
+                    .with_system(send_player_stats.after(update_respawns))
+                    .with_system(update_props.after(process_client_messages))
+                    .with_system(update_interest.after(update_props).after(update_orphans))
+                    .with_system(send_replication.after(update_interest))
+                    .with_system(draw_rewound_hitboxes),
             )
             .add_system_set(SystemSet::on_exit(GameState::Game).with_system(close_server));
     }
@@ -46,6 +81,7 @@ fn update(
         if let Err(e) = server.server.update(time.delta()) {
             error!("{}", e);
         }
+        server.disconnect_leaving(time.elapsed_seconds());
 
         while let Some(event) = server.server.get_event() {
             server_events.send(event);
@@ -61,6 +97,16 @@ fn send_packets(mut server_resource: ResMut<ServerResource>) {
 
 fn close_server(mut server_resource: ResMut<ServerResource>) {
     if let Some(server) = (*server_resource).as_mut() {
+        // the server is going away so this is the only chance to tell the clients why
+        server.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::Disconnected {
+                reason: "The server closed".to_string(),
+            },
+        );
+        if let Err(e) = server.server.send_packets() {
+            error!("{}", e);
+        }
         let clients = server.server.clients_id();
         for client in clients {
             server.server.disconnect(client);
@@ -70,169 +116,643 @@ fn close_server(mut server_resource: ResMut<ServerResource>) {
     }
 }
 
+// seconds a player can't be damaged for after spawning
+const SPAWN_PROTECTION_TIME: f32 = 2.0;
+// seconds a bullet the server took over from a player that left keeps flying for
+const ORPHAN_BULLET_LIFETIME: f32 = 5.0;
+// how far outside the map bounds an update can be before the player is respawned instead
+const INVALID_POSITION_MARGIN: f32 = 32.0;
+
 #[derive(Resource, Deref, DerefMut)]
 pub struct ServerResource(pub Option<Server>);
 
 pub struct Server {
-    pub server: RenetServer,
-    pub players: HashMap<u64, String>,
+    pub server: Box<dyn ServerTransport>,
+    pub players: HashMap<u64, ServerPlayer>,
     networked_entities: HashMap<u64, HashMap<Entity, NetworkedEntity>>,
+    // client ids that are made admin as soon as they connect, i.e. the host
+    pub admins: HashSet<u64>,
+    pub admin_password: Option<String>,
+    pub bans: Bans,
+    pub map: String,
+    pub chat_settings: ChatSettings,
+    // recent chat for players who join later
+    pub chat_history: VecDeque<ChatLine>,
+    pub lag_compensation: LagCompensation,
+    pub replication: Replication,
+    pub interest: Interest,
+    pub sessions: Sessions,
+    pub map_info: MapInfo,
+    pub game_mode: Box<dyn GameMode>,
+    pub round: Round,
+    pub rules: GameRules,
+    // authoritative copy of the map with its edits, used for placing portals and sight
+    pub world: VoxelWorld,
+    pub portals: HashMap<PortalId, PortalPlacement>,
+    // physics objects keyed by their id on the server
+    pub props: HashMap<Entity, Prop>,
+    // id of the next entity the server owns, props and entities it took over alike
+    pub next_entity_id: u32,
+    pub orphan_policy: OrphanPolicy,
+}
+
+/// What happens to the entities of a player that leaves
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OrphanPolicy {
+    Despawn,
+    // the server keeps them where they were last seen
+    TransferToServer,
+}
+
+impl OrphanPolicy {
+    pub const ALL: [OrphanPolicy; 2] = [OrphanPolicy::Despawn, OrphanPolicy::TransferToServer];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OrphanPolicy::Despawn => "Despawn",
+            OrphanPolicy::TransferToServer => "Transfer to server",
+        }
+    }
+}
+
+pub struct ServerPlayer {
+    pub username: String,
+    pub admin: bool,
+    pub muted: bool,
+    pub chat_limit: TokenBucket,
+    pub prop_limit: TokenBucket,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub health: f32,
+    // server time at which a dead player comes back
+    pub respawn_at: Option<f32>,
+    pub history: PositionHistory,
+    // server time until which the player can't be damaged
+    pub protected_until: f32,
+    pub team: Option<u8>,
+    pub kills: u32,
+    pub deaths: u32,
+    // times the server has moved the player to a spawn, updates from before the latest
+    // one are stale
+    pub respawns: u32,
 }
 
 struct NetworkedEntity {
     entity_type: NetworkedEntityType,
     transform: NetworkTransform,
+    // set once the owner reports it going through a portal
+    through_portal: bool,
+    // server time a bullet the server took over disappears at
+    expires_at: Option<f32>,
 }
 
 impl Server {
     pub fn new(bind_ip: String, _: String) -> Self {
         let socket = UdpSocket::bind("0.0.0.0:1234").unwrap();
         let server_addr = bind_ip.to_socket_addrs().unwrap().next().unwrap();
-        let connection_config = RenetConnectionConfig::default();
-        let server_config = ServerConfig::new(64, 0, server_addr, ServerAuthentication::Unsecure);
-
-        // let register_server = RegisterServer {
-        //     name: lobby_name,
-        //     address: server_addr,
-        //     max_clients: server_config.max_clients as u64,
-        //     private_key,
-        //     password,
-        //     current_clients: 0,
-        // };
-        let current_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-
-        info!("Server started on {}", server_addr);
+        let mut server = Self::with_socket(socket, server_addr);
+        server.bans = Bans::load(BANS_FILE);
+        server
+    }
 
+    /// Runs on an already bound socket, e.g. one on an ephemeral port, with `server_addr`
+    /// being the address clients connect to
+    pub fn with_socket(socket: UdpSocket, server_addr: SocketAddr) -> Self {
+        Self::with_transport(Box::new(renet_server(socket, server_addr)))
+    }
+
+    /// Listens on `bind_ip` for remote players, returning the host's own client which is
+    /// connected in memory
+    pub fn listen(bind_ip: String, username: String) -> Result<(Self, Client), String> {
+        let local = MemoryServerTransport::default();
+        let host = Client::local(&local.listener(), username)?;
+        let socket = UdpSocket::bind("0.0.0.0:1234").unwrap();
+        let server_addr = bind_ip.to_socket_addrs().unwrap().next().unwrap();
+        let mut server = Self::with_transport(Box::new(ListenServerTransport::new(
+            renet_server(socket, server_addr),
+            local,
+        )));
+        server.bans = Bans::load(BANS_FILE);
+        Ok((server, host))
+    }
+
+    pub fn with_transport(server: Box<dyn ServerTransport>) -> Self {
+        // conditions are perfect until changed from the command line or the debug panel
+        let conditioner = LinkConditioner::new(default(), rand::thread_rng().gen());
         Self {
-            server: RenetServer::new(current_time, server_config, connection_config, socket)
-                .unwrap(),
+            server: Box::new(StatsServerTransport::new(Box::new(
+                BatchedServerTransport::new(Box::new(ConditionedServerTransport::new(
+                    server,
+                    conditioner,
+                ))),
+            ))),
             players: HashMap::default(),
             networked_entities: HashMap::default(),
+            admins: HashSet::default(),
+            admin_password: None,
+            bans: Bans::default(),
+            map: DEFAULT_MAP.to_string(),
+            chat_settings: ChatSettings::default(),
+            chat_history: VecDeque::new(),
+            lag_compensation: LagCompensation::default(),
+            replication: Replication::default(),
+            interest: Interest::default(),
+            sessions: Sessions::default(),
+            map_info: MapInfo::load(DEFAULT_MAP),
+            game_mode: GameModeKind::Sandbox.create(),
+            round: Round::default(),
+            rules: GameRules::default(),
+            world: VoxelWorld::load(DEFAULT_MAP),
+            portals: HashMap::default(),
+            props: HashMap::default(),
+            next_entity_id: 0,
+            orphan_policy: OrphanPolicy::Despawn,
+        }
+    }
+
+    /// Returns the first living player, other than the shooter, that a bullet moving from
+    /// `start` to `end` passes through
+    fn bullet_hit(&self, shooter_id: u64, start: Vec3, end: Vec3) -> Option<u64> {
+        let half_extents = player_half_extents();
+        let shooter_team = self.players.get(&shooter_id).and_then(|player| player.team);
+        self.players
+            .iter()
+            .filter(|(&client_id, player)| client_id != shooter_id && player.health > 0.0)
+            // bullets pass through teammates without friendly fire
+            .filter(|(_, player)| {
+                self.rules.friendly_fire || shooter_team.is_none() || player.team != shooter_team
+            })
+            .find(|(_, player)| segment_hits_box(start, end, player.position, half_extents))
+            .map(|(&client_id, _)| client_id)
+    }
+
+    pub fn networked_entity_count(&self, client_id: u64) -> usize {
+        self.networked_entities
+            .get(&client_id)
+            .map_or(0, |entities| entities.len())
+    }
+
+    /// Every entity the clients spawned with its owner, type and latest state
+    pub fn networked_entities(
+        &self,
+    ) -> impl Iterator<Item = (u64, Entity, NetworkedEntityType, NetworkTransform)> + '_ {
+        self.networked_entities
+            .iter()
+            .flat_map(|(&client_id, entities)| {
+                entities.iter().map(move |(&entity, networked_entity)| {
+                    (
+                        client_id,
+                        entity,
+                        networked_entity.entity_type,
+                        networked_entity.transform,
+                    )
+                })
+            })
+    }
+
+    pub fn networked_entity(
+        &self,
+        client_id: u64,
+        entity: Entity,
+    ) -> Option<(NetworkedEntityType, NetworkTransform)> {
+        self.networked_entities
+            .get(&client_id)
+            .and_then(|entities| entities.get(&entity))
+            .map(|networked_entity| (networked_entity.entity_type, networked_entity.transform))
+    }
+
+    /// Deals with the entities of a player that left according to the orphan policy,
+    /// clients that have them despawn them on the next interest update. Returns the ids of
+    /// the ones the server took over
+    fn remove_orphans(&mut self, client_id: u64, time: f32) -> Vec<Entity> {
+        let orphans = match self.networked_entities.remove(&client_id) {
+            Some(orphans) => orphans,
+            None => return Vec::new(),
+        };
+        let mut transferred = Vec::new();
+        if self.orphan_policy == OrphanPolicy::TransferToServer {
+            // new ids so they can't clash with the server's own entities
+            for (_, mut networked_entity) in orphans {
+                if let NetworkedEntityType::Bullet(_) = networked_entity.entity_type {
+                    networked_entity.expires_at = Some(time + ORPHAN_BULLET_LIFETIME);
+                }
+                let entity = Entity::from_raw(self.next_entity_id);
+                self.next_entity_id += 1;
+                self.networked_entities
+                    .entry(SERVER_ID)
+                    .or_default()
+                    .insert(entity, networked_entity);
+                transferred.push(entity);
+            }
         }
+        transferred
+    }
+
+    /// Flies the bullets the server took over in a straight line until they hit the world,
+    /// leave it or run out of time. They can't hurt anyone as their shooter is gone
+    fn simulate_orphans(&mut self, delta: f32, time: f32) {
+        let entities = match self.networked_entities.get_mut(&SERVER_ID) {
+            Some(entities) => entities,
+            None => return,
+        };
+        let mut moved = Vec::new();
+        let mut expired = Vec::new();
+        for (&entity, networked_entity) in entities.iter_mut() {
+            let expires_at = match networked_entity.expires_at {
+                Some(expires_at) => expires_at,
+                None => continue,
+            };
+            let transform = &mut networked_entity.transform;
+            let step = transform.velocity * delta;
+            let hit = self
+                .world
+                .raycast(transform.position, step, step.length())
+                .is_some();
+            transform.position += step;
+            if hit || time >= expires_at || self.map_info.is_out_of_world(transform.position) {
+                expired.push(entity);
+            } else if step != Vec3::ZERO {
+                moved.push(entity);
+            }
+        }
+
+        // clients that have them despawn them on the next interest update
+        for entity in expired {
+            entities.remove(&entity);
+        }
+        for entity in moved {
+            self.replicate(
+                Replicated::Entity {
+                    owner: SERVER_ID,
+                    entity,
+                },
+                None,
+            );
+        }
+    }
+
+    /// Drops the entities the server took over from a player that came back, as they
+    /// spawn their own again
+    fn reclaim_orphans(&mut self, transferred: &[Entity]) {
+        if let Some(entities) = self.networked_entities.get_mut(&SERVER_ID) {
+            for entity in transferred {
+                entities.remove(entity);
+            }
+        }
+    }
+
+    /// Despawns an entity on every client, including the one that owns it, the others
+    /// lose it once it is no longer relevant to them
+    fn remove_networked_entity(&mut self, client_id: u64, entity: Entity) {
+        if let Some(entities) = self.networked_entities.get_mut(&client_id) {
+            entities.remove(&entity);
+        }
+
+        self.server.send(
+            client_id,
+            DefaultChannel::Reliable,
+            &ServerMessages::RemoveLocalEntity { entity },
+        );
+    }
+
+    fn damage_player(
+        &mut self,
+        client_id: u64,
+        attacker_id: u64,
+        damage: f32,
+        weapon: Weapon,
+        through_portal: bool,
+        time: f32,
+    ) {
+        let player = self.players.get_mut(&client_id).unwrap();
+        if time < player.protected_until {
+            return;
+        }
+        player.health = (player.health - damage).max(0.0);
+        let health = player.health;
+
+        self.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::PlayerDamaged {
+                client_id,
+                attacker_id,
+                health,
+            },
+        );
+
+        if health <= 0.0 {
+            player.respawn_at = Some(time + RESPAWN_TIME);
+            self.server.broadcast(
+                DefaultChannel::Reliable,
+                &ServerMessages::PlayerKilled {
+                    client_id,
+                    attacker_id,
+                },
+            );
+            info!("Player {} was killed by {}.", client_id, attacker_id);
+            self.broadcast_game_event(GameEvent::PlayerKilled {
+                victim: client_id,
+                killer: Some(attacker_id),
+                weapon,
+                through_portal,
+            });
+            self.register_kill(Some(attacker_id), client_id, time);
+        }
+    }
+
+    pub fn respawn_player(&mut self, client_id: u64, time: f32) {
+        // with teams only the other teams count, without them everyone else does
+        let team = self.players[&client_id].team;
+        let enemies: Vec<Vec3> = self
+            .players
+            .iter()
+            .filter(|(&id, player)| {
+                id != client_id && player.health > 0.0 && (team.is_none() || player.team != team)
+            })
+            .map(|(_, player)| player.position)
+            .collect();
+        let position = self.map_info.pick_spawn_point(&enemies);
+
+        let player = self.players.get_mut(&client_id).unwrap();
+        player.health = MAX_HEALTH;
+        player.respawn_at = None;
+        player.position = position;
+        player.velocity = Vec3::ZERO;
+        player.protected_until = time + SPAWN_PROTECTION_TIME;
+        player.respawns += 1;
+
+        self.server.broadcast(
+            DefaultChannel::Reliable,
+            &ServerMessages::PlayerRespawned {
+                client_id,
+                position,
+                health: MAX_HEALTH,
+                protection: SPAWN_PROTECTION_TIME,
+                respawn: player.respawns,
+            },
+        );
+    }
+
+    /// Kills a living player that left the world and puts them back at a spawn
+    pub fn respawn_out_of_world(&mut self, client_id: u64, time: f32) {
+        self.broadcast_game_event(GameEvent::PlayerKilled {
+            victim: client_id,
+            killer: None,
+            weapon: Weapon::World,
+            through_portal: false,
+        });
+        self.register_kill(None, client_id, time);
+        self.respawn_player(client_id, time);
     }
 }
 
 fn process_server_events(
     mut server_resource: ResMut<ServerResource>,
     mut server_events: EventReader<ServerEvent>,
+    time: Res<Time>,
 ) {
     if let Some(server) = (*server_resource).as_mut() {
         for event in server_events.iter() {
             match event {
-                ServerEvent::ClientConnected(id, _) => {
-                    let username = "Bob".to_string();
-                    server.server.broadcast_message_except(
+                ServerEvent::ClientConnected(id, user_data) => {
+                    let username = username(user_data);
+                    let now = time.elapsed_seconds();
+                    let token = session_token(user_data);
+                    let address = server.server.client_addr(*id).map(|addr| addr.ip());
+                    if let Some(ban) = server.bans.find(address, token) {
+                        info!("Refused {} ({}), banned as {}.", username, id, ban.username);
+                        server.disconnect_with_reason(*id, "You are banned".to_string(), now);
+                        continue;
+                    }
+                    if token.map_or(false, |token| server.is_revoked(token)) {
+                        info!("Refused kicked player {} ({}).", username, id);
+                        server.disconnect_with_reason(*id, "You were kicked".to_string(), now);
+                        continue;
+                    }
+
+                    // ids are no secret, only the token lets someone back in as a player
+                    if server.claims_suspended(*id, token, now) {
+                        warn!(
+                            "Refused {} ({}) without the session of that id.",
+                            username, id
+                        );
+                        let reason = "Someone else is playing with that id".to_string();
+                        server.disconnect_with_reason(*id, reason, now);
+                        continue;
+                    }
+                    let resumed = match token
+                        .and_then(|token| server.resume_session(*id, token, now))
+                    {
+                        Some(suspended) => {
+                            server.reclaim_orphans(&suspended.transferred);
+                            // the rate limits carry on from where they were, so leaving
+                            // and coming back doesn't refill them
+                            let mut player = suspended.player;
+                            player.history = PositionHistory::default();
+                            server.players.insert(*id, player);
+                            Some(suspended.portals)
+                        }
+                        None => {
+                            server.players.insert(
+                                *id,
+                                ServerPlayer {
+                                    username: username.clone(),
+                                    admin: server.admins.contains(id),
+                                    muted: false,
+                                    chat_limit: TokenBucket::new(server.chat_settings.burst, now),
+                                    prop_limit: TokenBucket::new(PROP_BURST, now),
+                                    position: SPAWN_POSITION,
+                                    velocity: Vec3::ZERO,
+                                    health: MAX_HEALTH,
+                                    respawn_at: None,
+                                    history: PositionHistory::default(),
+                                    protected_until: 0.0,
+                                    team: server.smallest_team(),
+                                    kills: 0,
+                                    deaths: 0,
+                                    respawns: 0,
+                                },
+                            );
+                            None
+                        }
+                    };
+                    let resumed_player = resumed.is_some();
+                    // a resumed player keeps the name they had
+                    let username = server.players[id].username.clone();
+
+                    server.server.broadcast_except(
                         *id,
                         DefaultChannel::Reliable,
-                        bincode::serialize(&ServerMessages::ClientConnected {
+                        &ServerMessages::ClientConnected {
                             client_id: *id,
                             username: username.clone(),
-                        })
-                        .unwrap(),
+                        },
                     );
 
-                    // send currently connected players to the new player
-                    for &player_id in server.players.keys() {
-                        server.server.send_message(
+                    match resumed {
+                        Some(portals) => server.restore_player(*id, portals, now),
+                        None => server.respawn_player(*id, now),
+                    }
+
+                    server.start_session(*id);
+                    // everything that happened before the player joined
+                    // in parts, as a busy world is more than one message can hold
+                    for part in server.world_snapshot(*id, now).split() {
+                        server.server.send(
                             *id,
                             DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::ClientConnected {
-                                client_id: player_id,
-                                username: username.clone(),
-                            })
-                            .unwrap(),
+                            &ServerMessages::WorldSnapshot(part),
                         );
                     }
+                    // the new player's score and team are part of the state
+                    server.broadcast_game_mode_state(now);
+                    server.broadcast_game_event(GameEvent::PlayerJoined {
+                        client_id: *id,
+                        username: username.clone(),
+                    });
 
-                    // send currently spawned entities to the new player
-                    for (client_id, entities) in server.networked_entities.iter() {
-                        for (entity, networked_entity) in entities.iter() {
-                            info!("Sending entity to client: {:?}", entity);
-                            server.server.send_message(
-                                *id,
-                                DefaultChannel::Reliable,
-                                bincode::serialize(&ServerMessages::SpawnNetworkedEntity {
-                                    client_id: *client_id,
-                                    entity: *entity,
-                                    entity_type: networked_entity.entity_type,
-                                    transform: networked_entity.transform,
-                                })
-                                .unwrap(),
-                            );
-                        }
+                    if resumed_player {
+                        info!("Player {} ({}) reconnected.", username, id);
+                    } else {
+                        info!("Player {} ({}) connected.", username, id);
                     }
-
-                    server.players.insert(*id, username.clone());
-
-                    info!("Player {} ({}) connected.", username.clone(), id);
                 }
                 ServerEvent::ClientDisconnected(id) => {
-                    let username = server.players.remove(id).unwrap();
+                    server.clear_leaving(*id);
+                    let player = match server.players.remove(id) {
+                        Some(player) => player,
+                        // refused players are disconnected before they are added
+                        None => continue,
+                    };
 
-                    server.server.broadcast_message(
+                    server.server.broadcast(
                         DefaultChannel::Reliable,
-                        bincode::serialize(&ServerMessages::ClientDisconnected { client_id: *id })
-                            .unwrap(),
+                        &ServerMessages::ClientDisconnected { client_id: *id },
                     );
+                    let portals = server.remove_portals(*id);
+                    let transferred = server.remove_orphans(*id, time.elapsed_seconds());
+                    server.release_props(*id, time.elapsed_seconds());
+                    server.broadcast_game_event(GameEvent::PlayerLeft {
+                        client_id: *id,
+                        username: player.username.clone(),
+                    });
+                    server.balance_teams(time.elapsed_seconds());
 
-                    info!("Player {} ({}) disconnected.", username, id);
+                    info!("Player {} ({}) disconnected.", player.username, id);
+                    server.suspend_session(
+                        *id,
+                        player,
+                        transferred,
+                        portals,
+                        time.elapsed_seconds(),
+                    );
                 }
             }
         }
     }
 }
 
-fn process_client_messages(mut server_resource: ResMut<ServerResource>) {
+fn process_client_messages(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
     if let Some(server) = (*server_resource).as_mut() {
         for client_id in server.server.clients_id().into_iter() {
-            while let Some(message) = server
-                .server
-                .receive_message(client_id, DefaultChannel::Reliable)
-            {
-                let message: ClientMessages = bincode::deserialize(&message).unwrap();
+            // players on their way out have no say anymore
+            if !server.players.contains_key(&client_id) || server.is_leaving(client_id) {
+                continue;
+            }
+
+            while let Some(message) = server.server.receive(client_id, DefaultChannel::Reliable) {
                 match message {
                     ClientMessages::ChatMessage { message } => {
-                        info!("{}: {}", server.players[&client_id], message);
-                        server.server.broadcast_message(
+                        if message.starts_with('/') {
+                            if let Err(reason) =
+                                server.moderate_command(client_id, &message, time.elapsed_seconds())
+                            {
+                                server.send_system_message(client_id, reason);
+                                continue;
+                            }
+                            info!(
+                                "{} ran {}",
+                                server.players[&client_id].username,
+                                redact(&message)
+                            );
+                            for reply in server.run_command(
+                                Some(client_id),
+                                &message,
+                                time.elapsed_seconds(),
+                            ) {
+                                server.send_system_message(client_id, reply);
+                            }
+                            continue;
+                        }
+                        let message =
+                            match server.moderate_chat(client_id, &message, time.elapsed_seconds())
+                            {
+                                Ok(message) => message,
+                                Err(reason) => {
+                                    server.send_system_message(client_id, reason);
+                                    continue;
+                                }
+                            };
+
+                        info!("{}: {}", server.players[&client_id].username, message);
+                        server.record_chat(Some(client_id), message.clone());
+                        server.server.broadcast(
                             DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::ChatMessage { client_id, message })
-                                .unwrap(),
+                            &ServerMessages::ChatMessage { client_id, message },
                         );
                     }
-                    ClientMessages::UpdatePlayer { position, velocity } => {
-                        server.server.broadcast_message_except(
-                            client_id,
-                            DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::UpdatePlayer {
-                                client_id,
-                                position,
-                                velocity,
-                            })
-                            .unwrap(),
-                        );
+                    ClientMessages::SwitchTeam { team } => {
+                        if let Err(reason) =
+                            server.switch_team(client_id, team, time.elapsed_seconds())
+                        {
+                            server.send_system_message(client_id, reason);
+                        }
+                    }
+                    ClientMessages::UpdatePlayer {
+                        position,
+                        velocity,
+                        respawn,
+                    } => {
+                        // sent before the player heard they were moved
+                        if respawn != server.players[&client_id].respawns {
+                            continue;
+                        }
+                        if server
+                            .map_info
+                            .is_invalid_position(position, INVALID_POSITION_MARGIN)
+                        {
+                            warn!(
+                                "Respawning player {} from invalid position {}.",
+                                client_id, position
+                            );
+                            // left where they were the player would keep falling
+                            if server.players[&client_id].health > 0.0 {
+                                server.respawn_out_of_world(client_id, time.elapsed_seconds());
+                            }
+                            continue;
+                        }
+
+                        let player = server.players.get_mut(&client_id).unwrap();
+                        player.position = position;
+                        player.velocity = velocity;
+
+                        server.replicate(Replicated::Player(client_id), Some(client_id));
                     }
                     ClientMessages::SpawnNetworkedEntity {
                         entity,
                         entity_type,
                         transform,
                     } => {
-                        server.server.broadcast_message_except(
-                            client_id,
-                            DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::SpawnNetworkedEntity {
-                                client_id,
-                                entity,
-                                entity_type,
-                                transform,
-                            })
-                            .unwrap(),
-                        );
+                        // props only come from the server
+                        if matches!(entity_type, NetworkedEntityType::Prop(_)) {
+                            continue;
+                        }
 
                         let networked_entity = NetworkedEntity {
                             entity_type,
                             transform,
+                            through_portal: false,
+                            expires_at: None,
                         };
 
                         server
@@ -242,44 +762,165 @@ fn process_client_messages(mut server_resource: ResMut<ServerResource>) {
                             .insert(entity, networked_entity);
                     }
                     ClientMessages::UpdateNetworkedEntity { entity, transform } => {
-                        server.server.broadcast_message_except(
-                            client_id,
-                            DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::UpdateNetworkedEntity {
-                                client_id,
-                                entity,
-                                transform,
-                            })
-                            .unwrap(),
-                        );
-
-                        server
+                        // entities removed by the server, e.g. bullets that hit someone, can
+                        // still receive a few updates before the owner hears about it
+                        let networked_entity = match server
                             .networked_entities
                             .get_mut(&client_id)
-                            .unwrap()
-                            .get_mut(&entity)
-                            .unwrap()
-                            .transform = transform;
+                            .and_then(|entities| entities.get_mut(&entity))
+                        {
+                            Some(networked_entity) => networked_entity,
+                            None => continue,
+                        };
+                        let previous_position = networked_entity.transform.position;
+                        let through_portal = networked_entity.through_portal;
+                        networked_entity.transform = transform;
+
+                        match networked_entity.entity_type {
+                            // bullets that can't hurt anyone aren't worth a hit test
+                            NetworkedEntityType::Bullet(bullet_type)
+                                if bullet_damage(bullet_type) > 0.0 =>
+                            {
+                                // check the hit against where the shooter saw everyone
+                                let now = time.elapsed_seconds();
+                                let view_time = server.shooter_view_time(client_id, now);
+                                let saved = server.rewind_players(view_time, now);
+                                let hit = server.bullet_hit(
+                                    client_id,
+                                    previous_position,
+                                    transform.position,
+                                );
+                                server.restore_players(saved);
+
+                                if let Some(hit_id) = hit {
+                                    server.remove_networked_entity(client_id, entity);
+                                    server.damage_player(
+                                        hit_id,
+                                        client_id,
+                                        bullet_damage(bullet_type),
+                                        Weapon::PortalGun,
+                                        through_portal,
+                                        time.elapsed_seconds(),
+                                    );
+                                    continue;
+                                }
+                            }
+                            NetworkedEntityType::Bullet(_) | NetworkedEntityType::Prop(_) => {}
+                        }
+
+                        server.replicate(
+                            Replicated::Entity {
+                                owner: client_id,
+                                entity,
+                            },
+                            Some(client_id),
+                        );
                     }
-                    ClientMessages::DespawnNetworkedEntity { entity } => {
-                        server.server.broadcast_message_except(
+                    ClientMessages::PortalTraversal { entity, from, to } => {
+                        if !server.can_use_portal(client_id, from, to) {
+                            warn!(
+                                "Player {} went through portals {:?} -> {:?} it can't use.",
+                                client_id, from, to
+                            );
+                            continue;
+                        }
+                        if let Some(entity) = entity {
+                            let exit = match server.portals.get(&to) {
+                                Some(placement) => placement.position,
+                                None => continue,
+                            };
+                            match server
+                                .networked_entities
+                                .get_mut(&client_id)
+                                .and_then(|entities| entities.get_mut(&entity))
+                            {
+                                Some(networked_entity) => {
+                                    networked_entity.through_portal = true;
+                                    // the next hit test starts at the exit instead of cutting
+                                    // across the map between the portals
+                                    networked_entity.transform.position = exit;
+                                }
+                                None => continue,
+                            }
+                        }
+
+                        server.server.broadcast_except(
                             client_id,
                             DefaultChannel::Reliable,
-                            bincode::serialize(&ServerMessages::DespawnNetworkedEntity {
+                            &ServerMessages::PortalTraversal {
                                 client_id,
                                 entity,
-                            })
-                            .unwrap(),
+                                from,
+                                to,
+                            },
                         );
-
-                        server
-                            .networked_entities
-                            .get_mut(&client_id)
-                            .unwrap()
-                            .remove(&entity);
+                    }
+                    ClientMessages::ShootPortal { index, direction } => {
+                        match server.place_portal(client_id, index, direction) {
+                            Ok(placement) => {
+                                server.portals.insert(
+                                    PortalId {
+                                        owner: client_id,
+                                        index,
+                                    },
+                                    placement,
+                                );
+                                server.server.broadcast(
+                                    DefaultChannel::Reliable,
+                                    &ServerMessages::PortalPlaced {
+                                        client_id,
+                                        index,
+                                        placement,
+                                    },
+                                );
+                            }
+                            Err(reason) => server.send_system_message(client_id, reason),
+                        }
+                    }
+                    ClientMessages::SpawnProp { shape, direction } => {
+                        server.spawn_prop(client_id, shape, direction, time.elapsed_seconds());
+                    }
+                    ClientMessages::UpdateProp { entity, transform } => {
+                        server.update_prop(client_id, entity, transform);
+                    }
+                    ClientMessages::DespawnNetworkedEntity { entity } => {
+                        // clients that have it despawn it on the next interest update
+                        if let Some(entities) = server.networked_entities.get_mut(&client_id) {
+                            entities.remove(&entity);
+                        }
                     }
                 }
             }
         }
     }
 }
+
+fn update_orphans(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        server.simulate_orphans(time.delta_seconds(), time.elapsed_seconds());
+    }
+}
+
+fn update_respawns(mut server_resource: ResMut<ServerResource>, time: Res<Time>) {
+    if let Some(server) = (*server_resource).as_mut() {
+        let now = time.elapsed_seconds();
+        let respawning: Vec<u64> = server
+            .players
+            .iter()
+            .filter(|(_, player)| match player.respawn_at {
+                Some(respawn_at) => respawn_at <= now,
+                // living players that fell out of the world
+                None => server.map_info.is_out_of_world(player.position),
+            })
+            .map(|(&client_id, _)| client_id)
+            .collect();
+
+        for client_id in respawning {
+            if server.players[&client_id].respawn_at.is_none() {
+                server.respawn_out_of_world(client_id, now);
+            } else {
+                server.respawn_player(client_id, now);
+            }
+        }
+    }
+}
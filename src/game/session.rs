@@ -0,0 +1,349 @@
+use super::{
+    client::{reset_remote_world, ClientResource},
+    networking::{PortalId, ServerMessages},
+    portals::PortalPlacement,
+    server::{Server, ServerPlayer},
+};
+use crate::GameState;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext,
+};
+use matcher::Username;
+use rand::Rng;
+use renet::{DefaultChannel, NETCODE_USER_DATA_BYTES};
+use std::ops::Range;
+
+// seconds between attempts to reconnect
+const RETRY_INTERVAL: f32 = 2.0;
+// seconds a client is given to hear why it is being disconnected
+const DISCONNECT_DELAY: f32 = 0.5;
+// the token goes in the last 8 bytes of the user data, which no username reaches
+const TOKEN_BYTES: Range<usize> = NETCODE_USER_DATA_BYTES - 8..NETCODE_USER_DATA_BYTES;
+/// Longest username in bytes, leaving room for its length before it and the token after
+pub const MAX_USERNAME_LENGTH: usize = NETCODE_USER_DATA_BYTES - 16;
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(reconnecting_overlay));
+    }
+}
+
+/// User data naming the player and, when reconnecting, the session they had
+pub fn user_data(
+    username: &str,
+    token: Option<u64>,
+) -> Result<[u8; NETCODE_USER_DATA_BYTES], String> {
+    if username.len() > MAX_USERNAME_LENGTH {
+        return Err("Nick is too long".to_string());
+    }
+    let mut user_data = Username(username.to_string()).to_netcode_user_data();
+    user_data[TOKEN_BYTES].copy_from_slice(&token.unwrap_or(0).to_le_bytes());
+    Ok(user_data)
+}
+
+/// The username in user data, never reading into the token
+pub fn username(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> String {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&user_data[0..8]);
+    let len = (u64::from_le_bytes(buffer) as usize).min(MAX_USERNAME_LENGTH);
+    // anyone can send user data, so a name that isn't valid utf-8 mustn't panic
+    String::from_utf8_lossy(&user_data[8..len + 8]).into_owned()
+}
+
+pub fn session_token(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<u64> {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&user_data[TOKEN_BYTES]);
+    let token = u64::from_le_bytes(buffer);
+    (token != 0).then_some(token)
+}
+
+/// Players the server keeps for a while after they leave so they can come back as
+/// themselves
+pub struct Sessions {
+    // seconds a player is kept after disconnecting, none are kept at 0
+    pub grace: f32,
+    tokens: HashMap<u64, u64>,
+    suspended: HashMap<u64, Suspended>,
+    // tokens of kicked players, who only get back in by joining again
+    revoked: HashSet<u64>,
+    // clients told why they are being disconnected and when to cut them off
+    leaving: HashMap<u64, f32>,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self {
+            grace: 30.0,
+            tokens: HashMap::default(),
+            suspended: HashMap::default(),
+            revoked: HashSet::default(),
+            leaving: HashMap::default(),
+        }
+    }
+}
+
+/// A player that left, as they get it back when they resume
+pub struct Suspended {
+    token: u64,
+    pub player: ServerPlayer,
+    // entities the server took over, given back if the player returns
+    pub transferred: Vec<Entity>,
+    pub portals: Vec<(PortalId, PortalPlacement)>,
+    // portals only come back on the map they were placed on
+    map: String,
+    expires_at: f32,
+}
+
+impl Server {
+    /// Gives a player that joined a new token to reconnect with
+    pub fn start_session(&mut self, client_id: u64) {
+        let token = rand::thread_rng().gen_range(1..=u64::MAX);
+        self.sessions.tokens.insert(client_id, token);
+        self.server.send(
+            client_id,
+            DefaultChannel::Reliable,
+            &ServerMessages::Session {
+                token,
+                grace: self.sessions.grace,
+            },
+        );
+    }
+
+    /// Keeps a player that disconnected until the grace window runs out
+    pub fn suspend_session(
+        &mut self,
+        client_id: u64,
+        player: ServerPlayer,
+        transferred: Vec<Entity>,
+        portals: Vec<(PortalId, PortalPlacement)>,
+        time: f32,
+    ) {
+        self.sessions
+            .suspended
+            .retain(|_, suspended| suspended.expires_at > time);
+        let token = match self.sessions.tokens.remove(&client_id) {
+            Some(token) => token,
+            None => return,
+        };
+        if self.sessions.grace > 0.0 {
+            self.sessions.suspended.insert(
+                client_id,
+                Suspended {
+                    token,
+                    player,
+                    transferred,
+                    portals,
+                    map: self.map.clone(),
+                    expires_at: time + self.sessions.grace,
+                },
+            );
+        }
+    }
+
+    /// Whether a connection under this id would take the place of a suspended player
+    /// without their token
+    pub fn claims_suspended(&self, client_id: u64, token: Option<u64>, time: f32) -> bool {
+        self.sessions
+            .suspended
+            .get(&client_id)
+            .map_or(false, |suspended| {
+                suspended.expires_at > time && Some(suspended.token) != token
+            })
+    }
+
+    /// Takes back a suspended player if the token is theirs, along with the entities the
+    /// server took over from them and their portals
+    pub fn resume_session(&mut self, client_id: u64, token: u64, time: f32) -> Option<Suspended> {
+        match self.sessions.suspended.get(&client_id) {
+            Some(suspended) if suspended.expires_at > time && suspended.token == token => {}
+            _ => return None,
+        }
+        let mut suspended = self.sessions.suspended.remove(&client_id).unwrap();
+        if suspended.map != self.map {
+            suspended.portals.clear();
+        }
+        Some(suspended)
+    }
+
+    pub fn session_token_of(&self, client_id: u64) -> Option<u64> {
+        self.sessions.tokens.get(&client_id).copied()
+    }
+
+    /// Stops a player from coming back with their token, for players that are kicked
+    pub fn end_session(&mut self, client_id: u64) {
+        if let Some(token) = self.sessions.tokens.remove(&client_id) {
+            self.sessions.revoked.insert(token);
+        }
+    }
+
+    /// Tells a client why it is being disconnected so it doesn't try to reconnect, and
+    /// disconnects it once the message has had time to arrive
+    pub fn disconnect_with_reason(&mut self, client_id: u64, reason: String, time: f32) {
+        self.server.send(
+            client_id,
+            DefaultChannel::Reliable,
+            &ServerMessages::Disconnected { reason },
+        );
+        self.sessions
+            .leaving
+            .insert(client_id, time + DISCONNECT_DELAY);
+    }
+
+    /// Disconnects the clients that were told why long enough ago
+    pub fn disconnect_leaving(&mut self, time: f32) {
+        let due: Vec<u64> = self
+            .sessions
+            .leaving
+            .iter()
+            .filter(|(_, at)| **at <= time)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in due {
+            self.sessions.leaving.remove(&client_id);
+            self.server.disconnect(client_id);
+        }
+    }
+
+    /// Forgets a client that was being disconnected once it is gone, so the id is free
+    /// to connect again
+    pub fn clear_leaving(&mut self, client_id: u64) {
+        self.sessions.leaving.remove(&client_id);
+    }
+
+    pub fn is_leaving(&self, client_id: u64) -> bool {
+        self.sessions.leaving.contains_key(&client_id)
+    }
+
+    pub fn is_revoked(&self, token: u64) -> bool {
+        self.sessions.revoked.contains(&token)
+    }
+
+    pub fn suspended_count(&self) -> usize {
+        self.sessions.suspended.len()
+    }
+
+    /// Shows everyone a resumed player as they were when they left
+    pub fn restore_player(
+        &mut self,
+        client_id: u64,
+        portals: Vec<(PortalId, PortalPlacement)>,
+        time: f32,
+    ) {
+        for (id, placement) in portals {
+            self.portals.insert(id, placement);
+            self.server.broadcast(
+                DefaultChannel::Reliable,
+                &ServerMessages::PortalPlaced {
+                    client_id: id.owner,
+                    index: id.index,
+                    placement,
+                },
+            );
+        }
+
+        let player = &self.players[&client_id];
+        // dead players come back through the usual respawn
+        if player.health <= 0.0 {
+            return;
+        }
+        let message = ServerMessages::PlayerRespawned {
+            client_id,
+            position: player.position,
+            health: player.health,
+            protection: (player.protected_until - time).max(0.0),
+            respawn: player.respawns,
+        };
+        self.server.broadcast(DefaultChannel::Reliable, &message);
+    }
+}
+
+/// Where a client is in getting back a connection it lost
+#[derive(Clone, Copy, Debug)]
+pub struct Reconnecting {
+    pub since: f32,
+    last_attempt: f32,
+}
+
+/// Retries a lost connection with the session token for as long as the server keeps the
+/// player, going back to the menu once it gives up or when there is nothing to retry.
+/// Reconnecting ends when the server sends a new session
+pub fn reconnect(
+    mut commands: Commands,
+    mut client_resource: ResMut<ClientResource>,
+    mut game_state: ResMut<State<GameState>>,
+    time: Res<Time>,
+) {
+    if let Some(client) = (*client_resource).as_mut() {
+        let now = time.elapsed_seconds();
+        let connected = client.client.is_connected();
+        match client.reconnecting {
+            None => {
+                if client.connected && !connected {
+                    // clients the server disconnected on purpose have no session left
+                    if !client.can_reconnect() {
+                        back_to_menu(&mut game_state);
+                    } else {
+                        warn!("Lost the connection to the server, reconnecting.");
+                        // nothing from before is kept, the server sends it all again
+                        reset_remote_world(&mut commands, client);
+                        client.reconnecting = Some(Reconnecting {
+                            since: now,
+                            last_attempt: now - RETRY_INTERVAL,
+                        });
+                    }
+                }
+            }
+            Some(mut reconnecting) => {
+                let (token, grace) = client.session.unwrap();
+                if now - reconnecting.since > grace {
+                    error!("Couldn't reconnect to the server.");
+                    client.reconnecting = None;
+                    back_to_menu(&mut game_state);
+                } else if !connected && now - reconnecting.last_attempt >= RETRY_INTERVAL {
+                    reconnecting.last_attempt = now;
+                    client.reconnecting = Some(reconnecting);
+                    client.reconnect(token);
+                }
+            }
+        }
+        client.connected = connected;
+    }
+}
+
+/// Leaves the game even if another state change is already queued, a lost connection
+/// taking priority over it
+fn back_to_menu(game_state: &mut State<GameState>) {
+    if let Err(e) = game_state.overwrite_set(GameState::Menu) {
+        warn!("Couldn't go back to the menu: {:?}", e);
+    }
+}
+
+fn reconnecting_overlay(
+    mut egui_context: ResMut<EguiContext>,
+    client_resource: Res<ClientResource>,
+    time: Res<Time>,
+) {
+    let state = client_resource
+        .0
+        .as_ref()
+        .and_then(|client| client.reconnecting.zip(client.session));
+    let (reconnecting, (_, grace)) = match state {
+        Some(state) => state,
+        None => return,
+    };
+
+    egui::Area::new("reconnecting")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.colored_label(Color32::YELLOW, "Reconnecting…");
+            let left = grace - (time.elapsed_seconds() - reconnecting.since);
+            ui.label(format!("Giving up in {:.0}s", left.max(0.0)));
+        });
+}
@@ -0,0 +1,222 @@
+use super::{
+    networking::{NetworkTransform, NetworkedEntityType, PortalId, ServerMessages, SERVER_ID},
+    portals::PortalPlacement,
+    replication::Replicated,
+    scoreboard::PlayerStats,
+    server::Server,
+    voxel_world::VoxelEdit,
+    DEFAULT_MAP,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// chat lines the server keeps for players who join later
+const CHAT_HISTORY: usize = 50;
+// bytes a snapshot is split into parts of, well under what a reliable message can hold
+pub const MAX_PART_SIZE: u64 = 1024;
+
+/// Everything a client that joins late needs to see the same world as everyone else
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorldSnapshot {
+    // none for the default map
+    pub map: Option<String>,
+    // changes to the map since it was loaded
+    pub edits: Vec<VoxelEdit>,
+    pub players: Vec<PlayerSnapshot>,
+    pub entities: Vec<EntitySnapshot>,
+    pub portals: Vec<(PortalId, PortalPlacement)>,
+    pub chat: Vec<ChatLine>,
+    pub stats: Vec<PlayerStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLine {
+    // id and name of the sender, none for the server itself. The name is kept as the
+    // sender may have left by the time someone joins
+    pub sender: Option<(u64, String)>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerSnapshot {
+    pub client_id: u64,
+    pub username: String,
+    pub position: Vec3,
+    pub health: f32,
+    // seconds of spawn protection left
+    pub protection: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct EntitySnapshot {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub entity_type: NetworkedEntityType,
+    pub transform: NetworkTransform,
+    // player simulating a prop
+    pub owner: Option<u64>,
+}
+
+impl EntitySnapshot {
+    /// Messages that spawn the entity on a client
+    pub fn messages(&self) -> Vec<ServerMessages> {
+        let mut messages = vec![ServerMessages::SpawnNetworkedEntity {
+            client_id: self.client_id,
+            entity: self.entity,
+            entity_type: self.entity_type,
+            transform: self.transform,
+        }];
+        if let NetworkedEntityType::Prop(_) = self.entity_type {
+            messages.push(ServerMessages::PropOwner {
+                entity: self.entity,
+                owner: self.owner,
+                transform: self.transform,
+            });
+        }
+        messages
+    }
+}
+
+/// Moves `items` into the last of `parts`, starting a new part whenever it would go over
+/// `MAX_PART_SIZE`
+fn fill<T: Serialize>(
+    parts: &mut Vec<WorldSnapshot>,
+    items: Vec<T>,
+    field: fn(&mut WorldSnapshot) -> &mut Vec<T>,
+) {
+    for item in items {
+        let size = bincode::serialized_size(&item).unwrap();
+        let last = parts.last_mut().unwrap();
+        if !last.is_empty() && bincode::serialized_size(last).unwrap() + size > MAX_PART_SIZE {
+            parts.push(WorldSnapshot::default());
+        }
+        field(parts.last_mut().unwrap()).push(item);
+    }
+}
+
+impl WorldSnapshot {
+    /// Splits the snapshot into parts small enough to send as messages of their own, the
+    /// map and stats going with the first
+    pub fn split(self) -> Vec<WorldSnapshot> {
+        let mut parts = vec![WorldSnapshot {
+            map: self.map,
+            stats: self.stats,
+            ..default()
+        }];
+        fill(&mut parts, self.edits, |part| &mut part.edits);
+        fill(&mut parts, self.players, |part| &mut part.players);
+        fill(&mut parts, self.entities, |part| &mut part.entities);
+        fill(&mut parts, self.portals, |part| &mut part.portals);
+        fill(&mut parts, self.chat, |part| &mut part.chat);
+        parts
+    }
+
+    fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+            && self.players.is_empty()
+            && self.entities.is_empty()
+            && self.portals.is_empty()
+            && self.chat.is_empty()
+    }
+
+    /// Everything except the players and chat, as the messages that would have built it up
+    pub fn into_messages(self) -> Vec<ServerMessages> {
+        let mut messages = Vec::new();
+        if let Some(map) = self.map {
+            messages.push(ServerMessages::ChangeMap { map });
+        }
+        if !self.edits.is_empty() {
+            messages.push(ServerMessages::VoxelsEdited { edits: self.edits });
+        }
+        for entity in self.entities {
+            messages.extend(entity.messages());
+        }
+        for (id, placement) in self.portals {
+            messages.push(ServerMessages::PortalPlaced {
+                client_id: id.owner,
+                index: id.index,
+                placement,
+            });
+        }
+        // only the first part of a snapshot has the stats
+        if !self.stats.is_empty() {
+            messages.push(ServerMessages::PlayerStats { stats: self.stats });
+        }
+        messages
+    }
+}
+
+impl Server {
+    /// Keeps a chat line everyone was sent for the players who join later
+    pub fn record_chat(&mut self, client_id: Option<u64>, message: String) {
+        let sender = client_id.map(|id| (id, self.players[&id].username.clone()));
+        self.chat_history.push_back(ChatLine { sender, message });
+        if self.chat_history.len() > CHAT_HISTORY {
+            self.chat_history.pop_front();
+        }
+    }
+
+    pub fn entity_snapshot(&self, replicated: Replicated) -> Option<EntitySnapshot> {
+        match replicated {
+            Replicated::Player(_) => None,
+            Replicated::Entity { owner, entity }
+                if owner == SERVER_ID && self.props.contains_key(&entity) =>
+            {
+                let prop = &self.props[&entity];
+                Some(EntitySnapshot {
+                    client_id: SERVER_ID,
+                    entity,
+                    entity_type: NetworkedEntityType::Prop(prop.shape),
+                    transform: prop.transform,
+                    owner: prop.owner,
+                })
+            }
+            Replicated::Entity { owner, entity } => {
+                let (entity_type, transform) = self.networked_entity(owner, entity)?;
+                Some(EntitySnapshot {
+                    client_id: owner,
+                    entity,
+                    entity_type,
+                    transform,
+                    owner: None,
+                })
+            }
+        }
+    }
+
+    /// The world as a player that just joined should see it, which also counts as having
+    /// spawned the entities relevant to them
+    pub fn world_snapshot(&mut self, client_id: u64, time: f32) -> WorldSnapshot {
+        let viewer = self.players[&client_id].position;
+        let entities = self
+            .start_interest(client_id, viewer, time)
+            .into_iter()
+            .filter_map(|replicated| self.entity_snapshot(replicated))
+            .collect();
+
+        WorldSnapshot {
+            map: (self.map != DEFAULT_MAP).then(|| self.map.clone()),
+            edits: self.world.edits().collect(),
+            players: self
+                .players
+                .iter()
+                .filter(|(&id, _)| id != client_id)
+                .map(|(&id, player)| PlayerSnapshot {
+                    client_id: id,
+                    username: player.username.clone(),
+                    position: player.position,
+                    health: player.health,
+                    protection: (player.protected_until - time).max(0.0),
+                })
+                .collect(),
+            entities,
+            portals: self
+                .portals
+                .iter()
+                .map(|(&id, &placement)| (id, placement))
+                .collect(),
+            chat: self.chat_history.iter().cloned().collect(),
+            stats: self.player_stats(),
+        }
+    }
+}
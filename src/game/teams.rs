@@ -0,0 +1,184 @@
+use super::{
+    client::{Client, ClientResource},
+    health::MAX_HEALTH,
+    server::Server,
+};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_voxel_engine::{VoxelizationMaterial, VoxelizationMaterialType};
+
+// palette indices in monu9.vox, orange and blue are the original portal colours and the
+// lighter shades mark each team's second portal. Players are lime and teal, colours the
+// map and the portals don't use
+const NEUTRAL_PLAYER_MATERIAL: u8 = 10;
+const NEUTRAL_PORTAL_MATERIALS: [u8; 2] = [120, 121];
+const TEAM_MATERIALS: [u8; 2] = [4, 42];
+const TEAM_PORTAL_MATERIALS: [[u8; 2]; 2] = [[120, 40], [121, 24]];
+
+pub struct TeamsPlugin;
+
+impl Plugin for TeamsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(apply_team_colors));
+    }
+}
+
+pub fn player_material(team: Option<u8>) -> u8 {
+    match team {
+        Some(team) => TEAM_MATERIALS[team as usize % 2],
+        None => NEUTRAL_PLAYER_MATERIAL,
+    }
+}
+
+/// Material of a portal frame, `index` being 0 or 1 for the player's first or second portal
+pub fn portal_material(team: Option<u8>, index: u32) -> u8 {
+    match team {
+        Some(team) => TEAM_PORTAL_MATERIALS[team as usize % 2][index as usize % 2],
+        None => NEUTRAL_PORTAL_MATERIALS[index as usize % 2],
+    }
+}
+
+/// Who a portal belongs to, `client_id` is none for the local player's portals
+#[derive(Component)]
+pub struct PortalOwner {
+    pub client_id: Option<u64>,
+    pub index: u32,
+}
+
+#[derive(Component)]
+pub struct PortalFrame;
+
+impl Client {
+    pub fn team(&self, client_id: u64) -> Option<u8> {
+        let (state, _) = self.game_mode.as_ref()?;
+        state
+            .players
+            .iter()
+            .find(|player| player.client_id == client_id)
+            .and_then(|player| player.team)
+    }
+}
+
+fn apply_team_colors(
+    client_resource: Res<ClientResource>,
+    portals: Query<(&PortalOwner, &Children)>,
+    mut frames: Query<&mut VoxelizationMaterial, With<PortalFrame>>,
+    mut boxes: Query<&mut bevy_voxel_engine::Box>,
+) {
+    let client = match (*client_resource).as_ref() {
+        Some(client) => client,
+        None => return,
+    };
+
+    for (owner, children) in portals.iter() {
+        let team = client.team(owner.client_id.unwrap_or(client.client_id));
+        let material = portal_material(team, owner.index);
+        for &child in children.iter() {
+            if let Ok(mut frame) = frames.get_mut(child) {
+                // only write on change so the frame isn't revoxelized every frame
+                let current = match frame.material {
+                    VoxelizationMaterialType::Material(current) => Some(current),
+                    _ => None,
+                };
+                if current != Some(material) {
+                    frame.material = VoxelizationMaterialType::Material(material);
+                }
+            }
+        }
+    }
+
+    for (&client_id, player) in client.players.iter() {
+        if let Ok(mut player_box) = boxes.get_mut(player.entity) {
+            let material = player_material(client.team(client_id));
+            if player_box.material != material {
+                player_box.material = material;
+            }
+        }
+    }
+}
+
+impl Server {
+    /// Moves a player to another team if that doesn't leave the teams uneven. Only players
+    /// at full health are moved to a spawn, so switching can't skip a respawn or heal
+    pub fn switch_team(&mut self, client_id: u64, team: u8, time: f32) -> Result<(), String> {
+        let teams = self.game_mode.teams();
+        if team >= teams {
+            return Err("This game mode has no such team".to_string());
+        }
+        let current = self.players[&client_id].team;
+        if current == Some(team) {
+            return Ok(());
+        }
+
+        let size = |team: Option<u8>| {
+            self.players
+                .values()
+                .filter(|player| player.team == team)
+                .count()
+        };
+        if current.is_some() && size(Some(team)) >= size(current) {
+            return Err("That team already has more players".to_string());
+        }
+
+        let player = self.players.get_mut(&client_id).unwrap();
+        player.team = Some(team);
+        if player.respawn_at.is_none() && player.health >= MAX_HEALTH {
+            self.respawn_player(client_id, time);
+        }
+        self.broadcast_game_mode_state(time);
+        Ok(())
+    }
+
+    /// Moves players from the biggest team to the smallest until they differ by at most one
+    pub fn balance_teams(&mut self, time: f32) {
+        let teams = self.game_mode.teams();
+        if teams == 0 {
+            return;
+        }
+
+        let mut changed = false;
+        loop {
+            let sizes: Vec<usize> = (0..teams)
+                .map(|team| {
+                    self.players
+                        .values()
+                        .filter(|player| player.team == Some(team))
+                        .count()
+                })
+                .collect();
+            let (largest, &max) = sizes
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, &size)| size)
+                .unwrap();
+            let (smallest, &min) = sizes
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, &size)| size)
+                .unwrap();
+            if max <= min + 1 {
+                break;
+            }
+
+            // move the lowest scoring player so the balance costs the team the least
+            let client_id = *self
+                .players
+                .iter()
+                .filter(|(_, player)| player.team == Some(largest as u8))
+                .min_by_key(|(client_id, _)| self.round.scores.get(client_id).copied().unwrap_or(0))
+                .unwrap()
+                .0;
+            let player = self.players.get_mut(&client_id).unwrap();
+            player.team = Some(smallest as u8);
+            // as with switching, a damaged or dead player isn't healed by the move
+            if player.respawn_at.is_none() && player.health >= MAX_HEALTH {
+                self.respawn_player(client_id, time);
+            }
+            changed = true;
+        }
+
+        if changed {
+            self.broadcast_game_mode_state(time);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use super::{
+    link_conditioner::LinkConditioner,
+    network_stats::NetworkStats,
+    networking::{ClientMessages, ServerMessages},
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use renet::{
+    ClientAuthentication, DefaultChannel, NetworkInfo, RenetClient, RenetConnectionConfig,
+    RenetServer, ServerAuthentication, ServerConfig, ServerEvent, NETCODE_USER_DATA_BYTES,
+};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+/// What the client needs from a connection to the server
+pub trait ClientTransport: Send + Sync {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>>;
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>>;
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>);
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>>;
+    fn is_connected(&self) -> bool;
+    fn disconnect(&mut self);
+    fn network_info(&self) -> NetworkInfo;
+
+    /// Sends a message while it is still typed, so layers that look at what they send
+    /// don't have to deserialize it again
+    fn send(&mut self, channel: DefaultChannel, message: &ClientMessages) {
+        self.send_message(channel, bincode::serialize(message).unwrap());
+    }
+
+    /// Receives the next message that can be read, skipping any that can't
+    fn receive(&mut self, channel: DefaultChannel) -> Option<ServerMessages> {
+        loop {
+            match bincode::deserialize(&self.receive_message(channel)?) {
+                Ok(message) => return Some(message),
+                Err(e) => error!("Invalid message: {}", e),
+            }
+        }
+    }
+
+    /// Messages held back in our own layers, renet doesn't expose its queues
+    fn queued_messages(&self) -> usize {
+        0
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        None
+    }
+
+    fn stats(&self) -> Option<&NetworkStats> {
+        None
+    }
+
+    /// Swaps in a new connection to the same server, keeping the layers on top of it. Only
+    /// layers wrapping a connection can do this
+    fn reconnect(&mut self, _transport: Box<dyn ClientTransport>) {}
+}
+
+/// What the server needs from the connections to its clients
+pub trait ServerTransport: Send + Sync {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>>;
+    fn get_event(&mut self) -> Option<ServerEvent>;
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>>;
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>);
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>);
+    fn broadcast_message_except(
+        &mut self,
+        client_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    );
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>>;
+    fn clients_id(&self) -> Vec<u64>;
+    fn disconnect(&mut self, client_id: u64);
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo>;
+    // none for clients that aren't connected through a socket
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr>;
+
+    /// Sends a message while it is still typed, so layers that look at what they send
+    /// don't have to deserialize it again
+    fn send(&mut self, client_id: u64, channel: DefaultChannel, message: &ServerMessages) {
+        self.send_message(client_id, channel, bincode::serialize(message).unwrap());
+    }
+
+    fn broadcast(&mut self, channel: DefaultChannel, message: &ServerMessages) {
+        self.broadcast_message(channel, bincode::serialize(message).unwrap());
+    }
+
+    fn broadcast_except(
+        &mut self,
+        client_id: u64,
+        channel: DefaultChannel,
+        message: &ServerMessages,
+    ) {
+        self.broadcast_message_except(client_id, channel, bincode::serialize(message).unwrap());
+    }
+
+    /// Receives the next message from a client that can be read, skipping any that can't
+    fn receive(&mut self, client_id: u64, channel: DefaultChannel) -> Option<ClientMessages> {
+        loop {
+            match bincode::deserialize(&self.receive_message(client_id, channel)?) {
+                Ok(message) => return Some(message),
+                Err(e) => error!("Invalid message from {}: {}", client_id, e),
+            }
+        }
+    }
+
+    /// Messages to a client held back in our own layers, renet doesn't expose its queues
+    fn queued_messages(&self, _client_id: u64) -> usize {
+        0
+    }
+
+    fn conditioner(&mut self) -> Option<&mut LinkConditioner> {
+        None
+    }
+
+    fn stats(&self, _client_id: u64) -> Option<&NetworkStats> {
+        None
+    }
+}
+
+fn current_time() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+}
+
+/// Connects to a server over UDP from an ephemeral port
+pub fn renet_client(
+    ip: &str,
+    client_id: u64,
+    user_data: [u8; NETCODE_USER_DATA_BYTES],
+) -> RenetClient {
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let server_addr = ip.to_socket_addrs().unwrap().next().unwrap();
+    let connection_config = RenetConnectionConfig::default();
+    let authentication = ClientAuthentication::Unsecure {
+        protocol_id: 0,
+        client_id,
+        server_addr,
+        user_data: Some(user_data),
+    };
+
+    info!("Client connected to {}", server_addr);
+
+    RenetClient::new(current_time(), socket, connection_config, authentication).unwrap()
+}
+
+/// Runs a UDP server on an already bound socket, with `server_addr` being the address
+/// clients connect to
+pub fn renet_server(socket: UdpSocket, server_addr: SocketAddr) -> RenetServer {
+    let connection_config = RenetConnectionConfig::default();
+    let server_config = ServerConfig::new(64, 0, server_addr, ServerAuthentication::Unsecure);
+
+    // let register_server = RegisterServer {
+    //     name: lobby_name,
+    //     address: server_addr,
+    //     max_clients: server_config.max_clients as u64,
+    //     private_key,
+    //     password,
+    //     current_clients: 0,
+    // };
+
+    info!("Server started on {}", server_addr);
+
+    RenetServer::new(current_time(), server_config, connection_config, socket).unwrap()
+}
+
+impl ClientTransport for RenetClient {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        Ok(RenetClient::update(self, duration)?)
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(RenetClient::send_packets(self)?)
+    }
+
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        RenetClient::send_message(self, channel, message);
+    }
+
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        RenetClient::receive_message(self, channel)
+    }
+
+    fn is_connected(&self) -> bool {
+        RenetClient::is_connected(self)
+    }
+
+    fn disconnect(&mut self) {
+        RenetClient::disconnect(self);
+    }
+
+    fn network_info(&self) -> NetworkInfo {
+        RenetClient::network_info(self)
+    }
+}
+
+impl ServerTransport for RenetServer {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        Ok(RenetServer::update(self, duration)?)
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        RenetServer::get_event(self)
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(RenetServer::send_packets(self)?)
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        RenetServer::send_message(self, client_id, channel, message);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        RenetServer::broadcast_message(self, channel, message);
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        client_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        RenetServer::broadcast_message_except(self, client_id, channel, message);
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        RenetServer::receive_message(self, client_id, channel)
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        RenetServer::clients_id(self)
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        RenetServer::disconnect(self, client_id);
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        RenetServer::network_info(self, client_id)
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        RenetServer::client_addr(self, client_id)
+    }
+}
+
+// both directions of an in-memory connection, messages queued per channel
+#[derive(Default)]
+struct MemoryConnection {
+    to_server: HashMap<u8, VecDeque<Vec<u8>>>,
+    to_client: HashMap<u8, VecDeque<Vec<u8>>>,
+    disconnected: bool,
+}
+
+type SharedConnection = Arc<Mutex<MemoryConnection>>;
+
+/// The client end of an in-memory connection, e.g. the host's own client
+pub struct MemoryClientTransport {
+    connection: SharedConnection,
+}
+
+impl ClientTransport for MemoryClientTransport {
+    fn update(&mut self, _: Duration) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn send_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        let mut connection = self.connection.lock().unwrap();
+        if !connection.disconnected {
+            connection
+                .to_server
+                .entry(channel.into())
+                .or_default()
+                .push_back(message);
+        }
+    }
+
+    fn receive_message(&mut self, channel: DefaultChannel) -> Option<Vec<u8>> {
+        let mut connection = self.connection.lock().unwrap();
+        connection.to_client.get_mut(&channel.into())?.pop_front()
+    }
+
+    fn is_connected(&self) -> bool {
+        !self.connection.lock().unwrap().disconnected
+    }
+
+    fn disconnect(&mut self) {
+        self.connection.lock().unwrap().disconnected = true;
+    }
+
+    fn network_info(&self) -> NetworkInfo {
+        NetworkInfo::default()
+    }
+}
+
+type IncomingConnections = Arc<Mutex<Vec<(u64, [u8; NETCODE_USER_DATA_BYTES], SharedConnection)>>>;
+
+/// Hands out in-memory connections to a `MemoryServerTransport`
+#[derive(Clone)]
+pub struct MemoryListener {
+    incoming: IncomingConnections,
+}
+
+impl MemoryListener {
+    /// Opens a connection the server picks up on its next update
+    pub fn connect(
+        &self,
+        client_id: u64,
+        user_data: [u8; NETCODE_USER_DATA_BYTES],
+    ) -> MemoryClientTransport {
+        let connection = SharedConnection::default();
+        self.incoming
+            .lock()
+            .unwrap()
+            .push((client_id, user_data, connection.clone()));
+        MemoryClientTransport { connection }
+    }
+}
+
+/// A server whose clients live in the same process, with messages passed straight through
+/// memory
+#[derive(Default)]
+pub struct MemoryServerTransport {
+    incoming: IncomingConnections,
+    clients: HashMap<u64, SharedConnection>,
+    events: VecDeque<ServerEvent>,
+}
+
+impl MemoryServerTransport {
+    pub fn listener(&self) -> MemoryListener {
+        MemoryListener {
+            incoming: self.incoming.clone(),
+        }
+    }
+
+    fn connection(&self, client_id: u64) -> Option<MutexGuard<MemoryConnection>> {
+        self.clients
+            .get(&client_id)
+            .map(|connection| connection.lock().unwrap())
+    }
+
+    fn push_to_client(&self, client_id: u64, channel: u8, message: Vec<u8>) {
+        if let Some(mut connection) = self.connection(client_id) {
+            connection
+                .to_client
+                .entry(channel)
+                .or_default()
+                .push_back(message);
+        }
+    }
+}
+
+impl ServerTransport for MemoryServerTransport {
+    fn update(&mut self, _: Duration) -> Result<(), Box<dyn Error>> {
+        // dropped connections go first so a client reconnecting with the same id keeps its new one
+        let disconnected: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, connection)| connection.lock().unwrap().disconnected)
+            .map(|(&client_id, _)| client_id)
+            .collect();
+        for client_id in disconnected {
+            self.clients.remove(&client_id);
+            self.events
+                .push_back(ServerEvent::ClientDisconnected(client_id));
+        }
+
+        let incoming: Vec<_> = self.incoming.lock().unwrap().drain(..).collect();
+        for (client_id, user_data, connection) in incoming {
+            self.clients.insert(client_id, connection);
+            self.events
+                .push_back(ServerEvent::ClientConnected(client_id, Box::new(user_data)));
+        }
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        self.push_to_client(client_id, channel.into(), message);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        let channel = channel.into();
+        for &client_id in self.clients.keys() {
+            self.push_to_client(client_id, channel, message.clone());
+        }
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        except_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        let channel = channel.into();
+        for &client_id in self.clients.keys().filter(|&&id| id != except_id) {
+            self.push_to_client(client_id, channel, message.clone());
+        }
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.connection(client_id)?
+            .to_server
+            .get_mut(&channel.into())?
+            .pop_front()
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        self.clients.keys().copied().collect()
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        if let Some(connection) = self.clients.remove(&client_id) {
+            connection.lock().unwrap().disconnected = true;
+            self.events
+                .push_back(ServerEvent::ClientDisconnected(client_id));
+        }
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        self.clients
+            .contains_key(&client_id)
+            .then(NetworkInfo::default)
+    }
+
+    fn client_addr(&self, _client_id: u64) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// A UDP server for remote players with the host's own client connected in memory
+pub struct ListenServerTransport {
+    pub remote: RenetServer,
+    pub local: MemoryServerTransport,
+    local_clients: HashSet<u64>,
+}
+
+impl ListenServerTransport {
+    pub fn new(remote: RenetServer, local: MemoryServerTransport) -> Self {
+        Self {
+            remote,
+            local,
+            local_clients: HashSet::default(),
+        }
+    }
+
+    fn transport(&mut self, client_id: u64) -> &mut dyn ServerTransport {
+        if self.local_clients.contains(&client_id) {
+            &mut self.local
+        } else {
+            &mut self.remote
+        }
+    }
+}
+
+impl ServerTransport for ListenServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        ServerTransport::update(&mut self.local, duration)?;
+        self.local_clients = self.local.clients_id().into_iter().collect();
+        ServerTransport::update(&mut self.remote, duration)
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.local
+            .get_event()
+            .or_else(|| ServerTransport::get_event(&mut self.remote))
+    }
+
+    fn send_packets(&mut self) -> Result<(), Box<dyn Error>> {
+        ServerTransport::send_packets(&mut self.remote)
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: DefaultChannel, message: Vec<u8>) {
+        self.transport(client_id)
+            .send_message(client_id, channel, message);
+    }
+
+    fn broadcast_message(&mut self, channel: DefaultChannel, message: Vec<u8>) {
+        ServerTransport::broadcast_message(&mut self.local, channel, message.clone());
+        ServerTransport::broadcast_message(&mut self.remote, channel, message);
+    }
+
+    fn broadcast_message_except(
+        &mut self,
+        client_id: u64,
+        channel: DefaultChannel,
+        message: Vec<u8>,
+    ) {
+        ServerTransport::broadcast_message_except(
+            &mut self.local,
+            client_id,
+            channel,
+            message.clone(),
+        );
+        ServerTransport::broadcast_message_except(&mut self.remote, client_id, channel, message);
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: DefaultChannel) -> Option<Vec<u8>> {
+        self.transport(client_id)
+            .receive_message(client_id, channel)
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        let mut clients = self.local.clients_id();
+        clients.extend(ServerTransport::clients_id(&self.remote));
+        clients
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        self.transport(client_id).disconnect(client_id);
+    }
+
+    fn network_info(&self, client_id: u64) -> Option<NetworkInfo> {
+        self.local
+            .network_info(client_id)
+            .or_else(|| ServerTransport::network_info(&self.remote, client_id))
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        self.local
+            .client_addr(client_id)
+            .or_else(|| ServerTransport::client_addr(&self.remote, client_id))
+    }
+}
@@ -0,0 +1,113 @@
+use super::{
+    character::CharacterEntity,
+    client::{ClientResource, LocalNetworkedEntity},
+    networking::{ClientMessages, PortalId},
+    teams::PortalOwner,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_voxel_engine::Velocity;
+use renet::DefaultChannel;
+
+// how close to a portal an entity has to be on both sides of a jump for it to count as
+// going through
+const PORTAL_RANGE: f32 = 3.0;
+// extra distance on top of the entity's speed that it can move in a frame without it
+// being a jump
+const JUMP_MARGIN: f32 = 1.0;
+
+/// Finds the portal nearest to a position within `PORTAL_RANGE`
+pub fn nearest_portal(
+    portals: &Query<(&PortalOwner, &GlobalTransform)>,
+    local_client_id: u64,
+    position: Vec3,
+) -> Option<PortalId> {
+    portals
+        .iter()
+        .map(|(owner, transform)| {
+            let id = PortalId {
+                owner: owner.client_id.unwrap_or(local_client_id),
+                index: owner.index,
+            };
+            (id, transform.translation().distance(position))
+        })
+        .filter(|(_, distance)| *distance < PORTAL_RANGE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(id, _)| id)
+}
+
+pub fn portal_position(
+    portals: &Query<(&PortalOwner, &GlobalTransform)>,
+    local_client_id: u64,
+    id: PortalId,
+) -> Option<Vec3> {
+    portals
+        .iter()
+        .find(|(owner, _)| {
+            owner.client_id.unwrap_or(local_client_id) == id.owner && owner.index == id.index
+        })
+        .map(|(_, transform)| transform.translation())
+}
+
+/// Tells the server when the local character or one of our networked entities goes
+/// through a portal, so other clients don't interpolate it across the map
+pub fn detect_portal_traversals(
+    mut client_resource: ResMut<ClientResource>,
+    moving: Query<
+        (
+            Entity,
+            &Transform,
+            &Velocity,
+            Option<&CharacterEntity>,
+            Option<&LocalNetworkedEntity>,
+        ),
+        Or<(With<CharacterEntity>, With<LocalNetworkedEntity>)>,
+    >,
+    portals: Query<(&PortalOwner, &GlobalTransform)>,
+    mut previous_positions: Local<HashMap<Entity, Vec3>>,
+    time: Res<Time>,
+) {
+    let client = match (*client_resource).as_mut() {
+        Some(client) => client,
+        None => return,
+    };
+
+    let mut positions = HashMap::default();
+    for (entity, transform, velocity, character, networked) in moving.iter() {
+        let position = transform.translation;
+        positions.insert(entity, position);
+        let previous = match previous_positions.get(&entity) {
+            Some(previous) => *previous,
+            None => continue,
+        };
+
+        // the engine rotates the velocity of anything going through a portal, but portals
+        // facing opposite ways don't rotate so big jumps count too
+        let rotated = velocity.portal_rotation != Quat::IDENTITY;
+        let jumped = previous.distance(position)
+            > velocity.velocity.length() * time.delta_seconds() * 2.0 + JUMP_MARGIN;
+        if !rotated && !jumped {
+            continue;
+        }
+
+        let from = nearest_portal(&portals, client.client_id, previous);
+        let to = nearest_portal(&portals, client.client_id, position);
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) if to == from.partner() => (from, to),
+            // e.g. a respawn, which the server already tells everyone about
+            _ => continue,
+        };
+
+        let entity = if character.is_some() {
+            None
+        } else if networked.is_some() && client.local_networked_entitys.contains(&entity) {
+            Some(entity)
+        } else {
+            continue;
+        };
+        client.client.send(
+            DefaultChannel::Reliable,
+            &ClientMessages::PortalTraversal { entity, from, to },
+        );
+    }
+    *previous_positions = positions;
+}
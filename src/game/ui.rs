@@ -1,4 +1,13 @@
-use super::{character::CharacterEntity, Velocity};
+use super::{
+    character::CharacterEntity,
+    client::ClientResource,
+    game_mode::team_name,
+    network_stats::{NetworkSample, NetworkStats},
+    networking::ClientMessages,
+    portals::PortalAccess,
+    server::{OrphanPolicy, ServerResource},
+    Velocity,
+};
 use crate::GameState;
 use bevy::{
     core_pipeline::{bloom::BloomSettings, fxaa::Fxaa, tonemapping::Tonemapping},
@@ -6,10 +15,16 @@ use bevy::{
     prelude::*,
 };
 use bevy_egui::{
-    egui::{self, Slider},
+    egui::{
+        self,
+        plot::{Legend, Line, Plot},
+        Slider,
+    },
     EguiContext,
 };
 use bevy_voxel_engine::*;
+use renet::DefaultChannel;
+use std::{collections::BTreeMap, hash::Hash};
 
 pub struct UiPlugin;
 
@@ -34,6 +49,9 @@ fn ui_system(
     mut denoise_pass_data: ResMut<DenoiseSettings>,
     diagnostics: Res<Diagnostics>,
     mut game_state: ResMut<State<GameState>>,
+    mut server_resource: ResMut<ServerResource>,
+    mut client_resource: ResMut<ClientResource>,
+    time: Res<Time>,
 ) {
     egui::Window::new("Settings")
         .anchor(egui::Align2::RIGHT_TOP, [-5.0, 5.0])
@@ -139,6 +157,147 @@ fn ui_system(
                 ui.checkbox(&mut render_graph_settings.trace, "trace");
                 ui.checkbox(&mut render_graph_settings.denoise, "denoise");
             });
+            if let Some(client) = (*client_resource).as_mut() {
+                let teams = client
+                    .game_mode
+                    .as_ref()
+                    .map(|(state, _)| state.teams)
+                    .unwrap_or(0);
+                if teams > 0 {
+                    let current = client.team(client.client_id);
+                    ui.horizontal(|ui| {
+                        ui.label("Team:");
+                        for team in 0..teams {
+                            let selected = current == Some(team);
+                            if ui.selectable_label(selected, team_name(team)).clicked() && !selected
+                            {
+                                client.client.send(
+                                    DefaultChannel::Reliable,
+                                    &ClientMessages::SwitchTeam { team },
+                                );
+                            }
+                        }
+                    });
+                }
+            }
+            if let Some(client) = (*client_resource).as_ref() {
+                if let Some(stats) = client.client.stats() {
+                    ui.collapsing("Network", |ui| {
+                        let networked_entities = client
+                            .networked_entitys
+                            .values()
+                            .map(|entities| entities.len())
+                            .sum::<usize>()
+                            + client.local_networked_entitys.len();
+                        network_stats_ui(ui, "client", stats, networked_entities);
+                    });
+                }
+            }
+            if let Some(conditioner) = (*client_resource)
+                .as_mut()
+                .and_then(|client| client.client.conditioner())
+            {
+                ui.collapsing("Network conditions", |ui| {
+                    let conditions = &mut conditioner.conditions;
+                    ui.add(Slider::new(&mut conditions.latency, 0.0..=1.0).text("Latency (s)"));
+                    ui.add(Slider::new(&mut conditions.jitter, 0.0..=0.5).text("Jitter (s)"));
+                    ui.add(Slider::new(&mut conditions.loss, 0.0..=1.0).text("Loss"));
+                    ui.add(Slider::new(&mut conditions.duplication, 0.0..=1.0).text("Duplication"));
+                    ui.add(Slider::new(&mut conditions.reordering, 0.0..=1.0).text("Reordering"));
+                    if ui.button("Reset").clicked() {
+                        *conditions = default();
+                    }
+                });
+            }
+            if let Some(server) = (*server_resource).as_mut() {
+                ui.collapsing("Game rules", |ui| {
+                    let mut changed = ui
+                        .checkbox(&mut server.rules.friendly_fire, "Friendly fire")
+                        .changed();
+                    egui::ComboBox::from_label("Portals")
+                        .selected_text(server.rules.portal_access.name())
+                        .show_ui(ui, |ui| {
+                            for portal_access in PortalAccess::ALL {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut server.rules.portal_access,
+                                        portal_access,
+                                        portal_access.name(),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    if changed {
+                        server.broadcast_game_mode_state(time.elapsed_seconds());
+                    }
+                });
+                ui.collapsing("Server network", |ui| {
+                    let server = &*server;
+                    for (client_id, player) in server.players.iter() {
+                        if let Some(stats) = server.server.stats(*client_id) {
+                            ui.collapsing(player.username.as_str(), |ui| {
+                                let networked_entities = server.networked_entity_count(*client_id);
+                                network_stats_ui(ui, client_id, stats, networked_entities);
+                                ui.label(format!(
+                                    "Pending updates: {}",
+                                    server.pending_updates(*client_id)
+                                ));
+                                ui.label(format!(
+                                    "Relevant entities: {}",
+                                    server.relevant_count(*client_id)
+                                ));
+                            });
+                        }
+                    }
+                    ui.label(format!(
+                        "Players that can reconnect: {}",
+                        server.suspended_count()
+                    ));
+                });
+                ui.collapsing("Replication", |ui| {
+                    let replication = &mut server.replication;
+                    ui.checkbox(&mut replication.enabled, "Bandwidth budget");
+                    ui.add(
+                        Slider::new(&mut replication.budget, 1_000.0..=128_000.0)
+                            .text("Budget per client (bytes/s)"),
+                    );
+                    egui::ComboBox::from_label("Orphaned entities")
+                        .selected_text(server.orphan_policy.name())
+                        .show_ui(ui, |ui| {
+                            for orphan_policy in OrphanPolicy::ALL {
+                                ui.selectable_value(
+                                    &mut server.orphan_policy,
+                                    orphan_policy,
+                                    orphan_policy.name(),
+                                );
+                            }
+                        });
+                    ui.add(
+                        Slider::new(&mut server.sessions.grace, 0.0..=120.0)
+                            .text("Reconnect window (s)"),
+                    );
+                    let interest = &mut server.interest;
+                    ui.checkbox(&mut interest.enabled, "Interest management");
+                    ui.add(
+                        Slider::new(&mut interest.distance, 8.0..=256.0)
+                            .text("Relevance distance (m)"),
+                    );
+                });
+                ui.collapsing("Lag compensation", |ui| {
+                    let lag_compensation = &mut server.lag_compensation;
+                    ui.checkbox(&mut lag_compensation.enabled, "Enabled");
+                    ui.add(
+                        Slider::new(&mut lag_compensation.max_rewind, 0.0..=1.0)
+                            .text("Max rewind (s)"),
+                    );
+                    // the rewinding happens on the server, so only the host can draw them
+                    ui.checkbox(
+                        &mut lag_compensation.show_hitboxes,
+                        "Show rewound hitboxes (host only)",
+                    )
+                    .on_hover_text("Drawn on this machine only, other players don't see them");
+                });
+            }
             if ui.button("Disconnect").clicked() {
                 game_state.set(GameState::Menu).unwrap();
             }
@@ -156,3 +315,74 @@ fn ui_system(
     //     }
     // });
 }
+
+/// Connection numbers, a graph of their recent history and messages per second by type
+fn network_stats_ui(
+    ui: &mut egui::Ui,
+    id: impl Hash,
+    stats: &NetworkStats,
+    networked_entities: usize,
+) {
+    if let Some(sample) = stats.latest() {
+        ui.label(format!("RTT: {:.0} ms", sample.rtt));
+        ui.label(format!("Packet loss: {:.1}%", sample.packet_loss * 100.0));
+        ui.label(format!(
+            "Sent: {:.1} kbps, received: {:.1} kbps",
+            sample.sent_kbps, sample.received_kbps
+        ));
+        // renet's own queues aren't visible, so this is only what our layers hold back
+        ui.label(format!(
+            "Held back by batching/conditioner: {}",
+            sample.queued_messages
+        ));
+    }
+    ui.label(format!("Networked entities: {}", networked_entities));
+
+    let line = |value: fn(&NetworkSample) -> f32, name: &str| {
+        let points: Vec<[f64; 2]> = stats
+            .samples
+            .iter()
+            .map(|sample| [sample.time as f64, value(sample) as f64])
+            .collect();
+        Line::new(points).name(name)
+    };
+    Plot::new((&id, "graph"))
+        .height(100.0)
+        .include_y(0.0)
+        .legend(Legend::default())
+        .show(ui, |plot_ui| {
+            plot_ui.line(line(|sample| sample.rtt, "RTT (ms)"));
+            plot_ui.line(line(|sample| sample.sent_kbps, "Sent (kbps)"));
+            plot_ui.line(line(|sample| sample.received_kbps, "Received (kbps)"));
+            plot_ui.line(line(
+                |sample| sample.messages_sent as f32,
+                "Sent (messages/s)",
+            ));
+            plot_ui.line(line(
+                |sample| sample.messages_received as f32,
+                "Received (messages/s)",
+            ));
+        });
+
+    let mut messages: BTreeMap<&str, (u32, u32)> = BTreeMap::new();
+    for (kind, count) in stats.sent_per_second.iter() {
+        messages.entry(*kind).or_default().0 = *count;
+    }
+    for (kind, count) in stats.received_per_second.iter() {
+        messages.entry(*kind).or_default().1 = *count;
+    }
+    egui::Grid::new((&id, "messages"))
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Message");
+            ui.strong("Sent/s");
+            ui.strong("Received/s");
+            ui.end_row();
+            for (kind, (sent, received)) in messages {
+                ui.label(kind);
+                ui.label(sent.to_string());
+                ui.label(received.to_string());
+                ui.end_row();
+            }
+        });
+}
@@ -0,0 +1,240 @@
+use super::{networking::ServerMessages, server::Server};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_voxel_engine::VOXELS_PER_METER;
+use renet::DefaultChannel;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+// largest model MagicaVoxel saves along each axis
+const MAX_MODEL_SIZE: i32 = 256;
+
+/// The server's copy of the voxel world, read straight from the map's .vox file so it
+/// doesn't depend on the renderer
+pub struct VoxelWorld {
+    size: IVec3,
+    // palette index of every voxel, zero being empty
+    voxels: Vec<u8>,
+    // voxels changed since the map was loaded and what they are now
+    edits: HashMap<IVec3, u8>,
+}
+
+/// A voxel set to a material, zero removing it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelEdit {
+    pub voxel: IVec3,
+    pub material: u8,
+}
+
+pub struct RayHit {
+    pub voxel: IVec3,
+    // where the ray entered the voxel, in meters
+    pub position: Vec3,
+    pub normal: IVec3,
+}
+
+impl Default for VoxelWorld {
+    fn default() -> Self {
+        Self {
+            size: IVec3::ZERO,
+            voxels: Vec::new(),
+            edits: HashMap::default(),
+        }
+    }
+}
+
+impl VoxelWorld {
+    pub fn load(map: &str) -> Self {
+        let path = format!("assets/{}.vox", map);
+        match fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| Self::parse(&bytes))
+        {
+            Ok(world) => world,
+            Err(e) => {
+                error!("{}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Reads the first model of a MagicaVoxel file
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[0..4] != b"VOX " {
+            return Err("not a .vox file".to_string());
+        }
+
+        let read_i32 = |offset: usize| -> Result<i32, String> {
+            bytes
+                .get(offset..offset + 4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| "unexpected end of file".to_string())
+        };
+        let read_len = |offset: usize| -> Result<usize, String> {
+            usize::try_from(read_i32(offset)?).map_err(|_| "negative length".to_string())
+        };
+
+        // skip the header and the MAIN chunk header, everything else is a child of MAIN
+        let mut offset = 8 + 12;
+        let mut size = None;
+        while offset + 12 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let content_size = read_len(offset + 4)?;
+            let content = offset + 12;
+
+            match id {
+                b"SIZE" if size.is_none() => {
+                    // MagicaVoxel is z up, the engine is y up
+                    let model_size = IVec3::new(
+                        read_i32(content)?,
+                        read_i32(content + 8)?,
+                        read_i32(content + 4)?,
+                    );
+                    if model_size.cmplt(IVec3::ONE).any()
+                        || model_size.cmpgt(IVec3::splat(MAX_MODEL_SIZE)).any()
+                    {
+                        return Err(format!("invalid model size {}", model_size));
+                    }
+                    size = Some(model_size);
+                }
+                b"XYZI" => {
+                    let size = size.ok_or("XYZI chunk before SIZE")?;
+                    let mut world = Self {
+                        size,
+                        voxels: vec![0; (size.x * size.y * size.z) as usize],
+                        edits: HashMap::default(),
+                    };
+                    let count = read_len(content)?;
+                    for i in 0..count {
+                        let voxel = bytes
+                            .get(content + 4 + i * 4..content + 8 + i * 4)
+                            .ok_or("unexpected end of file")?;
+                        let position =
+                            IVec3::new(voxel[0] as i32, voxel[2] as i32, voxel[1] as i32);
+                        if let Some(index) = world.index(position) {
+                            world.voxels[index] = voxel[3];
+                        }
+                    }
+                    return Ok(world);
+                }
+                _ => {}
+            }
+
+            // skip the content and any children
+            offset = content + content_size + read_len(offset + 8)?;
+        }
+
+        Err("no voxel data".to_string())
+    }
+
+    fn index(&self, voxel: IVec3) -> Option<usize> {
+        if voxel.cmplt(IVec3::ZERO).any() || voxel.cmpge(self.size).any() {
+            return None;
+        }
+        Some((voxel.x + voxel.y * self.size.x + voxel.z * self.size.x * self.size.y) as usize)
+    }
+
+    /// Material of a voxel, zero for empty or outside the world
+    pub fn get(&self, voxel: IVec3) -> u8 {
+        self.index(voxel)
+            .map(|index| self.voxels[index])
+            .unwrap_or(0)
+    }
+
+    /// Changes a voxel and logs it, returning false for one outside the world
+    pub fn set(&mut self, voxel: IVec3, material: u8) -> bool {
+        match self.index(voxel) {
+            Some(index) => {
+                self.voxels[index] = material;
+                self.edits.insert(voxel, material);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every voxel changed since the map was loaded, once each with its latest material
+    pub fn edits(&self) -> impl Iterator<Item = VoxelEdit> + '_ {
+        self.edits
+            .iter()
+            .map(|(&voxel, &material)| VoxelEdit { voxel, material })
+    }
+
+    /// The voxel containing a point in meters, the world being centered on the origin
+    pub fn voxel_at(&self, position: Vec3) -> IVec3 {
+        (position * VOXELS_PER_METER).floor().as_ivec3() + self.size / 2
+    }
+
+    /// Steps through the world voxel by voxel until the ray hits something
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        // work in voxel units
+        let origin_voxels = origin * VOXELS_PER_METER + (self.size / 2).as_vec3();
+        let max_distance = max_distance * VOXELS_PER_METER;
+
+        let mut voxel = origin_voxels.floor().as_ivec3();
+        let step = direction.signum().as_ivec3();
+        let delta = (1.0 / direction).abs();
+        let next_boundary = Vec3::select(
+            direction.cmpgt(Vec3::ZERO),
+            voxel.as_vec3() + 1.0 - origin_voxels,
+            origin_voxels - voxel.as_vec3(),
+        );
+        // axes the ray doesn't move along never reach a boundary
+        let mut t_max = Vec3::select(
+            direction.cmpeq(Vec3::ZERO),
+            Vec3::splat(f32::INFINITY),
+            next_boundary * delta,
+        );
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+
+        while distance <= max_distance {
+            if self.get(voxel) != 0 && normal != IVec3::ZERO {
+                let position = (origin_voxels + direction * distance - (self.size / 2).as_vec3())
+                    / VOXELS_PER_METER;
+                return Some(RayHit {
+                    voxel,
+                    position,
+                    normal,
+                });
+            }
+
+            // advance along whichever axis reaches its next boundary first
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            distance = t_max[axis];
+            t_max[axis] += delta[axis];
+            voxel[axis] += step[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+
+        None
+    }
+}
+
+impl Server {
+    /// Changes voxels of the world on the server and every client, the ones outside the
+    /// world being dropped
+    pub fn edit_voxels(&mut self, edits: Vec<VoxelEdit>) {
+        let edits: Vec<VoxelEdit> = edits
+            .into_iter()
+            .filter(|edit| self.world.set(edit.voxel, edit.material))
+            .collect();
+        if !edits.is_empty() {
+            self.server.broadcast(
+                DefaultChannel::Reliable,
+                &ServerMessages::VoxelsEdited { edits },
+            );
+        }
+    }
+}
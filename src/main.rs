@@ -1,20 +1,59 @@
-use bevy::prelude::*;
+use bevy::{app::ScheduleRunnerSettings, log::LogPlugin, prelude::*};
 use bevy_egui::EguiPlugin;
+use bevy_networking::{
+    game::{
+        self,
+        commands::ConsolePlugin,
+        game_mode::GameModeKind,
+        link_conditioner::LinkConditionerSettings,
+        server::{Server, ServerPlugin, ServerResource},
+    },
+    menu, splash, GameState,
+};
+use std::time::Duration;
 
-mod game;
-mod menu;
-mod splash;
+fn main() {
+    // `--dedicated [bind ip] [--mode <mode>]` runs a headless server that takes commands
+    // from stdin
+    let args: Vec<String> = std::env::args().collect();
+    // `--latency <ms> --jitter <ms> --loss <%> --duplicate <%> --reorder <%> [--seed <n>]`
+    // simulate a bad network
+    let link_conditions = match LinkConditionerSettings::from_args(&args) {
+        Ok(link_conditions) => link_conditions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-#[derive(Clone, Eq, PartialEq, Debug, Hash)]
-enum GameState {
-    Splash,
-    Menu,
-    Game,
-}
+    if let Some(index) = args.iter().position(|arg| arg == "--dedicated") {
+        let bind_ip = args
+            .get(index + 1)
+            .filter(|arg| !arg.starts_with("--"))
+            .cloned()
+            .unwrap_or_else(|| "127.0.0.1:1234".to_string());
+        let game_mode = match args.iter().position(|arg| arg == "--mode") {
+            Some(index) => match args
+                .get(index + 1)
+                .and_then(|mode| GameModeKind::parse(mode))
+            {
+                Some(game_mode) => game_mode,
+                None => {
+                    eprintln!("--mode must be one of sandbox, ffa or tdm");
+                    return;
+                }
+            },
+            None => GameModeKind::Sandbox,
+        };
+        dedicated_server(bind_ip, game_mode, link_conditions);
+        return;
+    }
 
-fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
+    let mut app = App::new();
+    if let Some(link_conditions) = link_conditions {
+        app.insert_resource(link_conditions);
+    }
+    app.add_plugins(DefaultPlugins)
         .add_state(GameState::Menu)
         .add_state_to_stage(CoreStage::PreUpdate, GameState::Menu)
         .add_state_to_stage(CoreStage::PostUpdate, GameState::Menu)
@@ -25,9 +64,30 @@ fn main() {
         .run();
 }
 
-// Generic system that takes a component as a parameter, and will despawn all entities with that component
-fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
-    for entity in &to_despawn {
-        commands.entity(entity).despawn_recursive();
+fn dedicated_server(
+    bind_ip: String,
+    game_mode: GameModeKind,
+    link_conditions: Option<LinkConditionerSettings>,
+) {
+    let mut server = Server::new(bind_ip, "Dedicated server".to_string());
+    server.set_game_mode(game_mode);
+    if let (Some(link_conditions), Some(conditioner)) =
+        (link_conditions, server.server.conditioner())
+    {
+        link_conditions.apply(conditioner);
     }
+
+    App::new()
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / 60.0,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(LogPlugin::default())
+        .add_state(GameState::Game)
+        .add_state_to_stage(CoreStage::PreUpdate, GameState::Game)
+        .add_state_to_stage(CoreStage::PostUpdate, GameState::Game)
+        .add_plugin(ServerPlugin)
+        .add_plugin(ConsolePlugin)
+        .insert_resource(ServerResource(Some(server)))
+        .run();
 }
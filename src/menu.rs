@@ -2,6 +2,7 @@ use crate::{
     despawn_screen,
     game::{
         client::{Client, ClientResource},
+        game_mode::GameModeKind,
         server::{Server, ServerResource},
     },
     GameState,
@@ -34,6 +35,7 @@ struct MenuState {
     lobby_ip: String,
     lobby_name: String,
     bind_ip: String,
+    game_mode: GameModeKind,
     error: Option<String>,
 }
 
@@ -44,6 +46,7 @@ impl Default for MenuState {
             lobby_ip: "127.0.0.1:1234".to_string(),
             lobby_name: "Epic Lobby".to_string(),
             bind_ip: "127.0.0.1:1234".to_string(),
+            game_mode: GameModeKind::Sandbox,
             error: None,
         }
     }
@@ -83,12 +86,16 @@ fn menu(
                                 menu_state.error =
                                     Some("Nick or Lobby ip can't be empty".to_owned());
                             } else {
-                                *client = ClientResource(Some(Client::new(
+                                match Client::new(
                                     menu_state.lobby_ip.clone(),
                                     menu_state.username.clone(),
-                                )));
-
-                                game_state.set(GameState::Game).unwrap();
+                                ) {
+                                    Ok(new_client) => {
+                                        *client = ClientResource(Some(new_client));
+                                        game_state.set(GameState::Game).unwrap();
+                                    }
+                                    Err(e) => menu_state.error = Some(e),
+                                }
                             }
                         }
                     });
@@ -105,22 +112,43 @@ fn menu(
                         ui.text_edit_singleline(&mut menu_state.bind_ip)
                     });
 
+                    ui.horizontal(|ui| {
+                        ui.label("Mode:");
+                        egui::ComboBox::from_id_source("game_mode")
+                            .selected_text(menu_state.game_mode.name())
+                            .show_ui(ui, |ui| {
+                                for kind in GameModeKind::ALL {
+                                    ui.selectable_value(
+                                        &mut menu_state.game_mode,
+                                        kind,
+                                        kind.name(),
+                                    );
+                                }
+                            });
+                    });
+
                     ui.vertical_centered_justified(|ui| {
                         if ui.button("Host").clicked() {
                             if menu_state.username.is_empty() || menu_state.lobby_name.is_empty() {
                                 menu_state.error =
                                     Some("Nick or Lobby name can't be empty".to_owned());
                             } else {
-                                *client = ClientResource(Some(Client::new(
+                                // the host's own client skips the socket
+                                match Server::listen(
                                     menu_state.bind_ip.clone(),
                                     menu_state.username.clone(),
-                                )));
-                                *server = ServerResource(Some(Server::new(
-                                    menu_state.bind_ip.clone(),
-                                    menu_state.lobby_name.clone(),
-                                )));
-
-                                game_state.set(GameState::Game).unwrap();
+                                ) {
+                                    Ok((mut new_server, host)) => {
+                                        new_server.admins.insert(host.client_id);
+                                        new_server.set_game_mode(menu_state.game_mode);
+
+                                        *client = ClientResource(Some(host));
+                                        *server = ServerResource(Some(new_server));
+
+                                        game_state.set(GameState::Game).unwrap();
+                                    }
+                                    Err(e) => menu_state.error = Some(e),
+                                }
                             }
                         }
                     });
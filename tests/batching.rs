@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+use bevy_networking::game::{
+    batching::{BatchedClientTransport, BatchedServerTransport},
+    networking::ClientMessages,
+    transport::{ClientTransport, MemoryServerTransport, ServerTransport},
+};
+use renet::{DefaultChannel, NETCODE_USER_DATA_BYTES};
+use std::time::Duration;
+
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+fn update_player(x: f32) -> ClientMessages {
+    ClientMessages::UpdatePlayer {
+        position: Vec3::X * x,
+        velocity: Vec3::ZERO,
+        respawn: 0,
+    }
+}
+
+fn send_tick(client: &mut dyn ClientTransport) {
+    client.update(FRAME_TIME).unwrap();
+    for x in 0..3 {
+        client.send(DefaultChannel::Reliable, &update_player(x as f32));
+    }
+    client.send(
+        DefaultChannel::Reliable,
+        &ClientMessages::ChatMessage {
+            message: "hi".to_string(),
+        },
+    );
+    client.send(DefaultChannel::Reliable, &update_player(3.0));
+    client.send_packets().unwrap();
+}
+
+#[test]
+fn tick_is_sent_as_one_frame() {
+    let mut server = MemoryServerTransport::default();
+    let mut client = BatchedClientTransport::new(Box::new(
+        server.listener().connect(1, [0; NETCODE_USER_DATA_BYTES]),
+    ));
+    send_tick(&mut client);
+
+    server.update(FRAME_TIME).unwrap();
+    let frame = server.receive_message(1, DefaultChannel::Reliable).unwrap();
+    assert!(server
+        .receive_message(1, DefaultChannel::Reliable)
+        .is_none());
+    let messages: Vec<Vec<u8>> = bincode::deserialize(&frame).unwrap();
+    assert_eq!(messages.len(), 2);
+}
+
+#[test]
+fn superseded_updates_are_coalesced() {
+    let memory = MemoryServerTransport::default();
+    let mut client = BatchedClientTransport::new(Box::new(
+        memory.listener().connect(1, [0; NETCODE_USER_DATA_BYTES]),
+    ));
+    let mut server = BatchedServerTransport::new(Box::new(memory));
+    send_tick(&mut client);
+
+    server.update(FRAME_TIME).unwrap();
+    let mut received = Vec::new();
+    while let Some(message) = server.receive(1, DefaultChannel::Reliable) {
+        received.push(message);
+    }
+
+    // only the last position is left, in the slot of the first update before the chat message
+    assert_eq!(received.len(), 2);
+    match received[0] {
+        ClientMessages::UpdatePlayer { position, .. } => assert_eq!(position, Vec3::X * 3.0),
+        _ => panic!("expected an UpdatePlayer"),
+    }
+    assert_eq!(received[1].kind(), "ChatMessage");
+}
@@ -0,0 +1,10 @@
+use bevy_networking::game::commands::redact;
+
+#[test]
+fn secret_command_arguments_are_redacted() {
+    assert_eq!(redact("/password hunter2"), "/password <hidden>");
+    assert_eq!(redact("  /password  hunter2 "), "/password <hidden>");
+    assert_eq!(redact("/password"), "/password");
+    assert_eq!(redact("/kick bob"), "/kick bob");
+    assert_eq!(redact("/passwords hunter2"), "/passwords hunter2");
+}
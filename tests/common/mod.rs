@@ -0,0 +1,284 @@
+//! Runs a server and any number of clients as headless apps in one process, talking
+//! straight through memory or over loopback UDP, so tests can step them frame by frame
+#![allow(dead_code)]
+
+use bevy::{asset::AssetPlugin, prelude::*, time::TimePlugin};
+use bevy_networking::{
+    game::{
+        client::{Client, ClientResource, LocalNetworkedEntity},
+        link_conditioner::LinkConditions,
+        networking::NetworkedEntityType,
+        server::{Server, ServerPlugin, ServerResource},
+        transport::{MemoryListener, MemoryServerTransport},
+        voxel_world::VoxelWorld,
+        HeadlessClientPlugin,
+    },
+    GameState,
+};
+use bevy_voxel_engine::{Velocity, VOXELS_PER_METER};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+// frames a message has to get from one client to another through the server
+pub const MAX_TICKS: usize = 60;
+// every frame advances each app's clock by this much, over UDP it is also slept for as
+// renet works off the wall clock
+const FRAME_TIME: Duration = Duration::from_millis(16);
+// voxels along each side of the worlds built by `wall`
+pub const WORLD_SIZE: i32 = 128;
+pub const WALL_MATERIAL: u8 = 1;
+
+enum Connect {
+    Udp(SocketAddr),
+    Memory(MemoryListener),
+}
+
+pub struct Harness {
+    pub server: App,
+    pub clients: Vec<App>,
+    connect: Connect,
+}
+
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>())
+        .insert_resource(Time::default())
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_state(GameState::Game)
+        .add_state_to_stage(CoreStage::PreUpdate, GameState::Game)
+        .add_state_to_stage(CoreStage::PostUpdate, GameState::Game);
+    app
+}
+
+fn update(app: &mut App) {
+    let mut time = app.world.resource_mut::<Time>();
+    let now = time
+        .last_update()
+        .map_or_else(Instant::now, |last_update| last_update + FRAME_TIME);
+    time.update_with_instant(now);
+    app.update();
+}
+
+impl Harness {
+    /// Starts a server on an ephemeral port and connects `clients` clients to it over UDP,
+    /// which is slow and depends on timing so only smoke tests use it
+    pub fn udp(clients: usize) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        Self::start(
+            Server::with_socket(socket, server_addr),
+            Connect::Udp(server_addr),
+            clients,
+        )
+    }
+
+    /// Starts a server and connects `clients` clients to it without sockets, so nothing
+    /// depends on timing
+    pub fn new(clients: usize) -> Self {
+        let transport = MemoryServerTransport::default();
+        let listener = transport.listener();
+        Self::start(
+            Server::with_transport(Box::new(transport)),
+            Connect::Memory(listener),
+            clients,
+        )
+    }
+
+    fn start(server: Server, connect: Connect, clients: usize) -> Self {
+        let mut app = headless_app();
+        app.add_plugin(ServerPlugin)
+            .insert_resource(ServerResource(Some(server)));
+
+        let mut harness = Self {
+            server: app,
+            clients: Vec::new(),
+            connect,
+        };
+        for i in 0..clients {
+            harness.add_client(&format!("player{}", i));
+        }
+        harness
+    }
+
+    /// Connects another client, returning its index once the server has let it in
+    pub fn add_client(&mut self, username: &str) -> usize {
+        let client = match &self.connect {
+            Connect::Udp(server_addr) => {
+                Client::new(server_addr.to_string(), username.to_string()).unwrap()
+            }
+            Connect::Memory(listener) => Client::local(listener, username.to_string()).unwrap(),
+        };
+        let mut app = headless_app();
+        app.add_plugin(HeadlessClientPlugin)
+            .insert_resource(ClientResource(Some(client)));
+        self.clients.push(app);
+
+        let index = self.clients.len() - 1;
+        let client_id = self.client(index).client_id;
+        assert!(
+            self.step_until(120, |harness| harness
+                .server()
+                .players
+                .contains_key(&client_id)),
+            "{} never connected",
+            username
+        );
+        index
+    }
+
+    /// Disconnects a client and stops stepping it
+    pub fn remove_client(&mut self, index: usize) -> App {
+        let mut app = self.clients.remove(index);
+        if let Some(client) = app.world.resource_mut::<ClientResource>().as_mut() {
+            client.client.disconnect();
+        }
+        app
+    }
+
+    /// Where in-memory clients connect, for connecting by hand
+    pub fn listener(&self) -> &MemoryListener {
+        match &self.connect {
+            Connect::Memory(listener) => listener,
+            Connect::Udp(_) => panic!("Only in-memory harnesses have a listener"),
+        }
+    }
+
+    /// Runs one frame of the server and then every client
+    pub fn step(&mut self) {
+        if let Connect::Udp(_) = self.connect {
+            thread::sleep(FRAME_TIME);
+        }
+        update(&mut self.server);
+        for client in self.clients.iter_mut() {
+            update(client);
+        }
+    }
+
+    /// Steps until `condition` holds, giving up after `max_ticks` frames
+    pub fn step_until(&mut self, max_ticks: usize, condition: impl Fn(&Self) -> bool) -> bool {
+        self.ticks_until(max_ticks, condition).is_some()
+    }
+
+    /// How many frames it took for `condition` to hold
+    pub fn ticks_until(
+        &mut self,
+        max_ticks: usize,
+        condition: impl Fn(&Self) -> bool,
+    ) -> Option<usize> {
+        for tick in 1..=max_ticks {
+            self.step();
+            if condition(self) {
+                return Some(tick);
+            }
+        }
+        None
+    }
+
+    /// Simulates a bad network between a client and the server
+    pub fn condition_client(&mut self, index: usize, conditions: LinkConditions, seed: u64) {
+        let mut client_resource = self.clients[index].world.resource_mut::<ClientResource>();
+        let conditioner = client_resource
+            .as_mut()
+            .and_then(|client| client.client.conditioner())
+            .unwrap();
+        conditioner.conditions = conditions;
+        conditioner.reseed(seed);
+    }
+
+    pub fn server(&self) -> &Server {
+        self.server
+            .world
+            .resource::<ServerResource>()
+            .as_ref()
+            .unwrap()
+    }
+
+    pub fn client(&self, index: usize) -> &Client {
+        self.clients[index]
+            .world
+            .resource::<ClientResource>()
+            .as_ref()
+            .unwrap()
+    }
+
+    /// Spawns a bullet on a client, which it then sends to the server like any other
+    pub fn spawn_bullet(&mut self, index: usize, position: Vec3, velocity: Vec3) -> Entity {
+        self.spawn_bullet_of_type(index, 0, position, velocity)
+    }
+
+    pub fn spawn_bullet_of_type(
+        &mut self,
+        index: usize,
+        bullet_type: u32,
+        position: Vec3,
+        velocity: Vec3,
+    ) -> Entity {
+        self.clients[index]
+            .world
+            .spawn((
+                Transform::from_translation(position),
+                Velocity::new(velocity),
+                LocalNetworkedEntity {
+                    entity_type: NetworkedEntityType::Bullet(bullet_type),
+                },
+            ))
+            .id()
+    }
+
+    /// Whether a client has spawned an entity of `owner`
+    pub fn has_entity(&self, index: usize, owner: u64, entity: Entity) -> bool {
+        self.client(index)
+            .networked_entitys
+            .get(&owner)
+            .map_or(false, |entities| entities.contains_key(&entity))
+    }
+
+    /// How many entities of `owner` a client has spawned
+    pub fn entity_count(&self, index: usize, owner: u64) -> usize {
+        self.client(index)
+            .networked_entitys
+            .get(&owner)
+            .map_or(0, |entities| entities.len())
+    }
+}
+
+fn chunk(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend((content.len() as i32).to_le_bytes());
+    bytes.extend(0i32.to_le_bytes());
+    bytes.extend(content);
+    bytes
+}
+
+/// A .vox file with one model, `voxels` being in MagicaVoxel's z up coordinates
+pub fn vox(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+    let mut children = chunk(b"SIZE", &size.map(i32::to_le_bytes).concat());
+    let mut xyzi = (voxels.len() as i32).to_le_bytes().to_vec();
+    xyzi.extend(voxels.concat());
+    children.extend(chunk(b"XYZI", &xyzi));
+
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend(150i32.to_le_bytes());
+    bytes.extend(b"MAIN");
+    bytes.extend(0i32.to_le_bytes());
+    bytes.extend((children.len() as i32).to_le_bytes());
+    bytes.extend(children);
+    bytes
+}
+
+/// A world with a wall across it `x` meters along the x axis from the center
+pub fn wall(x: f32) -> VoxelWorld {
+    let x = ((x * VOXELS_PER_METER).floor() as i32 + WORLD_SIZE / 2) as u8;
+    let mut voxels = Vec::new();
+    for y in 0..WORLD_SIZE as u8 {
+        for z in 0..WORLD_SIZE as u8 {
+            voxels.push([x, y, z, WALL_MATERIAL]);
+        }
+    }
+    VoxelWorld::parse(&vox([WORLD_SIZE; 3], &voxels)).unwrap()
+}
@@ -0,0 +1,27 @@
+use bevy_networking::game::game_mode::{FreeForAll, GameMode, Round, Winner};
+
+fn round(scores: &[(u64, i32)]) -> Round {
+    let mut round = Round::default();
+    round.scores.extend(scores.iter().copied());
+    round
+}
+
+#[test]
+fn free_for_all_is_a_draw_when_time_runs_out_without_scores() {
+    let mode = FreeForAll { score_limit: 20 };
+    let round = round(&[]);
+
+    assert_eq!(mode.winner(&round, &[1, 2], false), None);
+    assert_eq!(mode.winner(&round, &[1, 2], true), Some(Winner::Draw));
+    assert_eq!(mode.winner(&round, &[], true), Some(Winner::Draw));
+}
+
+#[test]
+fn free_for_all_skips_players_that_left() {
+    let mode = FreeForAll { score_limit: 20 };
+    // player 3 left while in the lead
+    let round = round(&[(1, 4), (2, 2), (3, 25)]);
+
+    assert_eq!(mode.winner(&round, &[1, 2], false), None);
+    assert_eq!(mode.winner(&round, &[1, 2], true), Some(Winner::Player(1)));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    health::{bullet_damage, MAX_HEALTH},
+    server::ServerResource,
+};
+use common::{Harness, MAX_TICKS};
+
+/// Fires a bullet of a type straight through the second player, returning their health
+/// after
+fn shoot_through(bullet_type: u32) -> f32 {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(1).respawn > 0));
+
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    server.lag_compensation.enabled = false;
+    let player = server.players.get_mut(&b).unwrap();
+    player.protected_until = 0.0;
+    let target = player.position;
+
+    let bullet = harness.spawn_bullet_of_type(0, bullet_type, target + Vec3::X * 2.0, Vec3::ZERO);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().networked_entity(a, bullet).is_some()
+    }));
+    harness.clients[0]
+        .world
+        .get_mut::<Transform>(bullet)
+        .unwrap()
+        .translation = target - Vec3::X * 2.0;
+    for _ in 0..10 {
+        harness.step();
+    }
+    harness.server().players[&b].health
+}
+
+#[test]
+fn portal_bullets_hurt_and_others_dont() {
+    for bullet_type in [1, 2] {
+        assert_eq!(
+            shoot_through(bullet_type),
+            MAX_HEALTH - bullet_damage(bullet_type)
+        );
+        assert!(bullet_damage(bullet_type) > 0.0);
+    }
+    assert_eq!(bullet_damage(0), 0.0);
+    assert_eq!(shoot_through(0), MAX_HEALTH);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::server::ServerResource;
+use common::{wall, Harness, MAX_TICKS};
+
+#[test]
+fn entities_spawn_and_despawn_with_distance() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    let viewer = harness.server().players[&b].position;
+    let far = viewer + Vec3::X * 1000.0;
+
+    let bullet = harness.spawn_bullet(0, far, Vec3::ZERO);
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().networked_entity(a, bullet).is_some()
+    }));
+    harness.step();
+    assert!(!harness.has_entity(1, a, bullet));
+
+    harness.clients[0]
+        .world
+        .get_mut::<Transform>(bullet)
+        .unwrap()
+        .translation = viewer + Vec3::Y;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.has_entity(1, a, bullet)));
+
+    harness.clients[0]
+        .world
+        .get_mut::<Transform>(bullet)
+        .unwrap()
+        .translation = far;
+    assert!(harness.step_until(MAX_TICKS, |harness| !harness.has_entity(1, a, bullet)));
+}
+
+#[test]
+fn removed_entities_despawn_on_other_clients() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let viewer = harness.server().players[&harness.client(1).client_id].position;
+
+    let bullet = harness.spawn_bullet(0, viewer + Vec3::Y, Vec3::ZERO);
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.has_entity(1, a, bullet)));
+
+    harness.clients[0].world.despawn(bullet);
+    assert!(harness.step_until(MAX_TICKS, |harness| !harness.has_entity(1, a, bullet)));
+}
+
+#[test]
+fn entities_behind_walls_are_not_sent_until_seen() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    let viewer = harness.server().players[&b].position;
+    let hidden = viewer + Vec3::X * 4.0;
+    let seen = viewer - Vec3::X * 4.0;
+    {
+        let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+        let server = server_resource.0.as_mut().unwrap();
+        server.world = wall(viewer.x + 2.0);
+        assert!(!server.is_visible(viewer, hidden));
+        assert!(server.is_visible(viewer, seen));
+    }
+
+    let bullet = harness.spawn_bullet(0, hidden, Vec3::ZERO);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().networked_entity(a, bullet).is_some()
+    }));
+    harness.step();
+    assert!(!harness.has_entity(1, a, bullet));
+
+    harness.clients[0]
+        .world
+        .get_mut::<Transform>(bullet)
+        .unwrap()
+        .translation = seen;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.has_entity(1, a, bullet)));
+
+    // ducking behind the wall for a moment doesn't respawn it, staying there despawns it
+    harness.clients[0]
+        .world
+        .get_mut::<Transform>(bullet)
+        .unwrap()
+        .translation = hidden;
+    for _ in 0..10 {
+        harness.step();
+        assert!(harness.has_entity(1, a, bullet));
+    }
+    assert!(harness.step_until(MAX_TICKS * 2, |harness| !harness.has_entity(1, a, bullet)));
+}
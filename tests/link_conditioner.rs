@@ -0,0 +1,111 @@
+use bevy_networking::game::{
+    link_conditioner::{
+        ConditionedClientTransport, LinkConditioner, LinkConditionerSettings, LinkConditions,
+    },
+    transport::{ClientTransport, MemoryServerTransport, ServerTransport},
+};
+use renet::{DefaultChannel, NETCODE_USER_DATA_BYTES};
+use std::time::Duration;
+
+const FRAME_TIME: Duration = Duration::from_millis(16);
+const MESSAGES: u32 = 100;
+
+const BAD_NETWORK: LinkConditions = LinkConditions {
+    latency: 0.1,
+    jitter: 0.05,
+    loss: 0.2,
+    duplication: 0.2,
+    reordering: 0.2,
+};
+
+/// Sends numbered messages from a conditioned client, one per frame, returning the frame
+/// each one reached the server on and the client
+fn run(channel: DefaultChannel, seed: u64) -> (Vec<(u32, usize)>, ConditionedClientTransport) {
+    let mut server = MemoryServerTransport::default();
+    let mut client = ConditionedClientTransport::new(
+        Box::new(server.listener().connect(1, [0; NETCODE_USER_DATA_BYTES])),
+        LinkConditioner::new(BAD_NETWORK, seed),
+    );
+
+    let mut received = Vec::new();
+    for frame in 0..MESSAGES as usize * 2 {
+        client.update(FRAME_TIME).unwrap();
+        if frame < MESSAGES as usize {
+            client.send_message(channel, bincode::serialize(&(frame as u32)).unwrap());
+        }
+        client.send_packets().unwrap();
+
+        server.update(FRAME_TIME).unwrap();
+        while let Some(message) = server.receive_message(1, channel) {
+            received.push((bincode::deserialize(&message).unwrap(), frame));
+        }
+    }
+    (received, client)
+}
+
+fn send_messages(channel: DefaultChannel, seed: u64) -> Vec<(u32, usize)> {
+    run(channel, seed).0
+}
+
+#[test]
+fn same_seed_gives_same_network() {
+    assert_eq!(
+        send_messages(DefaultChannel::Unreliable, 7),
+        send_messages(DefaultChannel::Unreliable, 7)
+    );
+    assert_ne!(
+        send_messages(DefaultChannel::Unreliable, 7),
+        send_messages(DefaultChannel::Unreliable, 8)
+    );
+}
+
+#[test]
+fn reliable_messages_arrive_once_in_order() {
+    let (received, mut client) = run(DefaultChannel::Reliable, 1);
+    let received: Vec<u32> = received.into_iter().map(|(message, _)| message).collect();
+    assert_eq!(received, (0..MESSAGES).collect::<Vec<_>>());
+
+    // the reliable stream was duplicated and reordered underneath and put right again
+    let conditioner = client.conditioner().unwrap();
+    assert!(conditioner.duplicates_dropped > 0, "nothing was duplicated");
+    assert!(conditioner.reordered > 0, "nothing was reordered");
+}
+
+#[test]
+fn unreliable_messages_are_lost_duplicated_and_reordered() {
+    let received: Vec<u32> = send_messages(DefaultChannel::Unreliable, 1)
+        .into_iter()
+        .map(|(message, _)| message)
+        .collect();
+
+    let mut unique = received.clone();
+    unique.sort();
+    unique.dedup();
+    assert!(unique.len() < MESSAGES as usize, "nothing was lost");
+    assert!(unique.len() < received.len(), "nothing was duplicated");
+    assert!(
+        received.windows(2).any(|pair| pair[0] > pair[1]),
+        "nothing was reordered"
+    );
+}
+
+#[test]
+fn other_channels_pass_through() {
+    let received = send_messages(DefaultChannel::Chunk, 1);
+    assert_eq!(received.len(), MESSAGES as usize);
+    assert!(received
+        .iter()
+        .all(|&(message, frame)| message as usize == frame));
+}
+
+#[test]
+fn percentages_above_100_are_rejected() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert!(LinkConditionerSettings::from_args(&args(&["--loss", "150"])).is_err());
+    assert!(LinkConditionerSettings::from_args(&args(&["--reorder", "101"])).is_err());
+    assert!(
+        LinkConditionerSettings::from_args(&args(&["--loss", "100"]))
+            .unwrap()
+            .is_some()
+    );
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    game_mode::GameModeKind,
+    map::{MapInfo, SpawnRule},
+    server::ServerResource,
+};
+use common::Harness;
+
+#[test]
+fn spawn_rule_is_read_from_the_map_file() {
+    assert_eq!(
+        MapInfo::parse("spawn 0 0 0").unwrap().spawn_rule,
+        SpawnRule::FarthestFromEnemies
+    );
+    assert_eq!(
+        MapInfo::parse("spawn_rule random").unwrap().spawn_rule,
+        SpawnRule::Random
+    );
+    assert!(MapInfo::parse("spawn_rule nearest").is_err());
+    assert!(MapInfo::parse("spawn_rule random farthest").is_err());
+}
+
+#[test]
+fn farthest_spawn_ignores_teammates() {
+    let mut harness = Harness::new(3);
+    let ids: Vec<u64> = (0..3).map(|i| harness.client(i).client_id).collect();
+    let now = harness.server.world.resource::<Time>().elapsed_seconds();
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    server.set_game_mode(GameModeKind::TeamDeathmatch);
+    server.map_info = MapInfo::parse("spawn 0 5 0\nspawn 10 5 0\nspawn_rule farthest").unwrap();
+
+    // the teammate stands on the spawn farthest from the enemy
+    for (&client_id, (team, position)) in ids.iter().zip([
+        (0, Vec3::new(0.0, 5.0, 20.0)),
+        (0, Vec3::new(10.0, 5.0, 0.0)),
+        (1, Vec3::new(-10.0, 5.0, 0.0)),
+    ]) {
+        let player = server.players.get_mut(&client_id).unwrap();
+        player.team = Some(team);
+        player.position = position;
+    }
+
+    server.respawn_player(ids[0], now);
+    assert_eq!(server.players[&ids[0]].position, Vec3::new(10.0, 5.0, 0.0));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    moderation::{filter_words, Ban, Bans, TokenBucket},
+    server::ServerResource,
+};
+use common::{Harness, MAX_TICKS};
+use std::{fs, net::IpAddr};
+
+#[test]
+fn filter_stars_out_words_whatever_their_case_and_punctuation() {
+    let filter = vec!["heck".to_string()];
+    assert_eq!(filter_words("what the Heck!", &filter), "what the *****");
+    assert_eq!(filter_words("heck, heck", &filter), "***** ****");
+    // only whole words are filtered
+    assert_eq!(filter_words("checkpoint", &filter), "checkpoint");
+    assert_eq!(filter_words("what the heck", &[]), "what the heck");
+}
+
+#[test]
+fn token_bucket_allows_a_burst_then_refills_at_the_rate() {
+    let (rate, burst) = (0.5, 3.0);
+    let mut bucket = TokenBucket::new(burst, 0.0);
+    for _ in 0..3 {
+        assert!(bucket.take(0.0, rate, burst));
+    }
+    assert!(!bucket.take(0.0, rate, burst));
+
+    // one token every two seconds
+    assert!(!bucket.take(1.0, rate, burst));
+    assert!(bucket.take(2.0, rate, burst));
+    assert!(!bucket.take(2.0, rate, burst));
+
+    // a long wait refills no more than the burst
+    for _ in 0..3 {
+        assert!(bucket.take(100.0, rate, burst));
+    }
+    assert!(!bucket.take(100.0, rate, burst));
+}
+
+#[test]
+fn bans_match_address_or_token_and_are_kept_across_restarts() {
+    let path = std::env::temp_dir().join(format!("bans-{}.txt", std::process::id()));
+    let address: IpAddr = "10.0.0.7".parse().unwrap();
+    let mut bans = Bans::load(&path);
+    bans.add(Ban {
+        username: "the worst".to_string(),
+        address: Some(address),
+        token: Some(42),
+    });
+    bans.add(Ban {
+        username: "host".to_string(),
+        address: None,
+        token: Some(7),
+    });
+
+    let bans = Bans::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(bans.iter().count(), 2);
+    assert_eq!(
+        bans.find(Some(address), None)
+            .map(|ban| ban.username.as_str()),
+        Some("the worst")
+    );
+    assert_eq!(
+        bans.find(None, Some(42)).map(|ban| ban.username.as_str()),
+        Some("the worst")
+    );
+    assert_eq!(
+        bans.find(None, Some(7)).map(|ban| ban.username.as_str()),
+        Some("host")
+    );
+    assert!(bans
+        .find(Some("10.0.0.8".parse().unwrap()), Some(8))
+        .is_none());
+    assert!(bans.find(None, None).is_none());
+}
+
+#[test]
+fn banning_keeps_the_players_session_token() {
+    let mut harness = Harness::new(2);
+    let username = harness.client(1).username.clone();
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(1).session.is_some()));
+    let (token, _) = harness.client(1).session.unwrap();
+
+    let now = harness.server.world.resource::<Time>().elapsed_seconds();
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    server.run_command(None, &format!("/ban {}", username), now);
+
+    assert!(server.bans.find(None, Some(token)).is_some());
+    // another player with the same name isn't caught by it
+    assert!(server.bans.find(None, None).is_none());
+}
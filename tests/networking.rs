@@ -0,0 +1,152 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    client::ClientResource,
+    link_conditioner::LinkConditions,
+    network_stats::NetworkStats,
+    networking::{NetworkTransform, ServerMessages},
+    server::ServerResource,
+};
+use common::{Harness, MAX_TICKS};
+use renet::{DefaultChannel, NETCODE_USER_DATA_BYTES};
+
+#[test]
+fn clients_see_each_other() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.client(0).players.contains_key(&b) && harness.client(1).players.contains_key(&a)
+    }));
+    assert_eq!(harness.server().players.len(), 2);
+}
+
+fn bullet_reaches_other_client(harness: &mut Harness, max_ticks: usize) {
+    let a = harness.client(0).client_id;
+    let bullet = harness.spawn_bullet(0, Vec3::new(0.0, 5.0, 0.0), Vec3::X);
+    assert!(harness.step_until(max_ticks, |harness| harness.has_entity(1, a, bullet)));
+}
+
+#[test]
+fn spawned_bullet_reaches_other_client() {
+    bullet_reaches_other_client(&mut Harness::new(2), MAX_TICKS);
+}
+
+#[test]
+fn spawned_bullet_reaches_other_client_over_udp() {
+    // the one test over real sockets, everything else runs in memory
+    bullet_reaches_other_client(&mut Harness::udp(2), MAX_TICKS);
+}
+
+#[test]
+fn spawned_bullet_survives_bad_network() {
+    let mut harness = Harness::new(2);
+    harness.condition_client(
+        0,
+        LinkConditions {
+            latency: 0.1,
+            jitter: 0.05,
+            loss: 0.3,
+            duplication: 0.2,
+            reordering: 0.2,
+        },
+        1,
+    );
+    // lost messages are resent, so it only takes longer
+    bullet_reaches_other_client(&mut harness, MAX_TICKS * 4);
+
+    // duplicates and reordering reached the reliable stream and were dealt with there
+    let mut client_resource = harness.clients[0].world.resource_mut::<ClientResource>();
+    let conditioner = client_resource
+        .as_mut()
+        .and_then(|client| client.client.conditioner())
+        .unwrap();
+    assert!(conditioner.duplicates_dropped > 0);
+    assert!(conditioner.reordered > 0);
+}
+
+#[test]
+fn disconnect_removes_remote_player() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.client(1).players.contains_key(&a)
+    }));
+    let remote_player = harness.client(1).players[&a].entity;
+
+    harness.remove_client(0);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        !harness.server().players.contains_key(&a)
+            && !harness.client(0).players.contains_key(&a)
+            && harness.clients[0].world.get_entity(remote_player).is_none()
+    }));
+}
+
+#[test]
+fn stats_count_messages_by_type() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+
+    // the character's position goes out every frame
+    assert!(harness.step_until(MAX_TICKS * 2, |harness| {
+        let sent_updates = |stats: &NetworkStats| {
+            stats
+                .sent_per_second
+                .iter()
+                .any(|(kind, count)| *kind == "UpdatePlayer" && *count > 0)
+        };
+        harness.client(0).client.stats().map_or(false, sent_updates)
+            && harness
+                .server()
+                .server
+                .stats(a)
+                .map_or(false, |stats| !stats.received_per_second.is_empty())
+    }));
+}
+
+#[test]
+fn username_that_is_not_utf8_still_joins() {
+    let mut harness = Harness::new(1);
+    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+    user_data[0..8].copy_from_slice(&2u64.to_le_bytes());
+    user_data[8..10].copy_from_slice(&[0xff, 0xfe]);
+
+    let _player = harness.listener().connect(7, user_data);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().players.contains_key(&7)
+    }));
+    assert_eq!(harness.server().players[&7].username, "\u{fffd}\u{fffd}");
+}
+
+#[test]
+fn stale_messages_about_unknown_players_and_entities_are_dropped() {
+    let mut harness = Harness::new(1);
+    let a = harness.client(0).client_id;
+    let unknown = Entity::from_raw(12345);
+    {
+        let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+        let server = server_resource.0.as_mut().unwrap();
+        for message in [
+            ServerMessages::ClientDisconnected { client_id: 99 },
+            ServerMessages::UpdateNetworkedEntity {
+                client_id: 99,
+                entity: unknown,
+                transform: NetworkTransform::from_transform(&Transform::default(), Vec3::ZERO),
+            },
+            ServerMessages::DespawnNetworkedEntity {
+                client_id: 99,
+                entity: unknown,
+            },
+        ] {
+            server.server.send(a, DefaultChannel::Reliable, &message);
+        }
+    }
+
+    for _ in 0..MAX_TICKS {
+        harness.step();
+    }
+    assert!(harness.client(0).client.is_connected());
+}
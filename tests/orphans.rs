@@ -0,0 +1,71 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    networking::SERVER_ID,
+    server::{OrphanPolicy, ServerResource},
+};
+use common::{Harness, MAX_TICKS};
+
+/// Spawns a bullet of the second client next to the first and then disconnects its owner
+fn leave_bullet_behind(harness: &mut Harness, orphan_policy: OrphanPolicy, velocity: Vec3) -> u64 {
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    server.0.as_mut().unwrap().orphan_policy = orphan_policy;
+
+    let b = harness.client(1).client_id;
+    let viewer = harness.server().players[&harness.client(0).client_id].position;
+    harness.spawn_bullet(1, viewer + Vec3::Y, velocity);
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.entity_count(0, b) == 1));
+
+    harness.remove_client(1);
+    b
+}
+
+#[test]
+fn orphaned_entities_despawn() {
+    let mut harness = Harness::new(2);
+    let b = leave_bullet_behind(&mut harness, OrphanPolicy::Despawn, Vec3::ZERO);
+
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.entity_count(0, b) == 0));
+    assert_eq!(harness.server().networked_entities().count(), 0);
+}
+
+#[test]
+fn orphaned_entities_transfer_to_server() {
+    let mut harness = Harness::new(2);
+    let b = leave_bullet_behind(&mut harness, OrphanPolicy::TransferToServer, Vec3::ZERO);
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.entity_count(0, b) == 0 && harness.entity_count(0, SERVER_ID) == 1
+    }));
+    assert!(harness
+        .server()
+        .networked_entities()
+        .all(|(owner, ..)| owner == SERVER_ID));
+}
+
+#[test]
+fn transferred_bullets_fly_on_and_expire() {
+    let mut harness = Harness::new(2);
+    leave_bullet_behind(&mut harness, OrphanPolicy::TransferToServer, Vec3::X);
+    let position = |harness: &Harness| {
+        harness
+            .server()
+            .networked_entities()
+            .find(|(owner, ..)| *owner == SERVER_ID)
+            .map(|(.., transform)| transform.position)
+    };
+    assert!(harness.step_until(MAX_TICKS, |harness| position(harness).is_some()));
+
+    let start = position(&harness).unwrap();
+    for _ in 0..10 {
+        harness.step();
+    }
+    assert!(position(&harness).unwrap().x > start.x);
+
+    // bullets the server took over last a few seconds at most
+    assert!(harness.step_until(MAX_TICKS * 6, |harness| {
+        harness.server().networked_entities().count() == 0
+            && harness.entity_count(0, SERVER_ID) == 0
+    }));
+}
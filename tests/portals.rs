@@ -0,0 +1,89 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    client::ClientResource,
+    health::MAX_HEALTH,
+    networking::{ClientMessages, NetworkTransform, NetworkedEntityType, PortalId},
+    portals::PortalPlacement,
+    server::ServerResource,
+};
+use common::{Harness, MAX_TICKS};
+use renet::DefaultChannel;
+
+fn send(harness: &mut Harness, index: usize, message: ClientMessages) {
+    let mut client_resource = harness.clients[index]
+        .world
+        .resource_mut::<ClientResource>();
+    client_resource
+        .as_mut()
+        .unwrap()
+        .client
+        .send(DefaultChannel::Reliable, &message);
+}
+
+fn transform(position: Vec3) -> NetworkTransform {
+    NetworkTransform::from_transform(&Transform::from_translation(position), Vec3::X)
+}
+
+#[test]
+fn bullet_through_portal_misses_bystander_between_portals() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    let bystander = harness.server().players[&b].position;
+
+    // the bullet enters on one side of the bystander and leaves on the other
+    let entrance = bystander - Vec3::X * 10.0;
+    let exit = bystander + Vec3::X * 10.0;
+    let from = PortalId { owner: a, index: 0 };
+    let to = PortalId { owner: a, index: 1 };
+    {
+        let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+        let server = server_resource.0.as_mut().unwrap();
+        server.players.get_mut(&b).unwrap().protected_until = 0.0;
+        for (id, position) in [(from, entrance), (to, exit)] {
+            let placement = PortalPlacement {
+                position,
+                normal: Vec3::X,
+            };
+            server.portals.insert(id, placement);
+        }
+    }
+
+    let bullet = harness.clients[0].world.spawn_empty().id();
+    send(
+        &mut harness,
+        0,
+        ClientMessages::SpawnNetworkedEntity {
+            entity: bullet,
+            entity_type: NetworkedEntityType::Bullet(0),
+            transform: transform(entrance - Vec3::X),
+        },
+    );
+    send(
+        &mut harness,
+        0,
+        ClientMessages::PortalTraversal {
+            entity: Some(bullet),
+            from,
+            to,
+        },
+    );
+    send(
+        &mut harness,
+        0,
+        ClientMessages::UpdateNetworkedEntity {
+            entity: bullet,
+            transform: transform(exit + Vec3::X),
+        },
+    );
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness
+            .server()
+            .networked_entity(a, bullet)
+            .map_or(false, |(_, transform)| transform.position.x > exit.x)
+    }));
+    assert_eq!(harness.server().players[&b].health, MAX_HEALTH);
+}
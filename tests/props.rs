@@ -0,0 +1,36 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    health::player_half_extents, props::PropShape, server::ServerResource,
+};
+use common::Harness;
+
+#[test]
+fn props_are_rate_limited_and_thrown_clear_of_the_thrower() {
+    let mut harness = Harness::new(1);
+    let a = harness.client(0).client_id;
+    let now = harness.server.world.resource::<Time>().elapsed_seconds();
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    let position = server.players[&a].position;
+
+    // a burst gets through, the rest of a flood at the same time doesn't
+    for _ in 0..10 {
+        server.spawn_prop(a, PropShape::Box, Vec3::X, now);
+    }
+    let thrown = server.props.len();
+    assert!(thrown > 0 && thrown < 10);
+    server.spawn_prop(a, PropShape::Box, Vec3::X, now + 10.0);
+    assert_eq!(server.props.len(), thrown + 1);
+
+    let extents = player_half_extents()
+        + PropShape::Box.half_size().as_vec3() / bevy_voxel_engine::VOXELS_PER_METER;
+    for prop in server.props.values() {
+        let offset = (prop.transform.position - position).abs();
+        assert!(
+            offset.cmpge(extents).any(),
+            "prop spawned inside the thrower"
+        );
+    }
+}
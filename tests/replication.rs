@@ -0,0 +1,72 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    replication::Replicated, server::ServerResource, voxel_world::VoxelWorld,
+};
+use common::Harness;
+
+const MAX_TICKS: usize = 180;
+
+fn sent_per_second(harness: &Harness, client_id: u64, kind: &str) -> u32 {
+    harness
+        .server()
+        .server
+        .stats(client_id)
+        .and_then(|stats| {
+            stats
+                .sent_per_second
+                .iter()
+                .find(|(sent_kind, _)| *sent_kind == kind)
+                .map(|(_, count)| *count)
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn player_updates_get_through_bullet_flood() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+
+    // room for the player and most of a bullet each tick, so the bullets have to queue
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    let server = server.0.as_mut().unwrap();
+    server.replication.budget = 6_000.0;
+    // nothing in the way, so the bullets are relevant wherever b spawned
+    server.world = VoxelWorld::default();
+
+    // far enough from b that their updates gain priority slower than the player's
+    for i in 0..3 {
+        harness.spawn_bullet(0, Vec3::new(40.0, 5.0, i as f32), Vec3::X);
+    }
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().relevant_count(b) == 3 && harness.server().pending_updates(b) > 0
+    }));
+
+    // every tick the player goes out ahead of the bullets that were waiting before it, the
+    // loop being long enough for a whole stats sample of it
+    for _ in 0..MAX_TICKS {
+        harness.step();
+        assert!(!harness.server().is_pending(b, Replicated::Player(a)));
+        assert!(harness.server().pending_updates(b) > 0);
+    }
+    assert!(sent_per_second(&harness, b, "UpdatePlayer") >= 60);
+    assert!(harness.client(1).players.contains_key(&a));
+}
+
+#[test]
+fn unlimited_replication_sends_every_update() {
+    let mut harness = Harness::new(2);
+    let b = harness.client(1).client_id;
+
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    server.0.as_mut().unwrap().replication.enabled = false;
+
+    harness.spawn_bullet(0, Vec3::new(0.0, 5.0, 0.0), Vec3::X);
+
+    for _ in 0..MAX_TICKS {
+        harness.step();
+        assert_eq!(harness.server().pending_updates(b), 0);
+    }
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{client::ClientResource, networking::ClientMessages};
+use common::{Harness, MAX_TICKS};
+use renet::DefaultChannel;
+
+fn update_player(harness: &mut Harness, position: Vec3, respawn: u32) {
+    let mut client_resource = harness.clients[0].world.resource_mut::<ClientResource>();
+    client_resource.as_mut().unwrap().client.send(
+        DefaultChannel::Reliable,
+        &ClientMessages::UpdatePlayer {
+            position,
+            velocity: Vec3::ZERO,
+            respawn,
+        },
+    );
+}
+
+#[test]
+fn updates_from_before_a_respawn_are_ignored() {
+    let mut harness = Harness::new(1);
+    let a = harness.client(0).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(0).respawn > 0));
+    let respawn = harness.client(0).respawn;
+
+    // a fall out of the world, followed by updates the client sent before hearing of
+    // the respawn
+    let fallen = harness.server().map_info.bounds_min - Vec3::Y;
+    update_player(&mut harness, fallen, respawn);
+    assert!(harness.step_until(MAX_TICKS, |harness| { harness.client(0).respawn > respawn }));
+    for _ in 0..3 {
+        update_player(&mut harness, fallen, respawn);
+        harness.step();
+    }
+
+    let player = &harness.server().players[&a];
+    assert_eq!(player.deaths, 1);
+    assert_eq!(player.respawns, respawn + 1);
+    assert!(!harness.server().map_info.is_out_of_world(player.position));
+}
+
+#[test]
+fn invalid_position_respawns_the_player() {
+    let mut harness = Harness::new(1);
+    let a = harness.client(0).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(0).respawn > 0));
+    let respawn = harness.client(0).respawn;
+
+    let far_away = harness.server().map_info.bounds_max + Vec3::ONE * 1000.0;
+    update_player(&mut harness, far_away, respawn);
+    assert!(harness.step_until(MAX_TICKS, |harness| { harness.client(0).respawn > respawn }));
+
+    let player = &harness.server().players[&a];
+    assert_eq!(player.deaths, 1);
+    assert!(!harness.server().map_info.is_out_of_world(player.position));
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    client::ClientResource, networking::PortalId, portals::PortalPlacement, server::ServerResource,
+    session, transport::ClientTransport,
+};
+use bevy_networking::GameState;
+use common::{Harness, MAX_TICKS};
+
+/// Cuts a client's connection without it leaving, as a network drop would
+fn drop_connection(harness: &mut Harness, index: usize) {
+    let mut client_resource = harness.clients[index]
+        .world
+        .resource_mut::<ClientResource>();
+    client_resource.as_mut().unwrap().client.disconnect();
+}
+
+fn set_kills(harness: &mut Harness, client_id: u64, kills: u32) {
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    server
+        .0
+        .as_mut()
+        .unwrap()
+        .players
+        .get_mut(&client_id)
+        .unwrap()
+        .kills = kills;
+}
+
+#[test]
+fn dropped_client_resumes_its_session() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    let viewer = harness.server().players[&b].position;
+
+    let bullet = harness.spawn_bullet(0, viewer + Vec3::Y, Vec3::ZERO);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.client(0).session.is_some()
+            && harness.client(0).players.contains_key(&b)
+            && harness.has_entity(1, a, bullet)
+    }));
+    set_kills(&mut harness, a, 3);
+    let team = harness.server().players[&a].team;
+    let portal = PortalId { owner: a, index: 0 };
+    {
+        let mut server = harness.server.world.resource_mut::<ServerResource>();
+        let placement = PortalPlacement {
+            position: viewer + Vec3::X,
+            normal: Vec3::X,
+        };
+        server.0.as_mut().unwrap().portals.insert(portal, placement);
+    }
+
+    drop_connection(&mut harness, 0);
+    harness.step();
+    assert!(!harness.server().players.contains_key(&a));
+    assert!(!harness.server().portals.contains_key(&portal));
+    assert!(harness.client(0).reconnecting.is_some());
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.client(0).reconnecting.is_none()
+            && harness.client(0).players.contains_key(&b)
+            && harness.client(1).players.contains_key(&a)
+            && harness.has_entity(1, a, bullet)
+            && harness.client(1).portals.contains_key(&portal)
+    }));
+    assert!(harness.server().portals.contains_key(&portal));
+    assert_eq!(harness.client(0).client_id, a);
+    assert_eq!(harness.server().players[&a].kills, 3);
+    assert_eq!(harness.server().players[&a].team, team);
+}
+
+#[test]
+fn session_is_not_resumed_after_the_grace_window() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(0).session.is_some()));
+    set_kills(&mut harness, a, 3);
+
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    server.0.as_mut().unwrap().sessions.grace = 0.0;
+    drop_connection(&mut harness, 0);
+
+    // the server has nothing to resume so the client joins again from scratch
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.server().players.contains_key(&a) && harness.client(0).reconnecting.is_none()
+    }));
+    assert_eq!(harness.server().players[&a].kills, 0);
+}
+
+#[test]
+fn suspended_player_cannot_be_taken_over_without_the_token() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(0).session.is_some()));
+    set_kills(&mut harness, a, 3);
+    let (token, _) = harness.client(0).session.unwrap();
+    let username = harness.client(0).username.clone();
+
+    harness.remove_client(0);
+    harness.step();
+    assert!(!harness.server().players.contains_key(&a));
+
+    for wrong_token in [None, Some(token.wrapping_add(1))] {
+        let impostor = harness
+            .listener()
+            .connect(a, session::user_data("impostor", wrong_token).unwrap());
+        assert!(harness.step_until(MAX_TICKS, |harness| {
+            assert!(!harness.server().players.contains_key(&a));
+            !impostor.is_connected()
+        }));
+    }
+
+    let _player = harness
+        .listener()
+        .connect(a, session::user_data(&username, Some(token)).unwrap());
+    harness.step();
+    assert_eq!(harness.server().players[&a].kills, 3);
+}
+
+#[test]
+fn kicked_client_goes_back_to_the_menu() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    assert!(harness.step_until(MAX_TICKS, |harness| harness.client(0).session.is_some()));
+    let username = harness.client(0).username.clone();
+
+    {
+        let now = harness.server.world.resource::<Time>().elapsed_seconds();
+        let mut server = harness.server.world.resource_mut::<ServerResource>();
+        let kick = format!("/kick {}", username);
+        server.0.as_mut().unwrap().run_command(None, &kick, now);
+    }
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        assert!(harness.client(0).reconnecting.is_none());
+        harness.clients[0]
+            .world
+            .resource::<State<GameState>>()
+            .current()
+            == &GameState::Menu
+    }));
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        !harness.server().players.contains_key(&a)
+    }));
+    assert_eq!(harness.server().suspended_count(), 0);
+}
+
+#[test]
+fn longest_username_keeps_its_token() {
+    let username = "a".repeat(session::MAX_USERNAME_LENGTH);
+    let user_data = session::user_data(&username, Some(42)).unwrap();
+    assert_eq!(session::username(&user_data), username);
+    assert_eq!(session::session_token(&user_data), Some(42));
+
+    let too_long = "a".repeat(session::MAX_USERNAME_LENGTH + 1);
+    assert!(session::user_data(&too_long, Some(42)).is_err());
+}
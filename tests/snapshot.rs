@@ -0,0 +1,164 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{
+    chat::ChatState,
+    client::ClientResource,
+    health::Health,
+    networking::ClientMessages,
+    server::ServerResource,
+    snapshot::{ChatLine, WorldSnapshot, MAX_PART_SIZE},
+    voxel_world::VoxelEdit,
+};
+use common::{Harness, MAX_TICKS};
+use renet::DefaultChannel;
+
+fn send_chat(harness: &mut Harness, index: usize, message: &str) {
+    let mut client_resource = harness.clients[index]
+        .world
+        .resource_mut::<ClientResource>();
+    client_resource.as_mut().unwrap().client.send(
+        DefaultChannel::Reliable,
+        &ClientMessages::ChatMessage {
+            message: message.to_string(),
+        },
+    );
+}
+
+fn has_chat(harness: &Harness, index: usize, username: &str, message: &str) -> bool {
+    harness.clients[index]
+        .world
+        .resource::<ChatState>()
+        .history()
+        .any(|line| line == (Some(username), message))
+}
+
+fn remote_player(harness: &Harness, index: usize, client_id: u64) -> (Vec3, f32) {
+    let entity = harness.client(index).players[&client_id].entity;
+    let world = &harness.clients[index].world;
+    (
+        world.get::<Transform>(entity).unwrap().translation,
+        world.get::<Health>(entity).unwrap().current,
+    )
+}
+
+#[test]
+fn late_joiner_matches_existing_client() {
+    let mut harness = Harness::new(2);
+    let a = harness.client(0).client_id;
+    let b = harness.client(1).client_id;
+    let username = harness.client(1).username.clone();
+
+    // entities are checked whatever their distance to the late joiner
+    let mut server = harness.server.world.resource_mut::<ServerResource>();
+    let server = server.0.as_mut().unwrap();
+    server.interest.enabled = false;
+    // a voxel added, one knocked out and one outside the world that is left out
+    server.edit_voxels(vec![
+        VoxelEdit {
+            voxel: IVec3::new(1, 2, 3),
+            material: 5,
+        },
+        VoxelEdit {
+            voxel: IVec3::new(3, 2, 1),
+            material: 0,
+        },
+        VoxelEdit {
+            voxel: IVec3::splat(-1),
+            material: 5,
+        },
+    ]);
+
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        harness.client(0).players.contains_key(&b) && harness.client(1).players.contains_key(&a)
+    }));
+
+    send_chat(&mut harness, 1, "hello");
+    let position = harness.server().players[&b].position;
+    let bullet = harness.spawn_bullet(1, position + Vec3::Y, Vec3::ZERO);
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        has_chat(harness, 0, &username, "hello") && harness.has_entity(0, b, bullet)
+    }));
+
+    let late = harness.add_client("late");
+    // let the existing client's interpolation settle on the same state
+    for _ in 0..MAX_TICKS {
+        harness.step();
+    }
+
+    let existing = harness.client(0);
+    let joined = harness.client(late);
+    for client_id in [a, b] {
+        assert_eq!(
+            joined.players[&client_id].username,
+            harness.server().players[&client_id].username
+        );
+    }
+    assert_eq!(joined.players[&b].username, existing.players[&b].username);
+
+    let (existing_position, existing_health) = remote_player(&harness, 0, b);
+    let (joined_position, joined_health) = remote_player(&harness, late, b);
+    assert!(existing_position.distance(joined_position) < 0.01);
+    assert_eq!(existing_health, joined_health);
+
+    assert_eq!(existing.edits.len(), 2);
+    assert_eq!(joined.edits, existing.edits);
+    assert_eq!(joined.edits.get(&IVec3::new(1, 2, 3)), Some(&5));
+    assert_eq!(joined.edits.get(&IVec3::new(3, 2, 1)), Some(&0));
+
+    assert!(harness.has_entity(late, b, bullet));
+    assert!(has_chat(&harness, late, &username, "hello"));
+    assert_eq!(
+        joined.stats.get(&b).map(|stats| stats.deaths),
+        Some(harness.server().players[&b].deaths)
+    );
+}
+
+#[test]
+fn late_joiner_sees_names_of_players_who_left() {
+    let mut harness = Harness::new(2);
+    let username = harness.client(1).username.clone();
+    assert!(harness.step_until(MAX_TICKS, |harness| { harness.client(1).session.is_some() }));
+
+    send_chat(&mut harness, 1, "bye");
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        has_chat(harness, 0, &username, "bye")
+    }));
+    harness.remove_client(1);
+    harness.step();
+
+    let late = harness.add_client("late");
+    assert!(harness.step_until(MAX_TICKS, |harness| {
+        has_chat(harness, late, &username, "bye")
+    }));
+}
+
+#[test]
+fn big_snapshot_is_split_into_parts() {
+    let lines = 200;
+    let snapshot = WorldSnapshot {
+        map: Some("arena".to_string()),
+        chat: (0..lines)
+            .map(|i| ChatLine {
+                sender: Some((i, format!("player {}", i))),
+                message: "a line of chat that takes up some room".to_string(),
+            })
+            .collect(),
+        ..default()
+    };
+    assert!(bincode::serialized_size(&snapshot).unwrap() > MAX_PART_SIZE);
+
+    let parts = snapshot.split();
+    assert!(parts.len() > 1);
+    assert_eq!(parts[0].map.as_deref(), Some("arena"));
+    assert!(parts[1..].iter().all(|part| part.map.is_none()));
+    for part in parts.iter() {
+        assert!(bincode::serialized_size(part).unwrap() <= MAX_PART_SIZE);
+    }
+    let chat: Vec<_> = parts.iter().flat_map(|part| part.chat.iter()).collect();
+    assert_eq!(chat.len(), lines as usize);
+    assert!(chat
+        .iter()
+        .enumerate()
+        .all(|(i, line)| line.sender.as_ref().map(|(id, _)| *id) == Some(i as u64)));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{game_mode::GameModeKind, health::MAX_HEALTH, server::ServerResource};
+use common::Harness;
+
+#[test]
+fn balancing_doesnt_heal_or_revive_the_moved_players() {
+    let mut harness = Harness::new(4);
+    let ids: Vec<u64> = (0..4).map(|i| harness.client(i).client_id).collect();
+    let now = harness.server.world.resource::<Time>().elapsed_seconds();
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    server.set_game_mode(GameModeKind::TeamDeathmatch);
+
+    // everyone ends up on one team, the two lowest scorers being the ones moved
+    for &client_id in ids.iter() {
+        server.players.get_mut(&client_id).unwrap().team = Some(0);
+    }
+    server.round.scores.insert(ids[2], 5);
+    server.round.scores.insert(ids[3], 5);
+    let damaged = ids[0];
+    let dead = ids[1];
+    server.players.get_mut(&damaged).unwrap().health = MAX_HEALTH / 2.0;
+    let respawn_at = now + 5.0;
+    let player = server.players.get_mut(&dead).unwrap();
+    player.health = 0.0;
+    player.respawn_at = Some(respawn_at);
+
+    server.balance_teams(now);
+
+    let player = &server.players[&damaged];
+    assert_eq!(player.team, Some(1));
+    assert_eq!(player.health, MAX_HEALTH / 2.0);
+    let player = &server.players[&dead];
+    assert_eq!(player.team, Some(1));
+    assert_eq!(player.health, 0.0);
+    assert_eq!(player.respawn_at, Some(respawn_at));
+}
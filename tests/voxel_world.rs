@@ -0,0 +1,98 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_networking::game::{server::ServerResource, voxel_world::VoxelWorld};
+use bevy_voxel_engine::VOXELS_PER_METER;
+use common::{vox, Harness, WALL_MATERIAL, WORLD_SIZE as SIZE};
+
+// voxels from the center of the world to the wall
+const WALL_DISTANCE: i32 = 8;
+
+fn wall() -> VoxelWorld {
+    common::wall(WALL_DISTANCE as f32 / VOXELS_PER_METER)
+}
+
+#[test]
+fn parse_swaps_to_y_up() {
+    let world = VoxelWorld::parse(&vox([4, 8, 2], &[[1, 2, 0, 7]])).unwrap();
+    assert_eq!(world.get(IVec3::new(1, 0, 2)), 7);
+    assert_eq!(world.get(IVec3::new(1, 0, 0)), 0);
+    // outside the 4x2x8 world
+    assert_eq!(world.get(IVec3::new(1, 2, 0)), 0);
+}
+
+#[test]
+fn parse_rejects_corrupt_sizes() {
+    assert!(VoxelWorld::parse(&vox([4, -1, 4], &[])).is_err());
+    assert!(VoxelWorld::parse(&vox([i32::MAX; 3], &[])).is_err());
+
+    let mut negative_length = vox([4, 4, 4], &[]);
+    negative_length[24..28].copy_from_slice(&(-1i32).to_le_bytes());
+    assert!(VoxelWorld::parse(&negative_length).is_err());
+
+    let mut truncated = vox([4, 4, 4], &[[0, 0, 0, 1]]);
+    truncated.truncate(truncated.len() - 2);
+    assert!(VoxelWorld::parse(&truncated).is_err());
+}
+
+#[test]
+fn raycast_hits_the_wall_face() {
+    let world = wall();
+    let hit = world.raycast(Vec3::ZERO, Vec3::X, 100.0).unwrap();
+    assert_eq!(
+        hit.voxel,
+        IVec3::new(SIZE / 2 + WALL_DISTANCE, SIZE / 2, SIZE / 2)
+    );
+    assert_eq!(hit.normal, IVec3::NEG_X);
+    assert!((hit.position.x - WALL_DISTANCE as f32 / VOXELS_PER_METER).abs() < 1e-4);
+
+    assert!(world.raycast(Vec3::ZERO, Vec3::NEG_X, 100.0).is_none());
+    assert!(world
+        .raycast(Vec3::ZERO, Vec3::X, 1.0 / VOXELS_PER_METER)
+        .is_none());
+    assert!(world.raycast(Vec3::ZERO, Vec3::ZERO, 100.0).is_none());
+}
+
+#[test]
+fn portals_are_placed_only_on_allowed_walls() {
+    let mut harness = Harness::new(1);
+    let a = harness.client(0).client_id;
+    let mut server_resource = harness.server.world.resource_mut::<ServerResource>();
+    let server = server_resource.0.as_mut().unwrap();
+    server.world = wall();
+    server.players.get_mut(&a).unwrap().position = Vec3::ZERO;
+
+    let placement = server.place_portal(a, 0, Vec3::X).unwrap();
+    assert_eq!(placement.normal, Vec3::NEG_X);
+    assert!((placement.position.x - WALL_DISTANCE as f32 / VOXELS_PER_METER).abs() < 1e-4);
+
+    assert!(server.place_portal(a, 0, Vec3::NEG_X).is_err());
+    assert!(server.place_portal(a, 2, Vec3::X).is_err());
+
+    server.map_info.no_portal_materials.push(WALL_MATERIAL);
+    assert!(server.place_portal(a, 0, Vec3::X).is_err());
+}
+
+#[test]
+fn monu9_is_y_up_and_centered_on_the_origin() {
+    let world = VoxelWorld::load("monu9");
+    // the top of the monument, (118, 196, 157) in MagicaVoxel's z up coordinates
+    let voxel = IVec3::new(118, 157, 196);
+    assert_eq!(world.get(voxel), 41);
+    assert_eq!(world.get(voxel + IVec3::Y), 0);
+    // where it would be without swapping y and z
+    assert_eq!(world.get(IVec3::new(118, 196, 157)), 0);
+
+    // the model is 256 voxels along each side, so the origin is voxel 128
+    let corner = (voxel - IVec3::splat(128)).as_vec3() / VOXELS_PER_METER;
+    assert_eq!(corner, Vec3::new(-10.0, 29.0, 68.0) / VOXELS_PER_METER);
+    let center = corner + Vec3::splat(0.5 / VOXELS_PER_METER);
+    assert_eq!(world.voxel_at(center), voxel);
+
+    let hit = world
+        .raycast(center + Vec3::Y * 10.0, Vec3::NEG_Y, 20.0)
+        .unwrap();
+    assert_eq!(hit.voxel, voxel);
+    assert_eq!(hit.normal, IVec3::Y);
+    assert!((hit.position.y - (corner.y + 1.0 / VOXELS_PER_METER)).abs() < 1e-4);
+}